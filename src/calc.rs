@@ -1,13 +1,67 @@
+mod batch;
+mod builtins;
+mod compile;
+mod complex;
+mod editor;
+mod forms;
+mod fuzz;
+mod highlight;
+mod interp;
+mod interval;
+mod lambda;
+mod limits;
+mod lint;
+mod lsp;
+mod matrix;
+mod operators;
+mod parser;
+mod plot;
+mod relex;
+mod session;
+mod sheet;
+mod tokens;
+mod trace;
+mod types;
+mod word;
+
+pub use self::batch::{eval_batch, BatchFormat, BatchSummary};
+pub use self::compile::{compile, Compiled};
+pub use self::complex::Complex;
+pub use self::editor::CalcHelper;
+pub use self::fuzz::{compare, fuzz, minimise, to_rpn, Divergence, ExprGen};
+pub use self::highlight::{annotate_highlighted, highlight};
+pub use self::interp::{Env, EvalError, EvalErrorKind, Function, Interpreter, Value};
+pub use self::interval::Interval;
+pub use self::lambda::{FuncType, Lambda};
+pub use self::limits::{LimitError, LimitErrorKind, Limits};
+pub use self::lint::{lint, Warning, WarningKind};
+pub use self::lsp::serve_lsp;
+pub use self::operators::{Assoc, Fixity, Operator, OperatorTable};
+pub use self::parser::{
+    parse, parse_stmt, parse_stmt_with, parse_with, Ast, AstKind, BinOp, BinOpKind, Ident,
+    ParseError, Stmt, StmtKind, UniOp, UniOpKind,
+};
+pub use self::plot::{plot, Chart};
+pub use self::relex::{relex, relex_with, TextEdit};
+pub use self::session::{load_session, save_session};
+pub use self::sheet::{CellError, CellRef, Sheet, SheetError};
+pub use self::trace::{trace, Step, Trace};
+pub use self::types::{check, check_in, check_stmt, Type, TypeError, TypeErrorKind};
+pub use self::word::WordWidth;
+
 // Location info, pair of from_offset, to_offset.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Loc(usize, usize);
 
-// impl Loc {
-//     fn merge(&self, other: &Loc) -> Loc {
-//         use std::cmp::{max, min};
-//         Loc(min(self.0, other.0), max(self.1, other.1))
-//     }
-// }
+impl Loc {
+    pub fn new(start: usize, end: usize) -> Self {
+        Loc(start, end)
+    }
+    fn merge(&self, other: &Loc) -> Loc {
+        use std::cmp::{max, min};
+        Loc(min(self.0, other.0), max(self.1, other.1))
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Annot<T> {
@@ -20,47 +74,69 @@ impl<T> Annot<T> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum TokenKind {
-    Number(u64),
-    Plus,
-    Minus,
-    Asterisk,
-    Slash,
-    LParen,
-    RParen,
+crate::tokens! {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum TokenKind for Token {
+        Number(u64),
+        Float(f64) = "{:?}",
+        // imaginary literal, e.g. `3i`
+        Imag(f64) = "{}i",
+        Ident(String),
+        // a symbol from the operator table, e.g. `!`
+        Operator(String),
+    }
+    symbols {
+        "+" => Plus, plus;
+        "-" => Minus, minus;
+        // `±` is lexed by hand
+        "+/-" => PlusMinus, plus_minus;
+        "*" => Asterisk, asterisk;
+        // matrix product
+        "@" => At, at;
+        "/" => Slash, slash;
+        "//" => SlashSlash, slash_slash;
+        "(" => LParen, lparen;
+        ")" => RParen, rparen;
+        "[" => LBracket, lbracket;
+        "]" => RBracket, rbracket;
+        "," => Comma, comma;
+        "&" => Amp, amp;
+        "|" => Pipe, pipe;
+        "~" => Tilde, tilde;
+        "<<" => Shl, shl;
+        ">>" => Shr, shr;
+        "=" => Eq, eq;
+        // the parameters and the body of a lambda, `x -> x * 2`
+        "->" => Arrow, arrow;
+    }
 }
 
 pub type Token = Annot<TokenKind>;
 
-// TOOD: impl by macro
 impl Token {
     fn number(n: u64, loc: Loc) -> Self {
         Self::new(TokenKind::Number(n), loc)
     }
-    fn plus(loc: Loc) -> Self {
-        Self::new(TokenKind::Plus, loc)
-    }
-    fn minus(loc: Loc) -> Self {
-        Self::new(TokenKind::Minus, loc)
+    fn float(f: f64, loc: Loc) -> Self {
+        Self::new(TokenKind::Float(f), loc)
     }
-    fn asterisk(loc: Loc) -> Self {
-        Self::new(TokenKind::Asterisk, loc)
+    fn imag(f: f64, loc: Loc) -> Self {
+        Self::new(TokenKind::Imag(f), loc)
     }
-    fn slash(loc: Loc) -> Self {
-        Self::new(TokenKind::Slash, loc)
+    fn ident(name: &str, loc: Loc) -> Self {
+        Self::new(TokenKind::Ident(name.to_string()), loc)
     }
-    fn lparen(loc: Loc) -> Self {
-        Self::new(TokenKind::LParen, loc)
-    }
-    fn rparen(loc: Loc) -> Self {
-        Self::new(TokenKind::RParen, loc)
+    fn operator(symbol: &str, loc: Loc) -> Self {
+        Self::new(TokenKind::Operator(symbol.to_string()), loc)
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LexErrorKind {
     InvalidChar(char),
+    NumberTooLarge,
+    // the start of a symbol which is not complete, e.g. `<` of `<<`
+    ExpectedSymbol(&'static str),
     Eof,
 }
 
@@ -69,8 +145,11 @@ impl LexError {
     fn invalid_char(c: char, loc: Loc) -> Self {
         LexError::new(LexErrorKind::InvalidChar(c), loc)
     }
-    fn eof(loc: Loc) -> Self {
-        LexError::new(LexErrorKind::Eof, loc)
+    fn number_too_large(loc: Loc) -> Self {
+        LexError::new(LexErrorKind::NumberTooLarge, loc)
+    }
+    fn expected_symbol(symbol: &'static str, loc: Loc) -> Self {
+        LexError::new(LexErrorKind::ExpectedSymbol(symbol), loc)
     }
 }
 type LexResult<T> = Result<T, LexError>;
@@ -83,53 +162,115 @@ fn recognize_many(input: &[u8], pos: usize, mut f: impl FnMut(u8) -> bool) -> us
     ret
 }
 fn lex_number(input: &[u8], pos: usize) -> LexResult<LexValue> {
-    let pattern = b"0123456789";
-    let next_pos = recognize_many(input, pos, |b| pattern.contains(&b));
-    let n = std::str::from_utf8(&input[pos..next_pos])
-        .unwrap()
-        .parse()
-        .unwrap();
-    let token = Token::number(n, Loc(pos, next_pos));
+    if let Some(v) = lex_radix_number(input, pos) {
+        return v;
+    }
+    let is_digit = |b: u8| b.is_ascii_digit();
+    let digit_at = |p: usize| p < input.len() && is_digit(input[p]);
+    let mut next_pos = recognize_many(input, pos, is_digit);
+    let mut is_float = false;
+    // fraction part: `1.5`, but not `1.`
+    if next_pos < input.len() && input[next_pos] == b'.' && digit_at(next_pos + 1) {
+        next_pos = recognize_many(input, next_pos + 1, is_digit);
+        is_float = true;
+    }
+    // exponent part: `1e3`, `2.5e-3`
+    if next_pos < input.len() && (input[next_pos] == b'e' || input[next_pos] == b'E') {
+        let sign = next_pos + 1 < input.len() && b"+-".contains(&input[next_pos + 1]);
+        let digits_from = if sign { next_pos + 2 } else { next_pos + 1 };
+        if digit_at(digits_from) {
+            next_pos = recognize_many(input, digits_from, is_digit);
+            is_float = true;
+        }
+    }
+    let s = std::str::from_utf8(&input[pos..next_pos]).unwrap();
+    // imaginary suffix: `3i`, but not `3if`
+    let is_ident_char =
+        |p: usize| p < input.len() && (input[p].is_ascii_alphanumeric() || input[p] == b'_');
+    if next_pos < input.len() && input[next_pos] == b'i' && !is_ident_char(next_pos + 1) {
+        let token = Token::imag(s.parse().unwrap(), Loc(pos, next_pos + 1));
+        return Ok((token, next_pos + 1));
+    }
+    let loc = Loc(pos, next_pos);
+    let token = if is_float {
+        Token::float(s.parse().unwrap(), loc)
+    } else {
+        match s.parse() {
+            Ok(n) => Token::number(n, loc),
+            Err(_) => return Err(LexError::number_too_large(loc)),
+        }
+    };
     Ok((token, next_pos))
 }
-fn skip_spaces(input: &[u8], pos: usize) -> LexResult<((), usize)> {
-    let pattern = b" \n\t";
-    let next_pos = recognize_many(input, pos, |b| pattern.contains(&b));
-    Ok(((), next_pos))
-}
-fn consume_byte(input: &[u8], pos: usize, expected: u8) -> LexResult<(u8, usize)> {
-    if input.len() <= pos {
-        return Err(LexError::eof(Loc(pos, pos)));
+// `0x1f`, `0o17` and `0b101`. Returns `None` if the input is not prefixed.
+fn lex_radix_number(input: &[u8], pos: usize) -> Option<LexResult<LexValue>> {
+    if input[pos] != b'0' || pos + 2 >= input.len() {
+        return None;
     }
-    if input[pos] == expected {
-        Ok((input[pos], pos + 1))
-    } else {
-        Err(LexError::invalid_char(
-            input[pos] as char,
-            Loc(pos, pos + 1),
-        ))
+    let radix = match input[pos + 1] {
+        b'x' => 16,
+        b'o' => 8,
+        b'b' => 2,
+        _ => return None,
+    };
+    let is_digit = |b: u8| (b as char).is_digit(radix);
+    if !is_digit(input[pos + 2]) {
+        return None;
     }
+    let next_pos = recognize_many(input, pos + 2, is_digit);
+    let s = std::str::from_utf8(&input[pos + 2..next_pos]).unwrap();
+    let loc = Loc(pos, next_pos);
+    Some(match u64::from_str_radix(s, radix) {
+        Ok(n) => Ok((Token::number(n, loc), next_pos)),
+        Err(_) => Err(LexError::number_too_large(loc)),
+    })
 }
-fn lex_plus(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b'+').map(|(_, end)| (Token::plus(Loc(start, end)), end))
+fn lex_ident(input: &[u8], pos: usize) -> LexResult<LexValue> {
+    let next_pos = recognize_many(input, pos, |b| b.is_ascii_alphanumeric() || b == b'_');
+    let name = std::str::from_utf8(&input[pos..next_pos]).unwrap();
+    Ok((Token::ident(name, Loc(pos, next_pos)), next_pos))
 }
-fn lex_minus(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b'-').map(|(_, end)| (Token::minus(Loc(start, end)), end))
-}
-fn lex_asterisk(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b'*').map(|(_, end)| (Token::asterisk(Loc(start, end)), end))
+// `#` starts a comment up to the end of the line.
+fn skip_comment(input: &[u8], pos: usize) -> LexResult<((), usize)> {
+    let next_pos = recognize_many(input, pos, |b| b != b'\n');
+    Ok(((), next_pos))
 }
-fn lex_slash(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b'/').map(|(_, end)| (Token::slash(Loc(start, end)), end))
+fn skip_spaces(input: &[u8], pos: usize) -> LexResult<((), usize)> {
+    let pattern = b" \n\t";
+    let next_pos = recognize_many(input, pos, |b| pattern.contains(&b));
+    Ok(((), next_pos))
 }
-fn lex_lparen(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b'(').map(|(_, end)| (Token::lparen(Loc(start, end)), end))
+// The character at `pos`, which may take more than one byte, e.g. `°`.
+fn invalid_char_at(input: &[u8], pos: usize) -> LexError {
+    let end = (pos + 1..=(pos + 4).min(input.len()))
+        .find(|&end| std::str::from_utf8(&input[pos..end]).is_ok())
+        .unwrap_or(pos + 1);
+    let c = std::str::from_utf8(&input[pos..end])
+        .ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER);
+    LexError::invalid_char(c, Loc(pos, end))
 }
-fn lex_rparen(input: &[u8], start: usize) -> LexResult<LexValue> {
-    consume_byte(input, start, b')').map(|(_, end)| (Token::rparen(Loc(start, end)), end))
+fn lex_plus_minus(input: &[u8], start: usize) -> LexResult<LexValue> {
+    // `±` is encoded as 0xC2 0xB1 in UTF-8, as are `°` and `£` from 0xC2
+    if !input[start..].starts_with("±".as_bytes()) {
+        return Err(invalid_char_at(input, start));
+    }
+    let end = start + 2;
+    Ok((Token::plus_minus(Loc(start, end)), end))
 }
 
 pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
+    lex_symbols(input, &[])
+}
+
+// Same as `lex`, but also recognise the symbols of the operators in the
+// table, e.g. `!` once a factorial operator is registered.
+pub fn lex_with(input: &str, ops: &OperatorTable) -> Result<Vec<Token>, LexError> {
+    lex_symbols(input, &ops.symbols())
+}
+
+fn lex_symbols(input: &str, symbols: &[&str]) -> Result<Vec<Token>, LexError> {
     let mut tokens = Vec::new();
     let input = input.as_bytes();
     let mut pos = 0;
@@ -141,27 +282,212 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
         }};
     }
     while pos < input.len() {
+        // the longest symbol wins, and a built-in one on a tie
+        let builtin = TokenKind::lex_symbol(&input[pos..]);
+        let len = builtin.as_ref().map_or(0, |(_, len)| *len);
+        let matches = |s: &&&str| input[pos..].starts_with(s.as_bytes());
+        if let Some(symbol) = symbols.iter().filter(matches).max_by_key(|s| s.len()) {
+            if symbol.len() > len {
+                let end = pos + symbol.len();
+                tokens.push(Token::operator(symbol, Loc(pos, end)));
+                pos = end;
+                continue;
+            }
+        }
+        match builtin {
+            Ok((kind, len)) => {
+                tokens.push(Token::new(kind, Loc(pos, pos + len)));
+                pos += len;
+                continue;
+            }
+            // the start of a symbol, e.g. `<` of `<<`
+            Err(Some((symbol, len))) => {
+                return Err(LexError::expected_symbol(symbol, Loc(pos, pos + len)));
+            }
+            Err(None) => {}
+        }
         match input[pos] {
-            b'0'...b'9' => lex_a_token!(lex_number(input, pos)),
-            b'+' => lex_a_token!(lex_plus(input, pos)),
-            b'-' => lex_a_token!(lex_minus(input, pos)),
-            b'*' => lex_a_token!(lex_asterisk(input, pos)),
-            b'/' => lex_a_token!(lex_slash(input, pos)),
-            b'(' => lex_a_token!(lex_lparen(input, pos)),
-            b')' => lex_a_token!(lex_rparen(input, pos)),
+            b'0'..=b'9' => lex_a_token!(lex_number(input, pos)),
+            0xC2 => lex_a_token!(lex_plus_minus(input, pos)),
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => lex_a_token!(lex_ident(input, pos)),
             b' ' | b'\n' | b'\t' => {
                 let ((), p) = skip_spaces(input, pos)?;
                 pos = p;
             }
-            b => return Err(LexError::invalid_char(b as char, Loc(pos, pos + 1))),
+            b'#' => {
+                let ((), p) = skip_comment(input, pos)?;
+                pos = p;
+            }
+            _ => return Err(invalid_char_at(input, pos)),
         }
     }
     Ok(tokens)
 }
 
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let loc = &self.loc;
+        match self.value {
+            LexErrorKind::InvalidChar(c) => write!(f, "{}: invalid char '{}'", loc, c),
+            LexErrorKind::NumberTooLarge => write!(f, "{}: number is too large", loc),
+            LexErrorKind::ExpectedSymbol(s) => write!(f, "{}: expected `{}`", loc, s),
+            LexErrorKind::Eof => write!(f, "End of file"),
+        }
+    }
+}
+
+impl std::fmt::Display for Loc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}-{}", self.0, self.1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    Lex(LexError),
+    Parse(ParseError),
+    Type(TypeError),
+    Eval(EvalError),
+    Limit(LimitError),
+}
+
+impl CalcError {
+    pub fn loc(&self) -> Option<&Loc> {
+        match self {
+            CalcError::Lex(e) => Some(&e.loc),
+            CalcError::Parse(e) => e.loc(),
+            CalcError::Type(e) => Some(&e.loc),
+            CalcError::Eval(e) => Some(&e.loc),
+            CalcError::Limit(e) => Some(&e.loc),
+        }
+    }
+}
+
+impl From<LexError> for CalcError {
+    fn from(e: LexError) -> Self {
+        CalcError::Lex(e)
+    }
+}
+impl From<ParseError> for CalcError {
+    fn from(e: ParseError) -> Self {
+        CalcError::Parse(e)
+    }
+}
+impl From<TypeError> for CalcError {
+    fn from(e: TypeError) -> Self {
+        CalcError::Type(e)
+    }
+}
+impl From<EvalError> for CalcError {
+    fn from(e: EvalError) -> Self {
+        match e.value {
+            EvalErrorKind::Limit(kind) => CalcError::Limit(LimitError::new(kind, e.loc)),
+            _ => CalcError::Eval(e),
+        }
+    }
+}
+impl From<LimitError> for CalcError {
+    fn from(e: LimitError) -> Self {
+        CalcError::Limit(e)
+    }
+}
+
+impl std::fmt::Display for CalcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CalcError::Lex(e) => write!(f, "lexer error: {}", e),
+            CalcError::Parse(e) => write!(f, "parser error: {}", e),
+            CalcError::Type(e) => write!(f, "type error: {}", e),
+            CalcError::Eval(e) => write!(f, "eval error: {}", e),
+            CalcError::Limit(e) => write!(f, "limit error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+// Lex, parse, type-check and evaluate the input at once.
+pub fn eval(input: &str) -> Result<Value, CalcError> {
+    eval_with(&mut Interpreter::new(), input)
+}
+
+// Same as `eval`, but with the settings of the given interpreter.
+pub fn eval_with(interp: &mut Interpreter, input: &str) -> Result<Value, CalcError> {
+    let ast = parse_limited(interp, input)?;
+    check_in(&ast, interp.env())?;
+    Ok(interp.eval(&ast)?)
+}
+
+// Parse the expression of the input within the limits of the interpreter.
+fn parse_limited(interp: &Interpreter, input: &str) -> Result<Ast, CalcError> {
+    let limits = *interp.limits();
+    limits::check_input(&limits, input)?;
+    let tokens = lex_with(input, interp.operators())?;
+    limits::check_nesting(&limits, &tokens)?;
+    let ast = parse_with(tokens, interp.operators())?;
+    limits::check_ast(&limits, &ast, interp.env())?;
+    Ok(ast)
+}
+
+// Execute a statement: evaluate an expression, assign a variable with
+// `x = 1 + 2` or define a function with `f(x) = x * 2`. Returns the value of
+// an expression or an assignment.
+pub fn exec(interp: &mut Interpreter, input: &str) -> Result<Option<Value>, CalcError> {
+    let limits = *interp.limits();
+    limits::check_input(&limits, input)?;
+    let tokens = lex_with(input, interp.operators())?;
+    limits::check_nesting(&limits, &tokens)?;
+    let stmt = parse_stmt_with(tokens, interp.operators())?;
+    let e = match &stmt.value {
+        StmtKind::Expr(e) | StmtKind::Let { e, .. } => e,
+        StmtKind::Def { body, .. } => body,
+    };
+    limits::check_ast(&limits, e, interp.env())?;
+    exec_stmt(interp, &stmt)
+}
+
+fn exec_stmt(interp: &mut Interpreter, stmt: &Stmt) -> Result<Option<Value>, CalcError> {
+    check_stmt(stmt, interp.env())?;
+    match &stmt.value {
+        StmtKind::Expr(e) => Ok(Some(interp.eval(e)?)),
+        StmtKind::Let { name, e } => {
+            let v = interp.eval(e)?;
+            interp.env_mut().set(&name.value, v.clone());
+            Ok(Some(v))
+        }
+        StmtKind::Def { name, params, body } => {
+            interp
+                .env_mut()
+                .define(&name.value, params.clone(), body.clone());
+            Ok(None)
+        }
+    }
+}
+
+// Lex, parse and evaluate the input in interval mode, where every value is
+// an interval and `2.5±0.1` literals are allowed.
+pub fn eval_interval(input: &str) -> Result<Interval, CalcError> {
+    eval_interval_with(&Interpreter::new(), input)
+}
+
+// Same as `eval_interval`, within the limits of the given interpreter. An
+// interval is evaluated once per node, so the steps are checked up front.
+pub fn eval_interval_with(interp: &Interpreter, input: &str) -> Result<Interval, CalcError> {
+    let ast = parse_limited(interp, input)?;
+    Ok(interval::eval(&ast)?)
+}
+
+// Render the input with `^` marks under the given location.
+pub fn annotate(input: &str, loc: &Loc) -> String {
+    let start = loc.0.min(input.len());
+    let width = loc.1.max(start + 1) - start;
+    format!("{}\n{}{}", input, " ".repeat(start), "^".repeat(width))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{lex, Loc, Token, TokenKind};
+    use super::{eval, exec, lex, CalcError, Interpreter, LexError, Loc, Token, TokenKind};
+    use super::{lex_with, Assoc, OperatorTable, TypeErrorKind, Value};
     #[test]
     fn test_lex() {
         let examples = vec![
@@ -185,4 +511,174 @@ mod test {
             assert_eq!(actual, expected);
         }
     }
+
+    #[test]
+    fn test_lex_float() {
+        let examples = vec![
+            ("1.5", vec![Token::float(1.5, Loc(0, 3))]),
+            ("2e3", vec![Token::float(2000.0, Loc(0, 3))]),
+            ("2.5e-1", vec![Token::float(0.25, Loc(0, 6))]),
+            ("3i", vec![Token::imag(3.0, Loc(0, 2))]),
+            ("0.5i", vec![Token::imag(0.5, Loc(0, 4))]),
+            (
+                "7 // 2",
+                vec![
+                    Token::number(7, Loc(0, 1)),
+                    Token::slash_slash(Loc(2, 4)),
+                    Token::number(2, Loc(5, 6)),
+                ],
+            ),
+        ];
+        for (input, expected) in examples {
+            assert_eq!(lex(input), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn test_lex_bitwise() {
+        assert_eq!(
+            lex("~0xff & 0b101 | 0o17 << 2 >> 1"),
+            Ok(vec![
+                Token::tilde(Loc(0, 1)),
+                Token::number(255, Loc(1, 5)),
+                Token::amp(Loc(6, 7)),
+                Token::number(5, Loc(8, 13)),
+                Token::pipe(Loc(14, 15)),
+                Token::number(15, Loc(16, 20)),
+                Token::shl(Loc(21, 23)),
+                Token::number(2, Loc(24, 25)),
+                Token::shr(Loc(26, 28)),
+                Token::number(1, Loc(29, 30)),
+            ])
+        );
+        assert_eq!(
+            lex("0x10000000000000000"),
+            Err(LexError::number_too_large(Loc(0, 19)))
+        );
+        assert_eq!(
+            lex("1 < 2"),
+            Err(LexError::expected_symbol("<<", Loc(2, 3)))
+        );
+        assert_eq!(lex("1 >"), Err(LexError::expected_symbol(">>", Loc(2, 3))));
+        assert_eq!(lex("1 < 2").unwrap_err().to_string(), "2-3: expected `<<`");
+        // the whole character, which starts like `±`
+        assert_eq!(lex("20°"), Err(LexError::invalid_char('°', Loc(2, 4))));
+        assert_eq!(lex("µ"), Err(LexError::invalid_char('µ', Loc(0, 2))));
+        assert_eq!(lex("1 € 2"), Err(LexError::invalid_char('€', Loc(2, 5))));
+    }
+
+    #[test]
+    fn test_lex_plus_minus() {
+        let expected = Ok(vec![
+            Token::number(2, Loc(0, 1)),
+            Token::plus_minus(Loc(1, 3)),
+            Token::float(0.1, Loc(3, 6)),
+        ]);
+        assert_eq!(lex("2±0.1"), expected);
+        assert_eq!(
+            lex("2 +/- 1"),
+            Ok(vec![
+                Token::number(2, Loc(0, 1)),
+                Token::plus_minus(Loc(2, 5)),
+                Token::number(1, Loc(6, 7)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_lex_stmt() {
+        assert_eq!(
+            lex("x = 1 # one\n"),
+            Ok(vec![
+                Token::ident("x", Loc(0, 1)),
+                Token::eq(Loc(2, 3)),
+                Token::number(1, Loc(4, 5)),
+            ])
+        );
+        assert_eq!(lex("# only a comment"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_lex_operators() {
+        let mut ops = OperatorTable::new();
+        ops.postfix("!", 75, "fact").unwrap();
+        ops.infix("**", 65, Assoc::Right, "pow").unwrap();
+        ops.infix("<", 5, Assoc::Left, "lt").unwrap();
+        assert_eq!(
+            lex_with("2**3! < 1 << 2", &ops),
+            Ok(vec![
+                Token::number(2, Loc(0, 1)),
+                Token::operator("**", Loc(1, 3)),
+                Token::number(3, Loc(3, 4)),
+                Token::operator("!", Loc(4, 5)),
+                Token::operator("<", Loc(6, 7)),
+                Token::number(1, Loc(8, 9)),
+                Token::shl(Loc(10, 12)),
+                Token::number(2, Loc(13, 14)),
+            ])
+        );
+        assert!(lex("3!").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(Value::Int(7)));
+        assert_eq!(eval("1 + 2.5"), Ok(Value::Float(3.5)));
+        assert_eq!(eval("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(eval("7 // 2"), Ok(Value::Int(3)));
+        match eval("7.0 // 2") {
+            Err(CalcError::Type(e)) => assert_eq!(e.loc, Loc(0, 3)),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exec() {
+        let mut interp = Interpreter::new();
+        let mut run = |input| exec(&mut interp, input);
+        assert_eq!(run("x = 2"), Ok(Some(Value::Int(2))));
+        assert_eq!(run("f(a) = a * x + 1"), Ok(None));
+        assert_eq!(run("f(3)"), Ok(Some(Value::Int(7))));
+        assert_eq!(run("f(1.5)"), Ok(Some(Value::Float(4.0))));
+        assert_eq!(run("x = 0.5 # rebind"), Ok(Some(Value::Float(0.5))));
+        assert_eq!(run("f(4)"), Ok(Some(Value::Float(3.0))));
+        assert_eq!(run("g(a, b) = f(a) // b"), Ok(None));
+        match run("1 + g(1, 2)") {
+            Err(CalcError::Type(e)) => assert_eq!(e.loc, Loc(4, 11)),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exec_def_error() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "f(a) = a + 1").unwrap();
+        exec(&mut interp, "g(a) = f(a) * 2").unwrap();
+        let mut err = |input| match exec(&mut interp, input) {
+            Err(CalcError::Type(e)) => e.value,
+            other => panic!("unexpected: {:?}", other),
+        };
+        let name = |s: &str| s.to_string();
+        assert_eq!(
+            err("h(a) = h(a)"),
+            TypeErrorKind::RecursiveFunction(name("h"))
+        );
+        assert_eq!(
+            err("f(a) = g(a)"),
+            TypeErrorKind::RecursiveFunction(name("f"))
+        );
+        assert_eq!(
+            err("sum(a) = a"),
+            TypeErrorKind::BuiltinRedefined(name("sum"))
+        );
+        assert_eq!(err("h(a, a) = a"), TypeErrorKind::DuplicateParam(name("a")));
+        assert_eq!(err("h(a) = b"), TypeErrorKind::UnknownVariable(name("b")));
+        assert_eq!(
+            err("h(a) = f(a, a)"),
+            TypeErrorKind::ArgCount {
+                expected: 1,
+                found: 2
+            }
+        );
+    }
 }
@@ -0,0 +1,135 @@
+use super::eval;
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchFormat {
+    // `input,result,error` with a header row
+    Csv,
+    // `{"input": ..., "result": ..., "error": ...}` per line
+    JsonLines,
+}
+
+impl std::str::FromStr for BatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(BatchFormat::Csv),
+            "json" | "jsonl" => Ok(BatchFormat::JsonLines),
+            _ => Err(format!("unknown format '{}', expected csv or json", s)),
+        }
+    }
+}
+
+// The number of expressions evaluated and how many of them failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub failed: usize,
+}
+
+// Evaluate each line of the input on its own and write a row for it. Blank
+// lines and comments are skipped, a failing expression gives a row with an
+// empty result and the evaluation goes on.
+pub fn eval_batch<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    format: BatchFormat,
+) -> io::Result<BatchSummary> {
+    let mut summary = BatchSummary {
+        total: 0,
+        failed: 0,
+    };
+    if format == BatchFormat::Csv {
+        writeln!(output, "input,result,error")?;
+    }
+    for line in input.lines() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        summary.total += 1;
+        let (result, error) = match eval(trimmed) {
+            Ok(v) => (Some(v.to_string()), None),
+            Err(e) => {
+                summary.failed += 1;
+                (None, Some(e.to_string()))
+            }
+        };
+        match format {
+            BatchFormat::Csv => writeln!(
+                output,
+                "{},{},{}",
+                csv_field(trimmed),
+                csv_field(result.as_deref().unwrap_or("")),
+                csv_field(error.as_deref().unwrap_or(""))
+            )?,
+            BatchFormat::JsonLines => {
+                let row = serde_json::json!({
+                    "input": trimmed,
+                    "result": result,
+                    "error": error,
+                });
+                writeln!(output, "{}", row)?
+            }
+        }
+    }
+    output.flush()?;
+    Ok(summary)
+}
+
+// Quote a field which contains a separator, a quote or a line break.
+pub(super) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{eval_batch, BatchFormat, BatchSummary};
+
+    const INPUT: &str = "1 + 2\n\n# a comment\nsort([3, 1])\n1 // 0\nmax(1.5, 2)\n";
+
+    #[test]
+    fn test_batch_csv() {
+        let mut output = Vec::new();
+        let summary = eval_batch(INPUT.as_bytes(), &mut output, BatchFormat::Csv).unwrap();
+        assert_eq!(
+            summary,
+            BatchSummary {
+                total: 4,
+                failed: 2
+            }
+        );
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "input,result,error");
+        assert_eq!(lines[1], "1 + 2,3,");
+        assert_eq!(lines[2], "\"sort([3, 1])\",\"[1, 3]\",");
+        assert_eq!(lines[3], "1 // 0,,eval error: 2-4: division by zero");
+        assert!(lines[4].starts_with("\"max(1.5, 2)\",,"));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_batch_json() {
+        let mut output = Vec::new();
+        eval_batch(INPUT.as_bytes(), &mut output, BatchFormat::JsonLines).unwrap();
+        let rows: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[0],
+            serde_json::json!({"input": "1 + 2", "result": "3", "error": null})
+        );
+        assert_eq!(rows[2]["result"], serde_json::Value::Null);
+        assert_eq!(rows[2]["error"], "eval error: 2-4: division by zero");
+    }
+}
@@ -0,0 +1,406 @@
+use super::{forms, lambda, matrix, EvalErrorKind, Type, Value};
+use crate::bitonic;
+use std::cmp::Ordering;
+
+// The kind of argument a built-in function accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Param {
+    Int,
+    // an int or a float
+    Real,
+    Number,
+    RealList,
+    // a list of rows, e.g. `[[1, 2], [3, 4]]`
+    RealMatrix,
+}
+
+pub(super) struct Builtin {
+    pub(super) name: &'static str,
+    pub(super) params: &'static [Param],
+    // Computes the result type from the argument types.
+    pub(super) ret: fn(&[Type]) -> Type,
+    pub(super) eval: fn(&[Value]) -> Result<Value, EvalErrorKind>,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sum",
+        params: &[Param::RealList],
+        ret: elem_type,
+        eval: sum,
+    },
+    Builtin {
+        name: "mean",
+        params: &[Param::RealList],
+        ret: float_type,
+        eval: mean,
+    },
+    Builtin {
+        name: "median",
+        params: &[Param::RealList],
+        ret: float_type,
+        eval: median,
+    },
+    Builtin {
+        name: "stddev",
+        params: &[Param::RealList],
+        ret: float_type,
+        eval: stddev,
+    },
+    Builtin {
+        name: "min",
+        params: &[Param::RealList],
+        ret: elem_type,
+        eval: min,
+    },
+    Builtin {
+        name: "max",
+        params: &[Param::RealList],
+        ret: elem_type,
+        eval: max,
+    },
+    Builtin {
+        name: "sort",
+        params: &[Param::RealList],
+        ret: first_type,
+        eval: sort,
+    },
+    Builtin {
+        name: "abs",
+        params: &[Param::Number],
+        ret: real_type,
+        eval: abs,
+    },
+    Builtin {
+        name: "arg",
+        params: &[Param::Number],
+        ret: float_type,
+        eval: arg,
+    },
+    Builtin {
+        name: "conj",
+        params: &[Param::Number],
+        ret: first_type,
+        eval: conj,
+    },
+    Builtin {
+        name: "re",
+        params: &[Param::Number],
+        ret: real_type,
+        eval: re,
+    },
+    Builtin {
+        name: "im",
+        params: &[Param::Number],
+        ret: real_type,
+        eval: im,
+    },
+    Builtin {
+        name: "fact",
+        params: &[Param::Int],
+        ret: first_type,
+        eval: fact,
+    },
+    Builtin {
+        name: "transpose",
+        params: &[Param::RealMatrix],
+        ret: first_type,
+        eval: matrix::transpose,
+    },
+    Builtin {
+        name: "det",
+        params: &[Param::RealMatrix],
+        ret: det_type,
+        eval: matrix::det,
+    },
+    Builtin {
+        name: "inv",
+        params: &[Param::RealMatrix],
+        ret: float_matrix_type,
+        eval: matrix::inv,
+    },
+    Builtin {
+        name: "linsolve",
+        params: &[Param::RealMatrix, Param::RealList],
+        ret: float_list_type,
+        eval: matrix::linsolve,
+    },
+];
+
+// The names of the builtins and of the forms like `solve`.
+pub(super) fn names() -> impl Iterator<Item = &'static str> {
+    let forms = forms::names().filter(|name| lookup(name).is_none());
+    BUILTINS
+        .iter()
+        .map(|b| b.name)
+        .chain(forms)
+        .chain(lambda::names())
+}
+
+pub(super) fn is_builtin(name: &str) -> bool {
+    names().any(|n| n == name)
+}
+
+pub(super) fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+fn elem_type(args: &[Type]) -> Type {
+    match &args[0] {
+        Type::List(t) => (**t).clone(),
+        _ => Type::Any,
+    }
+}
+fn float_type(_: &[Type]) -> Type {
+    Type::Float
+}
+fn first_type(args: &[Type]) -> Type {
+    args[0].clone()
+}
+// A matrix of integers has an integer determinant.
+fn det_type(args: &[Type]) -> Type {
+    match elem_type(&[elem_type(args)]) {
+        Type::Float => Type::Float,
+        _ => Type::Int,
+    }
+}
+fn float_list_type(_: &[Type]) -> Type {
+    Type::List(Box::new(Type::Float))
+}
+fn float_matrix_type(_: &[Type]) -> Type {
+    Type::List(Box::new(float_list_type(&[])))
+}
+// `Complex` is mapped to `Float`, real types are kept as is.
+fn real_type(args: &[Type]) -> Type {
+    match &args[0] {
+        Type::Complex => Type::Float,
+        t => t.clone(),
+    }
+}
+
+fn list(v: &Value) -> &[Value] {
+    match v {
+        Value::List(xs) => xs,
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+fn floats(v: &Value) -> Result<Vec<f64>, EvalErrorKind> {
+    let xs: Vec<f64> = list(v).iter().map(Value::as_f64).collect();
+    if xs.is_empty() {
+        Err(EvalErrorKind::EmptyList)
+    } else {
+        Ok(xs)
+    }
+}
+
+fn sum(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = list(&args[0]);
+    if xs.iter().all(|x| matches!(x, Value::Int(_))) {
+        let mut acc = 0i64;
+        for x in xs {
+            if let Value::Int(n) = x {
+                acc = acc.checked_add(*n).ok_or(EvalErrorKind::Overflow)?;
+            }
+        }
+        Ok(Value::Int(acc))
+    } else {
+        Ok(Value::Float(xs.iter().map(Value::as_f64).sum()))
+    }
+}
+
+fn mean(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = floats(&args[0])?;
+    Ok(Value::Float(xs.iter().sum::<f64>() / xs.len() as f64))
+}
+
+fn median(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = bitonic_sort(floats(&args[0])?, f64::total_cmp);
+    let mid = xs.len() / 2;
+    if xs.len() % 2 == 0 {
+        Ok(Value::Float((xs[mid - 1] + xs[mid]) / 2.0))
+    } else {
+        Ok(Value::Float(xs[mid]))
+    }
+}
+
+// Population standard deviation.
+fn stddev(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = floats(&args[0])?;
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    Ok(Value::Float(var.sqrt()))
+}
+
+fn min(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = sort(args)?;
+    list(&xs).first().cloned().ok_or(EvalErrorKind::EmptyList)
+}
+
+fn max(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = sort(args)?;
+    list(&xs).last().cloned().ok_or(EvalErrorKind::EmptyList)
+}
+
+fn sort(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let xs = list(&args[0]);
+    let ints: Option<Vec<i64>> = xs
+        .iter()
+        .map(|x| match x {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        })
+        .collect();
+    let sorted = match ints {
+        Some(ns) => bitonic_sort(ns, i64::cmp)
+            .into_iter()
+            .map(Value::Int)
+            .collect(),
+        None => {
+            let fs = xs.iter().map(Value::as_f64).collect();
+            bitonic_sort(fs, f64::total_cmp)
+                .into_iter()
+                .map(Value::Float)
+                .collect()
+        }
+    };
+    Ok(Value::List(sorted))
+}
+
+fn abs(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    match &args[0] {
+        Value::Int(n) => n
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or(EvalErrorKind::Overflow),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        Value::Complex(z) => Ok(Value::Float(z.abs())),
+        Value::List(_) | Value::Lambda(_) => unreachable!("rejected by the type checker"),
+    }
+}
+
+fn arg(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    Ok(Value::Float(args[0].as_complex().arg()))
+}
+
+fn conj(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    match &args[0] {
+        Value::Complex(z) => Ok(Value::Complex(z.conj())),
+        v => Ok(v.clone()),
+    }
+}
+
+fn re(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    match &args[0] {
+        Value::Complex(z) => Ok(Value::Float(z.re)),
+        v => Ok(v.clone()),
+    }
+}
+
+fn im(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    match &args[0] {
+        Value::Int(_) => Ok(Value::Int(0)),
+        Value::Float(_) => Ok(Value::Float(0.0)),
+        Value::Complex(z) => Ok(Value::Float(z.im)),
+        Value::List(_) | Value::Lambda(_) => unreachable!("rejected by the type checker"),
+    }
+}
+
+// The bitonic sorter only accepts power-of-two lengths, so pad `xs` with
+// `None`s which are ordered after every element and drop them afterwards.
+fn bitonic_sort<T, F>(xs: Vec<T>, cmp: F) -> Vec<T>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let len = xs.len();
+    let mut padded: Vec<Option<T>> = xs.into_iter().map(Some).collect();
+    padded.resize_with(len.next_power_of_two(), || None);
+    bitonic::sort_by(&mut padded, &|a: &Option<T>, b: &Option<T>| match (a, b) {
+        (Some(a), Some(b)) => cmp(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    })
+    .expect("padded to a power of two");
+    padded.into_iter().flatten().collect()
+}
+
+fn fact(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    match args[0] {
+        Value::Int(n) if n < 0 => Err(EvalErrorKind::NegativeFactorial(n)),
+        Value::Int(n) => (1..=n)
+            .try_fold(1i64, |acc, k| acc.checked_mul(k))
+            .map(Value::Int)
+            .ok_or(EvalErrorKind::Overflow),
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::bitonic_sort;
+    use crate::calc::{eval, CalcError, Complex, EvalErrorKind, Value};
+
+    #[test]
+    fn test_bitonic_sort() {
+        assert_eq!(bitonic_sort(vec![3, 1, 2], i32::cmp), vec![1, 2, 3]);
+        assert_eq!(bitonic_sort(Vec::<i32>::new(), i32::cmp), Vec::<i32>::new());
+        assert_eq!(
+            bitonic_sort(vec![5, 4, 3, 2, 1], i32::cmp),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let xs = "[4, 1, 3, 2, 10]";
+        let call = |f: &str| eval(&format!("{}({})", f, xs));
+        assert_eq!(call("sum"), Ok(Value::Int(20)));
+        assert_eq!(call("mean"), Ok(Value::Float(4.0)));
+        assert_eq!(call("median"), Ok(Value::Float(3.0)));
+        assert_eq!(call("min"), Ok(Value::Int(1)));
+        assert_eq!(call("max"), Ok(Value::Int(10)));
+        assert_eq!(
+            call("sort"),
+            Ok(Value::List(
+                vec![1, 2, 3, 4, 10].into_iter().map(Value::Int).collect()
+            ))
+        );
+        assert_eq!(eval("median([1, 2, 3, 4])"), Ok(Value::Float(2.5)));
+        assert_eq!(
+            eval("stddev([2, 4, 4, 4, 5, 5, 7, 9])"),
+            Ok(Value::Float(2.0))
+        );
+        assert_eq!(eval("sum([1, 2.5])"), Ok(Value::Float(3.5)));
+        assert_eq!(eval("max([1, 2.5])"), Ok(Value::Float(2.5)));
+    }
+
+    #[test]
+    fn test_complex() {
+        assert_eq!(eval("abs(3 + 4i)"), Ok(Value::Float(5.0)));
+        assert_eq!(eval("abs(-3)"), Ok(Value::Int(3)));
+        assert_eq!(
+            eval("arg(1i)"),
+            Ok(Value::Float(std::f64::consts::FRAC_PI_2))
+        );
+        assert_eq!(eval("arg(-1)"), Ok(Value::Float(std::f64::consts::PI)));
+        assert_eq!(
+            eval("conj(1 + 2i)"),
+            Ok(Value::Complex(Complex::new(1.0, -2.0)))
+        );
+        assert_eq!(eval("re(1 + 2i)"), Ok(Value::Float(1.0)));
+        assert_eq!(eval("im(1 + 2i)"), Ok(Value::Float(2.0)));
+        assert_eq!(eval("im(5)"), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_empty_list() {
+        assert_eq!(eval("sum([])"), Ok(Value::Int(0)));
+        match eval("mean([])") {
+            Err(CalcError::Eval(e)) => assert_eq!(e.value, EvalErrorKind::EmptyList),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
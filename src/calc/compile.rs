@@ -0,0 +1,251 @@
+use super::interp::{binop, index, int, uniop, unwrap_word};
+use super::{builtins, check_in, forms, Ast, AstKind, CalcError, Complex, Env, EvalError, Ident};
+use super::{EvalErrorKind, Interpreter, Loc, TypeError, Value, WordWidth};
+
+// An expression translated into a tree of closures. Evaluating it does not
+// walk the AST or look up builtins again, only variables are looked up in
+// the given `Env`.
+pub type Compiled = Box<dyn Fn(&Env) -> Result<Value, CalcError>>;
+
+// Type check the expression with the variables of the interpreter and
+// compile it, so that it can be evaluated repeatedly with other bindings.
+//
+// A variable must be bound to a value of the type it was compiled with (an
+// `int` is accepted for a `float`), otherwise the compiled function returns
+// a type error instead of evaluating. Lambdas and their calls are not
+// compiled, they are left to the interpreter.
+pub fn compile(interp: &Interpreter, ast: &Ast) -> Result<Compiled, CalcError> {
+    check_in(ast, interp.env())?;
+    if let Some(loc) = find_lambda(ast, interp.env()) {
+        return Err(EvalError::new(EvalErrorKind::UnsupportedInCompiled, loc).into());
+    }
+    let node = compile_node(ast, interp.env(), &[], interp.word_width());
+    Ok(Box::new(move |env| node(env, &[])))
+}
+
+// The first lambda, call of a lambda or of `map` and the like, or the call
+// of a user-defined function which has one.
+fn find_lambda(ast: &Ast, env: &Env) -> Option<Loc> {
+    match &ast.value {
+        AstKind::Lambda { .. } => return Some(ast.loc.clone()),
+        AstKind::Call { func, args }
+            if forms::lookup(&func.value, args.len()).is_none()
+                && builtins::lookup(&func.value).is_none() =>
+        {
+            match (env.get(&func.value), env.get_func(&func.value)) {
+                (Some(Value::Lambda(_)), _) | (_, None) => return Some(ast.loc.clone()),
+                (_, Some(f)) if find_lambda(f.body(), env).is_some() => {
+                    return Some(ast.loc.clone())
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    ast.children().into_iter().find_map(|e| find_lambda(e, env))
+}
+
+// A node of the closure tree. The slice holds the arguments of the
+// user-defined function the node is inlined into.
+type Node = Box<dyn Fn(&Env, &[Value]) -> Result<Value, CalcError>>;
+
+// Calls of user-defined functions are inlined, `params` are the parameters
+// of the function `ast` belongs to.
+fn compile_node(ast: &Ast, env: &Env, params: &[Ident], word: Option<WordWidth>) -> Node {
+    let loc = ast.loc.clone();
+    let compile = |e: &Ast| compile_node(e, env, params, word);
+    match &ast.value {
+        AstKind::Int(n) => {
+            let v = int(word, *n, &loc);
+            Box::new(move |_, _| Ok(v.clone()?))
+        }
+        AstKind::Float(f) => {
+            let f = *f;
+            Box::new(move |_, _| Ok(Value::Float(f)))
+        }
+        AstKind::Imag(f) => {
+            let z = Complex::new(0.0, *f);
+            Box::new(move |_, _| Ok(Value::Complex(z)))
+        }
+        AstKind::Var(name) if params.iter().any(|p| &p.value == name) => {
+            // the last one, as the variable of a form shadows a parameter
+            let i = params.iter().rposition(|p| &p.value == name).unwrap();
+            Box::new(move |_, args| Ok(args[i].clone()))
+        }
+        AstKind::Var(name) => {
+            let name = name.clone();
+            let expected = env
+                .get(&name)
+                .expect("checked by the type checker")
+                .type_of();
+            Box::new(move |env, _| {
+                let v = env
+                    .get(&name)
+                    .ok_or_else(|| EvalError::unbound_variable(&name, loc.clone()))?;
+                let found = v.type_of();
+                if expected.promote(&found).as_ref() != Some(&expected) {
+                    return Err(TypeError::mismatch(expected.clone(), found, loc.clone()).into());
+                }
+                Ok(v.clone())
+            })
+        }
+        AstKind::PlusMinus { .. } => unreachable!("rejected by the type checker"),
+        AstKind::Lambda { .. } => unreachable!("rejected by find_lambda"),
+        AstKind::List(elems) => {
+            let elems: Vec<_> = elems.iter().map(compile).collect();
+            Box::new(move |env, args| {
+                let elems = elems
+                    .iter()
+                    .map(|e| e(env, args))
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::list(elems))
+            })
+        }
+        AstKind::Index { e, index: i } => {
+            let iloc = i.loc.clone();
+            let e = compile(e);
+            let i = compile(i);
+            Box::new(move |env, args| Ok(index(e(env, args)?, i(env, args)?, &iloc)?))
+        }
+        AstKind::Call { func, args } if forms::lookup(&func.value, args.len()).is_some() => {
+            let form = forms::lookup(&func.value, args.len()).unwrap();
+            let x = forms::bound_var(args).expect("checked by the type checker");
+            let mut inner = params.to_vec();
+            inner.push(Ident::new(x.to_string(), args[1].loc.clone()));
+            let body = compile_node(&args[0], env, &inner, word);
+            let params: Vec<_> = args[2..].iter().map(compile).collect();
+            Box::new(move |env, locals| {
+                let params = params
+                    .iter()
+                    .map(|e| e(env, locals).map(|v| unwrap_word(word, v)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut locals = locals.to_vec();
+                locals.push(Value::Int(0));
+                let mut f = |v: Value| {
+                    *locals.last_mut().unwrap() = v;
+                    match body(env, &locals) {
+                        Ok(v) => Ok(unwrap_word(word, v)),
+                        Err(CalcError::Eval(e)) => Err(forms::inner_error(form, e, &loc).into()),
+                        Err(e) => Err(e),
+                    }
+                };
+                (form.eval)(&mut f, &params, &loc)
+            })
+        }
+        AstKind::Call { func, args } => {
+            let args: Vec<_> = args.iter().map(compile).collect();
+            let eval_args = move |env: &Env, locals: &[Value]| {
+                args.iter()
+                    .map(|e| e(env, locals))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            if let Some(builtin) = builtins::lookup(&func.value) {
+                return Box::new(move |env, locals| {
+                    let args = eval_args(env, locals)?;
+                    (builtin.eval)(&args).map_err(|kind| EvalError::new(kind, loc.clone()).into())
+                });
+            }
+            let f = env
+                .get_func(&func.value)
+                .expect("checked by the type checker");
+            let body = compile_node(f.body(), env, f.params(), word);
+            Box::new(move |env, locals| {
+                let args = eval_args(env, locals)?;
+                // the body is on another line, so report errors at the call
+                body(env, &args).map_err(|e| match e {
+                    CalcError::Eval(e) => EvalError::new(e.value, loc.clone()).into(),
+                    CalcError::Type(e) => TypeError::new(e.value, loc.clone()).into(),
+                    e => e,
+                })
+            })
+        }
+        AstKind::UniOp { op, e } => {
+            let op = op.clone();
+            let e = compile(e);
+            Box::new(move |env, args| Ok(uniop(word, &op, e(env, args)?, &loc)?))
+        }
+        AstKind::BinOp { op, l, r } => {
+            let op = op.clone();
+            let l = compile(l);
+            let r = compile(r);
+            Box::new(move |env, args| Ok(binop(word, &op, l(env, args)?, r(env, args)?, &loc)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile;
+    use crate::calc::{
+        eval_with, exec, lex, parse, CalcError, Env, EvalErrorKind, Interpreter, Value,
+    };
+
+    #[test]
+    fn test_compile() {
+        let mut interp = Interpreter::new();
+        interp.env_mut().set("x", Value::Int(0));
+        interp.env_mut().set("y", Value::Float(0.0));
+        let input = "x * x + 3 * x - y / 2 + max([x, y, 1])";
+        let f = compile(&interp, &parse(lex(input).unwrap()).unwrap()).unwrap();
+        let mut env = Env::new();
+        for i in -5..5 {
+            env.set("x", Value::Int(i));
+            env.set("y", Value::Float(i as f64 * 1.5));
+            interp.env_mut().set("x", Value::Int(i));
+            interp.env_mut().set("y", Value::Float(i as f64 * 1.5));
+            assert_eq!(f(&env), Ok(eval_with(&mut interp, input).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_compile_function() {
+        let mut interp = Interpreter::new();
+        interp.env_mut().set("x", Value::Int(0));
+        interp.env_mut().set("y", Value::Int(10));
+        exec(&mut interp, "f(a, b) = a * y - b").unwrap();
+        let f = compile(&interp, &parse(lex("f(x, 1) + x").unwrap()).unwrap()).unwrap();
+        let mut env = Env::new();
+        env.set("x", Value::Int(2));
+        env.set("y", Value::Int(3));
+        assert_eq!(f(&env), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_compile_form() {
+        let mut interp = Interpreter::new();
+        interp.env_mut().set("a", Value::Int(0));
+        let input = "solve(x * x - a, x, 1) + roots(x - a, x, 0, 10)[0]";
+        let f = compile(&interp, &parse(lex(input).unwrap()).unwrap()).unwrap();
+        let mut env = Env::new();
+        for a in 1..5 {
+            env.set("a", Value::Int(a));
+            interp.env_mut().set("a", Value::Int(a));
+            assert_eq!(f(&env), Ok(eval_with(&mut interp, input).unwrap()));
+        }
+        // a type error in the expression stops the method
+        env.set("a", Value::Float(1.0));
+        assert!(matches!(f(&env), Err(CalcError::Type(_))));
+    }
+
+    #[test]
+    fn test_compile_error() {
+        let mut interp = Interpreter::new();
+        interp.env_mut().set("x", Value::Int(1));
+        let f = compile(&interp, &parse(lex("7 // x").unwrap()).unwrap()).unwrap();
+        let mut env = Env::new();
+        match f(&env) {
+            Err(CalcError::Eval(e)) => {
+                assert_eq!(e.value, EvalErrorKind::UnboundVariable("x".to_string()))
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        env.set("x", Value::Int(0));
+        match f(&env) {
+            Err(CalcError::Eval(e)) => assert_eq!(e.value, EvalErrorKind::DivisionByZero),
+            other => panic!("unexpected: {:?}", other),
+        }
+        env.set("x", Value::Float(2.0));
+        assert!(matches!(f(&env), Err(CalcError::Type(_))));
+        assert!(compile(&interp, &parse(lex("y").unwrap()).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub(super) fn neg(self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+
+    pub(super) fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+
+    pub(super) fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+
+    pub(super) fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+
+    // Returns `None` when dividing by zero.
+    pub(super) fn div(self, other: Self) -> Option<Self> {
+        let d = other.re * other.re + other.im * other.im;
+        if d == 0.0 {
+            return None;
+        }
+        let n = self.mul(other.conj());
+        Some(Self::new(n.re / d, n.im / d))
+    }
+
+    // Render as `r∠θ` with θ in radians.
+    pub fn to_polar_string(self) -> String {
+        format!("{}∠{}", self.abs(), self.arg())
+    }
+}
+
+// Rendered in rectangular form, e.g. `2+3i`, `-1.5i`.
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.re == 0.0 {
+            return write!(f, "{}i", self.im);
+        }
+        let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+        write!(f, "{}{}{}i", self.re, sign, self.im.abs())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Complex;
+
+    #[test]
+    fn test_arith() {
+        let a = Complex::new(1.0, 2.0);
+        let b = Complex::new(3.0, -1.0);
+        assert_eq!(a.add(b), Complex::new(4.0, 1.0));
+        assert_eq!(a.sub(b), Complex::new(-2.0, 3.0));
+        assert_eq!(a.mul(b), Complex::new(5.0, 5.0));
+        assert_eq!(a.mul(b).div(b), Some(a));
+        assert_eq!(a.div(Complex::new(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Complex::new(2.0, 3.0).to_string(), "2+3i");
+        assert_eq!(Complex::new(2.5, -0.5).to_string(), "2.5-0.5i");
+        assert_eq!(Complex::new(0.0, -1.0).to_string(), "-1i");
+        assert_eq!(
+            Complex::new(0.0, 1.0).to_polar_string(),
+            "1∠1.5707963267948966"
+        );
+    }
+}
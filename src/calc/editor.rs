@@ -0,0 +1,126 @@
+use super::{builtins, highlight, Env};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+// Line editor support for the REPL: highlighting with the cursor position
+// and completion of the names known to the environment. Functions are
+// completed with the opening parenthesis.
+#[derive(Clone, Debug)]
+pub struct CalcHelper {
+    names: Vec<String>,
+}
+
+impl CalcHelper {
+    pub fn new() -> Self {
+        let mut helper = CalcHelper { names: Vec::new() };
+        helper.set_env(&Env::new());
+        helper
+    }
+
+    // Take the names of the environment, to be called after each line.
+    pub fn set_env(&mut self, env: &Env) {
+        let builtins = builtins::names().map(|name| format!("{}(", name));
+        let funcs = env
+            .funcs()
+            .into_iter()
+            .map(|(name, _)| format!("{}(", name));
+        let vars = env.vars().into_iter().map(|(name, _)| name.to_string());
+        let mut names: Vec<_> = builtins.chain(funcs).chain(vars).collect();
+        names.sort();
+        names.dedup();
+        self.names = names;
+    }
+
+    // The start of the identifier before `pos` and the names it prefixes.
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return (pos, Vec::new());
+        }
+        let names = self
+            .names
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .cloned()
+            .collect();
+        (start, names)
+    }
+}
+
+impl Default for CalcHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Completer for CalcHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Highlighter for CalcHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line, Some(pos)))
+    }
+
+    // the matched brackets move with the cursor
+    fn highlight_char(&self, _: &str, _: usize, _: bool) -> bool {
+        true
+    }
+}
+
+impl Hinter for CalcHelper {
+    type Hint = String;
+}
+
+impl Validator for CalcHelper {}
+
+impl Helper for CalcHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::CalcHelper;
+    use crate::calc::{exec, Interpreter};
+
+    #[test]
+    fn test_candidates() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "total = 1").unwrap();
+        exec(&mut interp, "twice(x) = x * 2").unwrap();
+        let mut helper = CalcHelper::new();
+        helper.set_env(interp.env());
+        assert_eq!(
+            helper.candidates("1 + tw", 6),
+            (4, vec!["twice(".to_string()])
+        );
+        assert_eq!(
+            helper.candidates("t + 1", 1),
+            (
+                0,
+                vec![
+                    "total".to_string(),
+                    "transpose(".to_string(),
+                    "twice(".to_string()
+                ]
+            )
+        );
+        assert_eq!(helper.candidates("su", 2), (0, vec!["sum(".to_string()]));
+        assert_eq!(helper.candidates("1 + ", 4), (4, vec![]));
+        assert_eq!(helper.candidates("2e", 2), (2, vec![]));
+    }
+}
@@ -0,0 +1,475 @@
+use super::builtins::{self, Param};
+use super::interp::binop;
+use super::{
+    Ast, AstKind, BinOp, BinOpKind, CalcError, EvalError, EvalErrorKind, Loc, Type, Value,
+};
+
+// Built-in functions which bind a variable in their first argument, as `x`
+// in `solve(x * x - 2, x, 1)`. The expression is evaluated again for each
+// value of the variable, so these are evaluated by the interpreter rather
+// than from the values of their arguments like the other builtins.
+pub(super) struct Form {
+    pub(super) name: &'static str,
+    // the type of the bound variable
+    pub(super) var: Type,
+    // the expression and the arguments after the variable
+    pub(super) body: Param,
+    pub(super) params: &'static [Param],
+    // Computes the result type from the type of the expression.
+    pub(super) ret: fn(&Type) -> Type,
+    pub(super) eval: fn(&mut Func, &[Value], &Loc) -> Result<Value, CalcError>,
+}
+
+// The expression as a function of the variable. The errors are those of
+// the interpreter or of a compiled expression.
+pub(super) type Func<'a> = dyn FnMut(Value) -> Result<Value, CalcError> + 'a;
+
+const FORMS: &[Form] = &[
+    Form {
+        name: "solve",
+        var: Type::Float,
+        body: Param::Real,
+        params: &[Param::Real],
+        ret: float_type,
+        eval: solve,
+    },
+    Form {
+        name: "roots",
+        var: Type::Float,
+        body: Param::Real,
+        params: &[Param::Real, Param::Real],
+        ret: float_list_type,
+        eval: roots,
+    },
+    Form {
+        name: "integrate",
+        var: Type::Float,
+        body: Param::Real,
+        params: &[Param::Real, Param::Real],
+        ret: float_type,
+        eval: integrate,
+    },
+    Form {
+        name: "sum",
+        var: Type::Int,
+        body: Param::Number,
+        params: &[Param::Int, Param::Int],
+        ret: same_type,
+        eval: sum,
+    },
+    Form {
+        name: "product",
+        var: Type::Int,
+        body: Param::Number,
+        params: &[Param::Int, Param::Int],
+        ret: same_type,
+        eval: product,
+    },
+];
+
+// A form is found by its name and the number of arguments, as a builtin
+// may have the same name. A form with another number of arguments is
+// returned when no builtin has the name, for the error message.
+pub(super) fn lookup(name: &str, args: usize) -> Option<&'static Form> {
+    let mut forms = FORMS.iter().filter(|f| f.name == name);
+    match forms.clone().find(|f| f.params.len() + 2 == args) {
+        Some(form) => Some(form),
+        None if builtins::lookup(name).is_none() => forms.next(),
+        None => None,
+    }
+}
+
+pub(super) fn names() -> impl Iterator<Item = &'static str> {
+    FORMS.iter().map(|f| f.name)
+}
+
+// The name of the variable bound by a form, the second argument.
+pub(super) fn bound_var(args: &[Ast]) -> Option<&str> {
+    match args.get(1).map(|e| &e.value) {
+        Some(AstKind::Var(name)) => Some(name),
+        _ => None,
+    }
+}
+
+// An error in the expression is located at the call, and keeps where in
+// the expression it occurred. A limit is not wrapped, it stops the whole
+// evaluation.
+pub(super) fn inner_error(form: &Form, e: EvalError, loc: &Loc) -> EvalError {
+    match e.value {
+        EvalErrorKind::Limit(_) => e,
+        _ => {
+            let kind = EvalErrorKind::InForm {
+                form: form.name,
+                error: Box::new(e),
+            };
+            EvalError::new(kind, loc.clone())
+        }
+    }
+}
+
+fn float_type(_: &Type) -> Type {
+    Type::Float
+}
+fn float_list_type(_: &Type) -> Type {
+    Type::List(Box::new(Type::Float))
+}
+fn same_type(t: &Type) -> Type {
+    t.clone()
+}
+
+// In programmer mode the values are the numbers the `Int`s represent, as
+// floats.
+fn int(v: &Value) -> i64 {
+    match v {
+        Value::Int(n) => *n,
+        v => v.as_f64() as i64,
+    }
+}
+
+// The expression as a real function.
+fn at(f: &mut Func, x: f64) -> Result<f64, CalcError> {
+    f(Value::Float(x)).map(|v| v.as_f64())
+}
+
+const MAX_ITERATIONS: usize = 100;
+
+// The tolerance of the methods, relative to the magnitude of `x`.
+fn tolerance(x: f64) -> f64 {
+    1e-12 * x.abs().max(1.0)
+}
+
+// Newton's method from `guess`, with a central difference as the
+// derivative.
+fn solve(f: &mut Func, args: &[Value], loc: &Loc) -> Result<Value, CalcError> {
+    let error = |kind| Err(EvalError::new(kind, loc.clone()).into());
+    let mut x = args[0].as_f64();
+    for _ in 0..MAX_ITERATIONS {
+        let fx = at(f, x)?;
+        if fx == 0.0 {
+            return Ok(Value::Float(x));
+        }
+        let h = 1e-6 * x.abs().max(1.0);
+        let d = (at(f, x + h)? - at(f, x - h)?) / (2.0 * h);
+        if d == 0.0 || !d.is_finite() || !fx.is_finite() {
+            return error(EvalErrorKind::ZeroDerivative(x));
+        }
+        let step = fx / d;
+        x -= step;
+        if step.abs() <= tolerance(x) {
+            return Ok(Value::Float(x));
+        }
+    }
+    error(EvalErrorKind::NoConvergence {
+        method: "newton's method",
+        iterations: MAX_ITERATIONS,
+        last: x,
+    })
+}
+
+// The number of pieces `roots` splits the interval into, each of which is
+// searched for a change of sign.
+const PIECES: usize = 1000;
+
+// The roots in `[lo, hi]` where the expression changes its sign, refined
+// by bisection. A double root like that of `x * x` has no change of sign
+// and is only found if it is hit exactly. A pole like that of `1 / x`
+// changes the sign too, it is told apart by the value at the end.
+fn roots(f: &mut Func, args: &[Value], loc: &Loc) -> Result<Value, CalcError> {
+    let (lo, hi) = (args[0].as_f64(), args[1].as_f64());
+    if lo >= hi || !(hi - lo).is_finite() {
+        let kind = EvalErrorKind::InvalidInterval { lo, hi };
+        return Err(EvalError::new(kind, loc.clone()).into());
+    }
+    let step = (hi - lo) / PIECES as f64;
+    let mut roots = Vec::new();
+    let mut a = lo;
+    let mut fa = at(f, a)?;
+    if fa == 0.0 {
+        roots.push(a);
+    }
+    for i in 1..=PIECES {
+        let b = if i == PIECES {
+            hi
+        } else {
+            lo + step * i as f64
+        };
+        let fb = at(f, b)?;
+        if fb == 0.0 {
+            roots.push(b);
+        } else if fa.signum() * fb.signum() < 0.0 {
+            let (x, fx) = bisect(f, (a, fa), b)?;
+            if fx.abs() <= fa.abs().max(fb.abs()) {
+                roots.push(x);
+            }
+        }
+        a = b;
+        fa = fb;
+    }
+    Ok(Value::List(roots.into_iter().map(Value::Float).collect()))
+}
+
+// Halve the bracket until it is within the tolerance. This always
+// converges, each step gains a bit.
+fn bisect(f: &mut Func, (mut a, mut fa): (f64, f64), mut b: f64) -> Result<(f64, f64), CalcError> {
+    loop {
+        let m = a + (b - a) / 2.0;
+        let fm = at(f, m)?;
+        if fm == 0.0 || b - a <= tolerance(m) || m == a || m == b {
+            return Ok((m, fm));
+        }
+        if fa.signum() == fm.signum() {
+            a = m;
+            fa = fm;
+        } else {
+            b = m;
+        }
+    }
+}
+
+// The halvings of an interval `integrate` may make, and the evaluations
+// of the expression over all the intervals.
+const MAX_DEPTH: usize = 50;
+const MAX_EVALUATIONS: usize = 100_000;
+
+// Adaptive Simpson's rule: an interval is halved until Simpson's rule on
+// the halves agrees with that on the whole. The tolerance is shared among
+// the halves, so that the error of the sum stays within it.
+fn integrate(f: &mut Func, args: &[Value], loc: &Loc) -> Result<Value, CalcError> {
+    let (a, b) = (args[0].as_f64(), args[1].as_f64());
+    if !a.is_finite() || !b.is_finite() {
+        let kind = EvalErrorKind::InvalidInterval { lo: a, hi: b };
+        return Err(EvalError::new(kind, loc.clone()).into());
+    }
+    let m = a + (b - a) / 2.0;
+    let points = [(a, at(f, a)?), (m, at(f, m)?), (b, at(f, b)?)];
+    let whole = simpson(points);
+    let eps = 1e-10 * whole.abs().max(1.0);
+    let mut evaluations = 3;
+    match adapt(f, points, whole, eps, MAX_DEPTH, &mut evaluations)? {
+        Some(v) if v.is_finite() => Ok(Value::Float(v)),
+        _ => {
+            let kind = EvalErrorKind::NoConvergence {
+                method: "adaptive simpson's rule",
+                iterations: evaluations,
+                last: whole,
+            };
+            Err(EvalError::new(kind, loc.clone()).into())
+        }
+    }
+}
+
+// Simpson's rule from the values at the ends and the middle of an interval.
+fn simpson([(a, fa), (_, fm), (b, fb)]: [(f64, f64); 3]) -> f64 {
+    (b - a) / 6.0 * (fa + 4.0 * fm + fb)
+}
+
+// The integral over the interval, or `None` if it did not settle within
+// the depth or the evaluations left.
+fn adapt(
+    f: &mut Func,
+    [a, m, b]: [(f64, f64); 3],
+    whole: f64,
+    eps: f64,
+    depth: usize,
+    evaluations: &mut usize,
+) -> Result<Option<f64>, CalcError> {
+    let (l, r) = (a.0 + (m.0 - a.0) / 2.0, m.0 + (b.0 - m.0) / 2.0);
+    let (l, r) = ((l, at(f, l)?), (r, at(f, r)?));
+    *evaluations += 2;
+    let (left, right) = (simpson([a, l, m]), simpson([m, r, b]));
+    // Richardson extrapolation of the error of the halves
+    let delta = left + right - whole;
+    if delta.abs() <= 15.0 * eps {
+        return Ok(Some(left + right + delta / 15.0));
+    }
+    if depth == 0 || *evaluations >= MAX_EVALUATIONS || !delta.is_finite() {
+        return Ok(None);
+    }
+    let left = match adapt(f, [a, l, m], left, eps / 2.0, depth - 1, evaluations)? {
+        Some(left) => left,
+        None => return Ok(None),
+    };
+    let right = adapt(f, [m, r, b], right, eps / 2.0, depth - 1, evaluations)?;
+    Ok(right.map(|right| left + right))
+}
+
+// The sum of the expression for `i` from `from` to `to`, both included.
+// The terms are added like with `+`, so the sum of ints is an int. The
+// sum of no terms is 0.
+fn sum(f: &mut Func, args: &[Value], loc: &Loc) -> Result<Value, CalcError> {
+    fold(f, args, BinOpKind::Add, Value::Int(0), loc)
+}
+
+// Like `sum`, the product of no factors is 1.
+fn product(f: &mut Func, args: &[Value], loc: &Loc) -> Result<Value, CalcError> {
+    fold(f, args, BinOpKind::Mult, Value::Int(1), loc)
+}
+
+fn fold(
+    f: &mut Func,
+    args: &[Value],
+    op: BinOpKind,
+    init: Value,
+    loc: &Loc,
+) -> Result<Value, CalcError> {
+    let op = BinOp::new(op, loc.clone());
+    let mut acc = init;
+    for i in int(&args[0])..=int(&args[1]) {
+        acc = binop(None, &op, acc, f(Value::Int(i))?, loc)?;
+    }
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calc::{eval, eval_with, exec, CalcError, EvalErrorKind, Interpreter, Loc, Value};
+
+    fn float(input: &str) -> f64 {
+        match eval(input) {
+            Ok(Value::Float(x)) => x,
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    fn floats(input: &str) -> Vec<f64> {
+        match eval(input) {
+            Ok(Value::List(xs)) => xs.iter().map(Value::as_f64).collect(),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    fn eval_error(input: &str) -> EvalErrorKind {
+        match eval(input) {
+            Err(CalcError::Eval(e)) => e.value,
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_solve() {
+        assert!((float("solve(x * x - 2, x, 1)") - 2f64.sqrt()).abs() < 1e-12);
+        assert!((float("solve(x * x - 2, x, -1)") + 2f64.sqrt()).abs() < 1e-12);
+        assert_eq!(float("solve(3x - 6, x, 0)"), 2.0);
+        assert_eq!(
+            eval_error("solve(x * x + 1, x, 0)"),
+            EvalErrorKind::ZeroDerivative(0.0)
+        );
+        match eval_error("solve(x * x + 1, x, 0.5)") {
+            EvalErrorKind::NoConvergence { iterations, .. } => assert_eq!(iterations, 100),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roots() {
+        let roots = floats("roots((x - 1)(x + 2)(x - 0.3), x, -5, 5)");
+        let expected = [-2.0, 0.3, 1.0];
+        assert_eq!(roots.len(), 3);
+        for (x, e) in roots.iter().zip(&expected) {
+            assert!((x - e).abs() < 1e-9, "{} != {}", x, e);
+        }
+        // the pole is not a root
+        assert_eq!(floats("roots(1 / (x - 1/3), x, 0, 1)"), Vec::<f64>::new());
+        assert_eq!(
+            eval_error("roots(x, x, 1, 0)"),
+            EvalErrorKind::InvalidInterval { lo: 1.0, hi: 0.0 }
+        );
+    }
+
+    #[test]
+    fn test_forms_scope() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "x = 10").unwrap();
+        exec(&mut interp, "a = 4").unwrap();
+        // the bound `x` shadows the variable
+        let v = eval_with(&mut interp, "solve(x * x - a, x, 1) + x").unwrap();
+        assert!((v.as_f64() - 12.0).abs() < 1e-12);
+        exec(&mut interp, "sqrt(a) = solve(x * x - a, x, a)").unwrap();
+        let v = eval_with(&mut interp, "sqrt(9)").unwrap();
+        assert!((v.as_f64() - 3.0).abs() < 1e-12);
+        // the variable must be a name and the expression a real number
+        assert!(matches!(
+            eval_with(&mut interp, "solve(x - 1, 2, 0)"),
+            Err(CalcError::Type(_))
+        ));
+        assert!(matches!(
+            eval_with(&mut interp, "solve(x * 1i, x, 0)"),
+            Err(CalcError::Type(_))
+        ));
+        assert!(matches!(
+            eval_with(&mut interp, "solve(x - 1, x)"),
+            Err(CalcError::Type(_))
+        ));
+        assert!(matches!(
+            eval_with(&mut interp, "solve(y - 1, x, 0)"),
+            Err(CalcError::Type(_))
+        ));
+        // errors in the expression are reported at the call and where they
+        // occur in the expression
+        match eval_with(&mut interp, "1 + solve(1 // 0 + x, x, 0)") {
+            Err(CalcError::Eval(e)) => {
+                assert_eq!(e.loc, Loc(4, 27));
+                match e.value {
+                    EvalErrorKind::InForm { form, error } => {
+                        assert_eq!(form, "solve");
+                        assert_eq!(error.value, EvalErrorKind::DivisionByZero);
+                        assert_eq!(error.loc, Loc(12, 14));
+                    }
+                    other => panic!("unexpected: {:?}", other),
+                }
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_integrate() {
+        assert!((float("integrate(x * x, x, 0, 3)") - 9.0).abs() < 1e-9);
+        assert!((float("integrate(1 / x, x, 1, 2)") - std::f64::consts::LN_2).abs() < 1e-9);
+        assert!((float("integrate(x, x, 1, 0)") + 0.5).abs() < 1e-12);
+        assert_eq!(float("integrate(x, x, 2, 2)"), 0.0);
+        // the integral of a pole does not settle
+        match eval_error("integrate(1 / (x - 1/3), x, 0, 1)") {
+            EvalErrorKind::NoConvergence { method, .. } => {
+                assert_eq!(method, "adaptive simpson's rule")
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        match eval_error("integrate(1 / x, x, -1, 1)") {
+            EvalErrorKind::InForm { form, error } => {
+                assert_eq!(form, "integrate");
+                assert_eq!(error.value, EvalErrorKind::DivisionByZero);
+                assert_eq!(error.loc, Loc(12, 13));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sum() {
+        assert_eq!(eval("sum(i, i, 1, 100)"), Ok(Value::Int(5050)));
+        assert_eq!(eval("sum(i * i, i, -2, 2)"), Ok(Value::Int(10)));
+        assert_eq!(eval("sum(1 / i, i, 1, 2)"), Ok(Value::Float(1.5)));
+        assert_eq!(eval("sum(i * 1i, i, 1, 2)"), eval("3i"));
+        assert_eq!(eval("sum(i, i, 1, 0)"), Ok(Value::Int(0)));
+        assert_eq!(eval("product(i, i, 1, 5)"), Ok(Value::Int(120)));
+        assert_eq!(eval("product(i, i, 1, 0)"), Ok(Value::Int(1)));
+        assert_eq!(
+            eval("sum(sum(i * j, j, 1, i), i, 1, 3)"),
+            Ok(Value::Int(1 + 2 + 4 + 3 + 6 + 9))
+        );
+        // the builtin takes a list
+        assert_eq!(eval("sum([1, 2, 3])"), Ok(Value::Int(6)));
+        assert_eq!(eval_error("product(i, i, 1, 30)"), EvalErrorKind::Overflow);
+        assert!(matches!(eval("sum(i, i, 1.5, 2)"), Err(CalcError::Type(_))));
+        assert!(matches!(eval("sum([i], i, 1, 2)"), Err(CalcError::Type(_))));
+        assert!(matches!(eval("product(i, i, 1)"), Err(CalcError::Type(_))));
+        match eval_error("sum(10 // (3 - i), i, 1, 5)") {
+            EvalErrorKind::InForm { form, error } => {
+                assert_eq!(form, "sum");
+                assert_eq!(error.value, EvalErrorKind::DivisionByZero);
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use super::{eval, Annot, Ast, AstKind, BinOp, BinOpKind, CalcError, EvalErrorKind, Loc};
+use super::{UniOp, UniOpKind, Value};
+use crate::{rpn, util};
+use rand::Rng;
+use rand_pcg::Pcg64Mcg;
+
+// Generates well-formed expressions in the subset of calc that `rpn` also
+// understands: number literals, negation and `+ - * /`.
+pub struct ExprGen {
+    rng: Pcg64Mcg,
+    max_depth: usize,
+}
+
+impl ExprGen {
+    pub fn new(seed: u64, max_depth: usize) -> Self {
+        ExprGen {
+            rng: util::new_rng(seed),
+            max_depth,
+        }
+    }
+
+    pub fn gen(&mut self) -> Ast {
+        self.gen_depth(self.max_depth)
+    }
+
+    fn gen_depth(&mut self, depth: usize) -> Ast {
+        // the locations are not meaningful, the tree is printed and parsed
+        let loc = Loc(0, 0);
+        if depth == 0 || self.rng.gen_bool(0.2) {
+            return if self.rng.gen_bool(0.7) {
+                Ast::new(AstKind::Int(self.rng.gen_range(0, 10)), loc)
+            } else {
+                // quarters are exact in binary, as in `2.25`
+                let f = self.rng.gen_range(0, 100) as f64 / 4.0;
+                Ast::new(AstKind::Float(f), loc)
+            };
+        }
+        if self.rng.gen_bool(0.1) {
+            let e = Box::new(self.gen_depth(depth - 1));
+            let op = UniOp::new(UniOpKind::Minus, loc.clone());
+            return Ast::new(AstKind::UniOp { op, e }, loc);
+        }
+        let ops = [
+            BinOpKind::Add,
+            BinOpKind::Sub,
+            BinOpKind::Mult,
+            BinOpKind::Div,
+        ];
+        let op = BinOp::new(ops[self.rng.gen_range(0, ops.len())], loc.clone());
+        let l = Box::new(self.gen_depth(depth - 1));
+        let r = Box::new(self.gen_depth(depth - 1));
+        Ast::new(AstKind::BinOp { op, l, r }, loc)
+    }
+}
+
+impl Iterator for ExprGen {
+    type Item = Ast;
+
+    fn next(&mut self) -> Option<Ast> {
+        Some(self.gen())
+    }
+}
+
+// The expression in the notation of `rpn::solve`. Negation becomes `0 x -`.
+pub fn to_rpn(ast: &Ast) -> Option<String> {
+    match &ast.value {
+        AstKind::Int(n) => Some(n.to_string()),
+        AstKind::Float(f) => Some(format!("{:?}", f)),
+        AstKind::UniOp { op, e } => match op.value {
+            UniOpKind::Plus => to_rpn(e),
+            UniOpKind::Minus => Some(format!("0 {} -", to_rpn(e)?)),
+            UniOpKind::BitNot => None,
+        },
+        AstKind::BinOp { op, l, r } => {
+            let op = match op.value {
+                BinOpKind::Add => "+",
+                BinOpKind::Sub => "-",
+                BinOpKind::Mult => "*",
+                BinOpKind::Div => "/",
+                _ => return None,
+            };
+            Some(format!("{} {} {}", to_rpn(l)?, to_rpn(r)?, op))
+        }
+        _ => None,
+    }
+}
+
+// An expression which evaluates differently in calc and in rpn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub input: String,
+    pub rpn: String,
+    pub calc: Result<Value, CalcError>,
+    pub expected: f64,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let calc = match &self.calc {
+            Ok(v) => v.to_string(),
+            Err(e) => e.to_string(),
+        };
+        write!(
+            f,
+            "calc: {}\n  => {}\nrpn:  {}\n  => {}",
+            self.input, calc, self.rpn, self.expected
+        )
+    }
+}
+
+// Evaluate the expression both ways. `None` if they agree, or if calc
+// reports an overflow or a division by zero, which rpn lets through as
+// infinities or inexact floats.
+pub fn compare(ast: &Ast) -> Option<Divergence> {
+    let input = ast.to_string();
+    let rpn = to_rpn(ast).expect("in the subset of rpn");
+    let expected = rpn::solve(&rpn);
+    let calc = eval(&input);
+    let agree = match &calc {
+        Ok(v) => {
+            let x = v.as_f64();
+            expected.is_finite()
+                && (x - expected).abs() <= 1e-9 * x.abs().max(expected.abs()).max(1.0)
+        }
+        Err(CalcError::Eval(e)) => matches!(
+            e.value,
+            EvalErrorKind::DivisionByZero | EvalErrorKind::Overflow
+        ),
+        Err(_) => false,
+    };
+    if agree {
+        None
+    } else {
+        Some(Divergence {
+            input,
+            rpn,
+            calc,
+            expected,
+        })
+    }
+}
+
+// Evaluate `n` generated expressions and return the first divergence,
+// minimised.
+pub fn fuzz(seed: u64, n: usize) -> Result<(), Divergence> {
+    for ast in ExprGen::new(seed, 6).take(n) {
+        if compare(&ast).is_some() {
+            let ast = minimise(ast, |e| compare(e).is_some());
+            return Err(compare(&ast).expect("still diverges"));
+        }
+    }
+    Ok(())
+}
+
+// Shrink the expression while `fails` holds, by replacing a node with one
+// of its operands or with a smaller literal.
+pub fn minimise(mut ast: Ast, fails: impl Fn(&Ast) -> bool) -> Ast {
+    'shrink: loop {
+        for candidate in shrink(&ast) {
+            if fails(&candidate) {
+                ast = candidate;
+                continue 'shrink;
+            }
+        }
+        return ast;
+    }
+}
+
+// The smaller trees, the smallest first.
+fn shrink(ast: &Ast) -> Vec<Ast> {
+    let loc = ast.loc.clone();
+    let lit = |v| Annot::new(v, loc.clone());
+    let mut smaller = Vec::new();
+    match &ast.value {
+        AstKind::Int(n) if *n > 1 => {
+            smaller.extend(vec![lit(AstKind::Int(0)), lit(AstKind::Int(1))])
+        }
+        AstKind::Float(f) => smaller.push(lit(AstKind::Int(f.trunc() as u64))),
+        AstKind::UniOp { op, e } => {
+            smaller.push((**e).clone());
+            for e in shrink(e) {
+                let e = Box::new(e);
+                smaller.push(lit(AstKind::UniOp { op: op.clone(), e }));
+            }
+        }
+        AstKind::BinOp { op, l, r } => {
+            smaller.push((**l).clone());
+            smaller.push((**r).clone());
+            for l in shrink(l) {
+                let (l, r) = (Box::new(l), r.clone());
+                smaller.push(lit(AstKind::BinOp {
+                    op: op.clone(),
+                    l,
+                    r,
+                }));
+            }
+            for r in shrink(r) {
+                let (l, r) = (l.clone(), Box::new(r));
+                smaller.push(lit(AstKind::BinOp {
+                    op: op.clone(),
+                    l,
+                    r,
+                }));
+            }
+        }
+        _ => {}
+    }
+    smaller
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fuzz, minimise, to_rpn, ExprGen};
+    use crate::calc::{lex, parse, AstKind, BinOpKind};
+
+    #[test]
+    fn test_gen() {
+        let a: Vec<_> = ExprGen::new(7, 4).take(20).map(|e| e.to_string()).collect();
+        let b: Vec<_> = ExprGen::new(7, 4).take(20).map(|e| e.to_string()).collect();
+        assert_eq!(a, b);
+        for input in &a {
+            assert!(parse(lex(input).unwrap()).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_to_rpn() {
+        let ast = parse(lex("-(1 + 2.5) * 3 / 4").unwrap()).unwrap();
+        assert_eq!(to_rpn(&ast).unwrap(), "0 1 2.5 + - 3 * 4 /");
+        assert_eq!(to_rpn(&parse(lex("7 // 2").unwrap()).unwrap()), None);
+    }
+
+    #[test]
+    fn test_fuzz() {
+        for seed in 0..4 {
+            if let Err(d) = fuzz(seed, 500) {
+                panic!("{}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimise() {
+        // any division "fails"
+        fn has_div(e: &crate::calc::Ast) -> bool {
+            match &e.value {
+                AstKind::BinOp { op, .. } if op.value == BinOpKind::Div => true,
+                _ => e.children().into_iter().any(has_div),
+            }
+        }
+        let ast = parse(lex("(3 + 4 * 5) - (6 / (7 - 8.5)) * 9").unwrap()).unwrap();
+        let small = minimise(ast, has_div);
+        assert_eq!(small.to_string(), "0 / 0");
+    }
+}
@@ -0,0 +1,195 @@
+use super::{builtins, lex, Loc, Token, TokenKind};
+
+const RESET: &str = "\x1b[0m";
+const NUMBER: &str = "\x1b[36m";
+const OPERATOR: &str = "\x1b[33m";
+const BUILTIN: &str = "\x1b[35m";
+const IDENT: &str = "\x1b[32m";
+const COMMENT: &str = "\x1b[90m";
+const MATCHED: &str = "\x1b[1;4m";
+const ERROR: &str = "\x1b[1;31m";
+
+fn style(tok: &Token) -> Option<&'static str> {
+    match &tok.value {
+        TokenKind::Number(_) | TokenKind::Float(_) | TokenKind::Imag(_) => Some(NUMBER),
+        TokenKind::Ident(name) if name == "xor" => Some(OPERATOR),
+        TokenKind::Ident(name) if builtins::is_builtin(name) => Some(BUILTIN),
+        TokenKind::Ident(_) => Some(IDENT),
+        TokenKind::LParen
+        | TokenKind::RParen
+        | TokenKind::LBracket
+        | TokenKind::RBracket
+        | TokenKind::Comma => None,
+        _ => Some(OPERATOR),
+    }
+}
+
+// For each bracket `Some` index of its match, or `Some(None)` if it is
+// unmatched. Other tokens are `None`.
+fn match_brackets(tokens: &[Token]) -> Vec<Option<Option<usize>>> {
+    let mut matches = vec![None; tokens.len()];
+    let mut open = Vec::new();
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.value {
+            TokenKind::LParen | TokenKind::LBracket => {
+                open.push(i);
+                matches[i] = Some(None);
+            }
+            TokenKind::RParen | TokenKind::RBracket => {
+                let close = if tok.value == TokenKind::RParen {
+                    TokenKind::LParen
+                } else {
+                    TokenKind::LBracket
+                };
+                match open.last() {
+                    Some(&j) if tokens[j].value == close => {
+                        open.pop();
+                        matches[i] = Some(Some(j));
+                        matches[j] = Some(Some(i));
+                    }
+                    _ => matches[i] = Some(None),
+                }
+            }
+            _ => {}
+        }
+    }
+    matches
+}
+
+// Colourise the input with ANSI escapes from its tokens. Unmatched brackets
+// are red; if the cursor (a byte offset) is on a bracket or right after a
+// closing one, the bracket and its match are underlined. An input which
+// does not lex is returned with the offending character in red.
+pub fn highlight(input: &str, cursor: Option<usize>) -> String {
+    let tokens = match lex(input) {
+        Ok(tokens) => tokens,
+        Err(e) => return mark(input, &e.loc, ERROR),
+    };
+    let matches = match_brackets(&tokens);
+    let at_cursor = cursor.and_then(|c| {
+        let on = tokens.iter().position(|t| t.loc.0 == c);
+        let after = tokens.iter().position(|t| {
+            t.loc.1 == c && matches!(t.value, TokenKind::RParen | TokenKind::RBracket)
+        });
+        let i = on.filter(|&i| matches[i].is_some()).or(after)?;
+        Some((i, matches[i]??))
+    });
+
+    let mut s = String::new();
+    let mut pos = 0;
+    for (i, tok) in tokens.iter().enumerate() {
+        push_gap(&mut s, &input[pos..tok.loc.0]);
+        let style = match (matches[i], at_cursor) {
+            (Some(None), _) => Some(ERROR),
+            (_, Some((a, b))) if i == a || i == b => Some(MATCHED),
+            _ => style(tok),
+        };
+        let text = &input[tok.loc.0..tok.loc.1];
+        match style {
+            Some(style) => s.push_str(&format!("{}{}{}", style, text, RESET)),
+            None => s.push_str(text),
+        }
+        pos = tok.loc.1;
+    }
+    push_gap(&mut s, &input[pos..]);
+    s
+}
+
+// Spaces between tokens, which may contain a comment.
+fn push_gap(s: &mut String, gap: &str) {
+    match gap.find('#') {
+        Some(i) => s.push_str(&format!("{}{}{}{}", &gap[..i], COMMENT, &gap[i..], RESET)),
+        None => s.push_str(gap),
+    }
+}
+
+fn mark(input: &str, loc: &Loc, style: &str) -> String {
+    let mut start = loc.0.min(input.len());
+    while !input.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = loc.1.max(start).min(input.len());
+    while !input.is_char_boundary(end) {
+        end += 1;
+    }
+    format!(
+        "{}{}{}{}{}",
+        &input[..start],
+        style,
+        &input[start..end],
+        RESET,
+        &input[end..]
+    )
+}
+
+// Same as `annotate`, with the input highlighted and the marks in red.
+pub fn annotate_highlighted(input: &str, loc: &Loc) -> String {
+    let start = loc.0.min(input.len());
+    let width = loc.1.max(start + 1) - start;
+    format!(
+        "{}\n{}{}{}{}",
+        highlight(input, None),
+        " ".repeat(start),
+        ERROR,
+        "^".repeat(width),
+        RESET
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::highlight;
+
+    // Show the escapes as `<n>` to keep the expectations readable.
+    fn show(s: &str) -> String {
+        let s = s.replace("\x1b[0m", "</>");
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.next();
+                let code: String = chars.by_ref().take_while(|&c| c != 'm').collect();
+                out.push_str(&format!("<{}>", code));
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            show(&highlight("sum([x, 2]) * 1.5 # total", None)),
+            "<35>sum</>([<32>x</>, <36>2</>]) <33>*</> <36>1.5</> <90># total</>"
+        );
+        assert_eq!(
+            show(&highlight("1 xor 2", None)),
+            "<36>1</> <33>xor</> <36>2</>"
+        );
+    }
+
+    #[test]
+    fn test_highlight_brackets() {
+        assert_eq!(
+            show(&highlight("(1]", None)),
+            "<1;31>(</><36>1</><1;31>]</>"
+        );
+        assert_eq!(
+            show(&highlight("((1))", Some(1))),
+            "(<1;4>(</><36>1</><1;4>)</>)"
+        );
+        assert_eq!(
+            show(&highlight("((1))", Some(5))),
+            "<1;4>(</>(<36>1</>)<1;4>)</>"
+        );
+        assert_eq!(show(&highlight("((1))", Some(2))), "((<36>1</>))");
+    }
+
+    #[test]
+    fn test_highlight_lex_error() {
+        assert_eq!(show(&highlight("1 $ 2", None)), "1 <1;31>$</> 2");
+        assert_eq!(show(&highlight("20°", None)), "20<1;31>°</>");
+        assert_eq!(show(&highlight("£5", None)), "<1;31>£</>5");
+    }
+}
@@ -0,0 +1,956 @@
+use super::builtins;
+use super::forms;
+use super::lambda::{self, Lambda};
+use super::limits::{measure, LimitError, LimitErrorKind, Limits};
+use super::matrix;
+use super::operators::OperatorTable;
+use super::parser::implicit_mult;
+use super::{
+    Annot, Ast, AstKind, BinOp, BinOpKind, CalcError, Complex, Ident, Loc, Type, UniOp, UniOpKind,
+    WordWidth,
+};
+use crate::format::Formatter;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Complex(Complex),
+    List(Vec<Value>),
+    Lambda(Rc<Lambda>),
+}
+
+impl Value {
+    pub(super) fn as_f64(&self) -> f64 {
+        match self {
+            Value::Int(n) => *n as f64,
+            Value::Float(f) => *f,
+            Value::Complex(_) | Value::List(_) | Value::Lambda(_) => {
+                unreachable!("rejected by the type checker")
+            }
+        }
+    }
+
+    pub fn type_of(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Complex(_) => Type::Complex,
+            Value::List(xs) => {
+                let t = xs
+                    .iter()
+                    .map(Value::type_of)
+                    .fold(Type::Any, |t, et| t.promote(&et).unwrap_or(t));
+                Type::List(Box::new(t))
+            }
+            Value::Lambda(f) => Type::Func(Rc::new(f.type_of())),
+        }
+    }
+
+    pub(super) fn as_complex(&self) -> Complex {
+        match self {
+            Value::Complex(z) => *z,
+            v => Complex::new(v.as_f64(), 0.0),
+        }
+    }
+
+    // Build a list, promoting the elements to their common number type so
+    // that the value agrees with the checked type, e.g. `list[float]`.
+    pub(super) fn list(elems: Vec<Value>) -> Value {
+        if elems.iter().any(|e| matches!(e, Value::Complex(_))) {
+            Value::List(
+                elems
+                    .iter()
+                    .map(|e| Value::Complex(e.as_complex()))
+                    .collect(),
+            )
+        } else if elems.iter().any(|e| matches!(e, Value::Float(_))) {
+            Value::List(
+                elems
+                    .into_iter()
+                    .map(|e| match e {
+                        Value::Int(n) => Value::Float(n as f64),
+                        e => e,
+                    })
+                    .collect(),
+            )
+        } else {
+            Value::List(elems)
+        }
+    }
+
+    // The value written with the notation and the marks of `fmt`. A list
+    // is separated by `;` when the decimal mark is a comma.
+    pub fn format(&self, fmt: &Formatter) -> String {
+        match self {
+            Value::Int(n) => fmt.int(*n),
+            Value::Float(x) => fmt.float(*x),
+            Value::Complex(z) if z.re == 0.0 => format!("{}i", fmt.real(z.im)),
+            Value::Complex(z) => {
+                let sign = if z.im.is_sign_negative() { '-' } else { '+' };
+                format!("{}{}{}i", fmt.real(z.re), sign, fmt.real(z.im.abs()))
+            }
+            Value::List(xs) => {
+                let sep = if fmt.decimal == ',' { "; " } else { ", " };
+                let xs: Vec<_> = xs.iter().map(|x| x.format(fmt)).collect();
+                format!("[{}]", xs.join(sep))
+            }
+            Value::Lambda(f) => f.to_string(),
+        }
+    }
+
+    // Like `to_string`, but complex numbers are rendered in polar form.
+    pub fn to_polar_string(&self) -> String {
+        match self {
+            Value::Complex(z) => z.to_polar_string(),
+            Value::List(xs) => {
+                let xs: Vec<_> = xs.iter().map(Value::to_polar_string).collect();
+                format!("[{}]", xs.join(", "))
+            }
+            v => v.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            // `{:?}` keeps the decimal point, so `3.0` is not confused with `3`
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Complex(z) => write!(f, "{}", z),
+            Value::List(xs) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            }
+            Value::Lambda(l) => write!(f, "{}", l),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalErrorKind {
+    DivisionByZero,
+    Overflow,
+    EmptyList,
+    IndexOutOfRange {
+        index: i64,
+        len: usize,
+    },
+    InvalidShift(i64),
+    NegativeFactorial(i64),
+    // the last `x` of a method which did not reach the tolerance
+    NoConvergence {
+        method: &'static str,
+        iterations: usize,
+        last: f64,
+    },
+    ZeroDerivative(f64),
+    // an error in the expression of a form like `sum(1 / i, i, 1, 10)`
+    InForm {
+        form: &'static str,
+        error: Box<EvalError>,
+    },
+    InvalidInterval {
+        lo: f64,
+        hi: f64,
+    },
+    // the lengths of the rows or the columns which do not fit
+    DimensionMismatch {
+        left: usize,
+        right: usize,
+    },
+    RaggedMatrix {
+        expected: usize,
+        found: usize,
+    },
+    NotSquare {
+        rows: usize,
+        cols: usize,
+    },
+    SingularMatrix,
+    UnboundVariable(String),
+    UnsupportedInIntervalMode,
+    // lambdas are only evaluated by the interpreter
+    UnsupportedInCompiled,
+    // a limit reached while evaluating, reported as `CalcError::Limit`
+    Limit(LimitErrorKind),
+}
+
+pub type EvalError = Annot<EvalErrorKind>;
+
+impl EvalError {
+    fn division_by_zero(loc: Loc) -> Self {
+        Self::new(EvalErrorKind::DivisionByZero, loc)
+    }
+    fn overflow(loc: Loc) -> Self {
+        Self::new(EvalErrorKind::Overflow, loc)
+    }
+    fn invalid_shift(amount: i64, loc: Loc) -> Self {
+        Self::new(EvalErrorKind::InvalidShift(amount), loc)
+    }
+    pub(super) fn unbound_variable(name: &str, loc: Loc) -> Self {
+        Self::new(EvalErrorKind::UnboundVariable(name.to_string()), loc)
+    }
+    fn index_out_of_range(index: i64, len: usize, loc: Loc) -> Self {
+        Self::new(EvalErrorKind::IndexOutOfRange { index, len }, loc)
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let loc = &self.loc;
+        match &self.value {
+            EvalErrorKind::DivisionByZero => write!(f, "{}: division by zero", loc),
+            EvalErrorKind::Overflow => write!(f, "{}: integer overflow", loc),
+            EvalErrorKind::EmptyList => write!(f, "{}: empty list", loc),
+            EvalErrorKind::IndexOutOfRange { index, len } => write!(
+                f,
+                "{}: index {} is out of range for a list of length {}",
+                loc, index, len
+            ),
+            EvalErrorKind::InvalidShift(n) => write!(f, "{}: invalid shift amount {}", loc, n),
+            EvalErrorKind::NegativeFactorial(n) => {
+                write!(f, "{}: factorial of negative number {}", loc, n)
+            }
+            EvalErrorKind::NoConvergence {
+                method,
+                iterations,
+                last,
+            } => write!(
+                f,
+                "{}: {} did not converge in {} iterations, the last estimate was {}",
+                loc, method, iterations, last
+            ),
+            EvalErrorKind::ZeroDerivative(x) => {
+                write!(f, "{}: the derivative is zero at x = {}", loc, x)
+            }
+            EvalErrorKind::InForm { form, error } => write!(f, "{}: in {}: {}", loc, form, error),
+            EvalErrorKind::InvalidInterval { lo, hi } => {
+                write!(f, "{}: invalid interval from {} to {}", loc, lo, hi)
+            }
+            EvalErrorKind::DimensionMismatch { left, right } => write!(
+                f,
+                "{}: dimensions do not match: {} and {}",
+                loc, left, right
+            ),
+            EvalErrorKind::RaggedMatrix { expected, found } => write!(
+                f,
+                "{}: matrix has rows of length {} and {}",
+                loc, expected, found
+            ),
+            EvalErrorKind::NotSquare { rows, cols } => {
+                write!(f, "{}: matrix of {}x{} is not square", loc, rows, cols)
+            }
+            EvalErrorKind::SingularMatrix => write!(f, "{}: matrix is singular", loc),
+            EvalErrorKind::UnboundVariable(name) => {
+                write!(f, "{}: unbound variable '{}'", loc, name)
+            }
+            EvalErrorKind::UnsupportedInIntervalMode => {
+                write!(f, "{}: not supported in interval mode", loc)
+            }
+            EvalErrorKind::UnsupportedInCompiled => {
+                write!(f, "{}: not supported in compiled expressions", loc)
+            }
+            EvalErrorKind::Limit(kind) => {
+                write!(f, "{}", LimitError::new(kind.clone(), loc.clone()))
+            }
+        }
+    }
+}
+
+type EvalResult = Result<Value, EvalError>;
+
+// A user-defined function, e.g. `f(x, y) = x * y`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    params: Vec<Ident>,
+    body: Ast,
+    // the size of the body with the calls inlined, see `limits::measure`
+    depth: usize,
+    steps: u64,
+}
+
+impl Function {
+    pub fn params(&self) -> &[Ident] {
+        &self.params
+    }
+
+    pub fn body(&self) -> &Ast {
+        &self.body
+    }
+
+    pub(super) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub(super) fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+// Variable bindings and user-defined functions. They live in separate
+// namespaces, so `f` and `f(x)` may both be defined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Env {
+    vars: HashMap<String, Value>,
+    funcs: HashMap<String, Rc<Function>>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Env {
+            vars: HashMap::new(),
+            funcs: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.vars.remove(name)
+    }
+
+    pub fn get_func(&self, name: &str) -> Option<&Function> {
+        self.funcs.get(name).map(|f| &**f)
+    }
+
+    // The body is not checked here, see `check_stmt`.
+    pub fn define(&mut self, name: &str, params: Vec<Ident>, body: Ast) {
+        let (depth, steps) = measure(&body, self);
+        let f = Function {
+            params,
+            body,
+            depth,
+            steps,
+        };
+        self.funcs.insert(name.to_string(), Rc::new(f));
+    }
+
+    // Variables in alphabetical order.
+    pub fn vars(&self) -> Vec<(&str, &Value)> {
+        let mut vars: Vec<_> = self.vars.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        vars.sort_by_key(|(k, _)| *k);
+        vars
+    }
+
+    // Functions in alphabetical order.
+    pub fn funcs(&self) -> Vec<(&str, &Function)> {
+        let mut funcs: Vec<_> = self.funcs.iter().map(|(k, f)| (k.as_str(), &**f)).collect();
+        funcs.sort_by_key(|(k, _)| *k);
+        funcs
+    }
+}
+
+pub struct Interpreter {
+    // Programmer mode: integers wrap around at this width instead of
+    // reporting an overflow.
+    word: Option<WordWidth>,
+    env: Env,
+    // The arguments of the user-defined functions being called.
+    frames: Vec<HashMap<String, Value>>,
+    // The reductions made so far, recorded only while tracing.
+    steps: Option<Vec<(Loc, Value)>>,
+    limits: Limits,
+    operators: OperatorTable,
+    // The nodes evaluated and the deadline of the current `eval`.
+    ticks: u64,
+    deadline: Option<Instant>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            word: None,
+            env: Env::new(),
+            frames: Vec::new(),
+            steps: None,
+            limits: Limits::default(),
+            operators: OperatorTable::new(),
+            ticks: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn word_width(&self) -> Option<WordWidth> {
+        self.word
+    }
+
+    pub fn set_word_width(&mut self, word: Option<WordWidth>) {
+        self.word = word;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Applied by `exec` and `eval_with`, and to every `eval`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Used by `exec`, `eval_with` and `trace` to lex and parse the input.
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    pub fn operators_mut(&mut self) -> &mut OperatorTable {
+        &mut self.operators
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    // Record every variable, operator, call and index evaluated until
+    // `end_trace`. Reductions inside user-defined functions are not recorded,
+    // the call is one step.
+    pub(super) fn begin_trace(&mut self) {
+        self.steps = Some(Vec::new());
+    }
+
+    pub(super) fn end_trace(&mut self) -> Vec<(Loc, Value)> {
+        self.steps.take().unwrap_or_default()
+    }
+
+    pub fn eval(&mut self, expr: &Ast) -> EvalResult {
+        self.ticks = 0;
+        self.deadline = self.limits.timeout.map(|t| Instant::now() + t);
+        self.eval_ast(expr)
+    }
+
+    fn eval_ast(&mut self, expr: &Ast) -> EvalResult {
+        self.tick(&expr.loc)?;
+        let v = self.eval_node(expr)?;
+        if let (Value::List(xs), Some(max)) = (&v, self.limits.max_list_len) {
+            if xs.len() > max {
+                let kind = LimitErrorKind::ListTooLong { len: xs.len(), max };
+                return Err(EvalError::new(EvalErrorKind::Limit(kind), expr.loc.clone()));
+            }
+        }
+        if let (Some(steps), true) = (&mut self.steps, self.frames.is_empty()) {
+            match expr.value {
+                AstKind::Var(_)
+                | AstKind::Index { .. }
+                | AstKind::Call { .. }
+                | AstKind::UniOp { .. }
+                | AstKind::BinOp { .. } => steps.push((expr.loc.clone(), v.clone())),
+                _ => {}
+            }
+        }
+        Ok(v)
+    }
+
+    // Count a step, and look at the clock every so often.
+    fn tick(&mut self, loc: &Loc) -> Result<(), EvalError> {
+        self.charge(1, loc)
+    }
+
+    // Count the steps of work done in one go, like the multiply-adds of a
+    // matrix product, before it is started.
+    fn charge(&mut self, n: u64, loc: &Loc) -> Result<(), EvalError> {
+        let ticks = self.ticks.saturating_add(n);
+        let look = n > 1 || self.ticks.is_multiple_of(256);
+        let kind = match (self.limits.max_steps, self.deadline) {
+            (Some(max), _) if ticks > max => LimitErrorKind::TooManySteps(max),
+            (_, Some(deadline)) if look && Instant::now() >= deadline => {
+                LimitErrorKind::Timeout(self.limits.timeout.unwrap())
+            }
+            _ => {
+                self.ticks = ticks;
+                return Ok(());
+            }
+        };
+        Err(EvalError::new(EvalErrorKind::Limit(kind), loc.clone()))
+    }
+
+    fn eval_node(&mut self, expr: &Ast) -> EvalResult {
+        match &expr.value {
+            AstKind::Int(n) => int(self.word, *n, &expr.loc),
+            AstKind::Float(f) => Ok(Value::Float(*f)),
+            AstKind::Imag(f) => Ok(Value::Complex(Complex::new(0.0, *f))),
+            AstKind::Var(name) => {
+                let v = self.variable(name).expect("checked by the type checker");
+                Ok(v.clone())
+            }
+            AstKind::PlusMinus { .. } => unreachable!("rejected by the type checker"),
+            AstKind::List(elems) => {
+                let elems = elems
+                    .iter()
+                    .map(|e| self.eval_ast(e))
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::list(elems))
+            }
+            AstKind::Index { e, index: i } => {
+                let xs = self.eval_ast(e)?;
+                let n = self.eval_ast(i)?;
+                index(xs, n, &i.loc)
+            }
+            AstKind::Call { func, args } if forms::lookup(&func.value, args.len()).is_some() => {
+                let form = forms::lookup(&func.value, args.len()).unwrap();
+                let x = forms::bound_var(args).expect("checked by the type checker");
+                let params = args[2..]
+                    .iter()
+                    .map(|e| self.eval_ast(e).map(|v| unwrap_word(self.word, v)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // the expression sees the variables of the caller and `x`
+                let frame = self.frames.last().cloned().unwrap_or_default();
+                self.frames.push(frame);
+                let mut f = |v: Value| {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.insert(x.to_string(), v);
+                    match self.eval_ast(&args[0]) {
+                        Ok(v) => Ok(unwrap_word(self.word, v)),
+                        Err(e) => Err(CalcError::Eval(forms::inner_error(form, e, &expr.loc))),
+                    }
+                };
+                let v = (form.eval)(&mut f, &params, &expr.loc);
+                self.frames.pop();
+                v.map_err(|e| match e {
+                    CalcError::Eval(e) => e,
+                    _ => unreachable!("the interpreter fails with eval errors"),
+                })
+            }
+            AstKind::Call { func, args } => {
+                let args = args
+                    .iter()
+                    .map(|e| self.eval_ast(e))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(hof) = lambda::lookup(&func.value) {
+                    let loc = &expr.loc;
+                    return (hof.eval)(args, &mut |f, args| self.apply(f, args, loc));
+                }
+                if let Some(builtin) = builtins::lookup(&func.value) {
+                    self.charge(matrix::builtin_steps(builtin.name, &args), &expr.loc)?;
+                    return (builtin.eval)(&args)
+                        .map_err(|kind| EvalError::new(kind, expr.loc.clone()));
+                }
+                if let Some(Value::Lambda(f)) = self.variable(&func.value) {
+                    let f = Rc::clone(f);
+                    return self.apply(&f, args, &expr.loc);
+                }
+                let f = self.env.funcs.get(&func.value);
+                let f = Rc::clone(f.expect("checked by the type checker"));
+                let frame = f.params.iter().map(|p| p.value.clone()).zip(args);
+                self.frames.push(frame.collect());
+                let v = self.eval_ast(&f.body);
+                self.frames.pop();
+                // the body is on another line, so report errors at the call
+                v.map_err(|e| EvalError::new(e.value, expr.loc.clone()))
+            }
+            AstKind::Lambda { params, body } => {
+                // capture by value, see `lambda.rs`
+                let mut captured: Vec<(String, Value)> = Vec::new();
+                for free in lambda::free_names(params, body) {
+                    let seen = captured.iter().any(|(name, _)| name == free.name);
+                    match self.variable(free.name) {
+                        Some(v) if !seen && (!free.call || matches!(v, Value::Lambda(_))) => {
+                            captured.push((free.name.to_string(), v.clone()))
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Value::Lambda(Rc::new(Lambda {
+                    params: params.iter().map(|p| p.value.clone()).collect(),
+                    body: Rc::new((**body).clone()),
+                    captured,
+                })))
+            }
+            AstKind::UniOp { op, e } => {
+                let e = self.eval_ast(e)?;
+                uniop(self.word, op, e, &expr.loc)
+            }
+            AstKind::BinOp { op, l, r } => {
+                let l = self.eval_ast(l)?;
+                let r = self.eval_ast(r)?;
+                match l {
+                    // `compose(f, g)(1)` applies the lambda, see `implicit_mult`
+                    Value::Lambda(f) if implicit_mult(op) => self.apply(&f, vec![r], &expr.loc),
+                    l => {
+                        if op.value == BinOpKind::MatMul {
+                            self.charge(matrix::matmul_steps(&l, &r), &expr.loc)?;
+                        }
+                        binop(self.word, op, l, r, &expr.loc)
+                    }
+                }
+            }
+        }
+    }
+
+    // A local of the function being called, or a global.
+    fn variable(&self, name: &str) -> Option<&Value> {
+        let local = self.frames.last().and_then(|frame| frame.get(name));
+        local.or_else(|| self.env.get(name))
+    }
+
+    // The body sees the captured values and the arguments only. As for a
+    // user-defined function, errors are reported at the call.
+    fn apply(&mut self, f: &Lambda, args: Vec<Value>, loc: &Loc) -> EvalResult {
+        let params = f.params.iter().cloned().zip(args);
+        let frame = f.captured.iter().cloned().chain(params).collect();
+        self.frames.push(frame);
+        let v = self.eval_ast(&f.body);
+        self.frames.pop();
+        v.map_err(|e| EvalError::new(e.value, loc.clone()))
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The semantics of each node, shared by the interpreter and the compiler.
+// `word` is the width in programmer mode.
+
+pub(super) fn int(word: Option<WordWidth>, n: u64, loc: &Loc) -> EvalResult {
+    if let Some(w) = word {
+        Ok(Value::Int(w.wrap(n as i128)))
+    } else if n > i64::MAX as u64 {
+        Err(EvalError::overflow(loc.clone()))
+    } else {
+        Ok(Value::Int(n as i64))
+    }
+}
+
+pub(super) fn index(xs: Value, i: Value, loc: &Loc) -> EvalResult {
+    let (xs, i) = match (xs, i) {
+        (Value::List(xs), Value::Int(i)) => (xs, i),
+        _ => unreachable!("rejected by the type checker"),
+    };
+    // negative indices count from the end, `xs[-1]` is the last element
+    let pos = if i < 0 { xs.len() as i64 + i } else { i };
+    if 0 <= pos && (pos as usize) < xs.len() {
+        Ok(xs[pos as usize].clone())
+    } else {
+        Err(EvalError::index_out_of_range(i, xs.len(), loc.clone()))
+    }
+}
+
+pub(super) fn uniop(word: Option<WordWidth>, op: &UniOp, e: Value, loc: &Loc) -> EvalResult {
+    match (op.value, e, word) {
+        (UniOpKind::Plus | UniOpKind::Minus, Value::List(xs), _) => {
+            let xs = xs.into_iter().map(|x| uniop(word, op, x, loc));
+            Ok(Value::list(xs.collect::<Result<_, _>>()?))
+        }
+        (UniOpKind::Plus, e, _) => Ok(e),
+        (UniOpKind::Minus, Value::Int(n), Some(w)) => Ok(Value::Int(w.wrap(-w.value(n)))),
+        (UniOpKind::Minus, Value::Int(n), None) => n
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| EvalError::overflow(loc.clone())),
+        (UniOpKind::Minus, Value::Float(f), _) => Ok(Value::Float(-f)),
+        (UniOpKind::Minus, Value::Complex(z), _) => Ok(Value::Complex(z.neg())),
+        (UniOpKind::BitNot, Value::Int(n), Some(w)) => Ok(Value::Int(w.wrap(!w.value(n)))),
+        (UniOpKind::BitNot, Value::Int(n), None) => Ok(Value::Int(!n)),
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+pub(super) fn binop(
+    word: Option<WordWidth>,
+    op: &BinOp,
+    l: Value,
+    r: Value,
+    loc: &Loc,
+) -> EvalResult {
+    let (l, r) = match (l, r) {
+        (l, r) if op.value == BinOpKind::MatMul => return matrix::matmul(word, op, l, r, loc),
+        (l @ Value::List(_), r) | (l, r @ Value::List(_)) => {
+            return matrix::elementwise(word, op, l, r, loc)
+        }
+        (Value::Int(l), Value::Int(r)) => return int_binop(word, op, l, r, loc),
+        (l, r) => (unwrap_word(word, l), unwrap_word(word, r)),
+    };
+    match (op.value, l, r) {
+        (kind, l, r) if matches!(l, Value::Complex(_)) || matches!(r, Value::Complex(_)) => {
+            let (l, r) = (l.as_complex(), r.as_complex());
+            let z = match kind {
+                BinOpKind::Add => l.add(r),
+                BinOpKind::Sub => l.sub(r),
+                BinOpKind::Mult => l.mul(r),
+                BinOpKind::Div => l
+                    .div(r)
+                    .ok_or_else(|| EvalError::division_by_zero(op.loc.clone()))?,
+                _ => unreachable!("rejected by the type checker"),
+            };
+            Ok(Value::Complex(z))
+        }
+        (BinOpKind::Div, l, r) => {
+            if r.as_f64() == 0.0 {
+                Err(EvalError::division_by_zero(op.loc.clone()))
+            } else {
+                Ok(Value::Float(l.as_f64() / r.as_f64()))
+            }
+        }
+        (kind, l, r) => {
+            let (l, r) = (l.as_f64(), r.as_f64());
+            let f = match kind {
+                BinOpKind::Add => l + r,
+                BinOpKind::Sub => l - r,
+                BinOpKind::Mult => l * r,
+                _ => unreachable!("rejected by the type checker"),
+            };
+            Ok(Value::Float(f))
+        }
+    }
+}
+
+// In programmer mode an `Int` is a bit pattern, so convert it to the
+// number it represents before mixing it with floats.
+pub(super) fn unwrap_word(word: Option<WordWidth>, v: Value) -> Value {
+    match (v, word) {
+        (Value::Int(n), Some(w)) => Value::Float(w.value(n) as f64),
+        (v, _) => v,
+    }
+}
+
+fn int_binop(word: Option<WordWidth>, op: &BinOp, l: i64, r: i64, loc: &Loc) -> EvalResult {
+    use self::BinOpKind::*;
+    if op.value == Div {
+        let to_float = |n: i64| match word {
+            Some(w) => Value::Float(w.value(n) as f64),
+            None => Value::Float(n as f64),
+        };
+        return binop(word, op, to_float(l), to_float(r), loc);
+    }
+    if op.value == IntDiv && r == 0 {
+        return Err(EvalError::division_by_zero(op.loc.clone()));
+    }
+    let bits = word.map(WordWidth::bits).unwrap_or(64);
+    if (op.value == Shl || op.value == Shr) && !(0 <= r && r < bits as i64) {
+        return Err(EvalError::invalid_shift(r, op.loc.clone()));
+    }
+    let n = match word {
+        // compute with the represented numbers and wrap the result around
+        Some(w) => {
+            let (l, r) = (w.value(l), w.value(r));
+            let n = match op.value {
+                Add => l + r,
+                Sub => l - r,
+                Mult => l.wrapping_mul(r),
+                IntDiv => floor_div(l, r),
+                BitAnd => l & r,
+                BitOr => l | r,
+                BitXor => l ^ r,
+                Shl => l << r,
+                Shr => l >> r,
+                Div | MatMul => unreachable!(),
+            };
+            Some(w.wrap(n))
+        }
+        None => match op.value {
+            Add => l.checked_add(r),
+            Sub => l.checked_sub(r),
+            Mult => l.checked_mul(r),
+            IntDiv => l.checked_div(r).map(|_| floor_div(l, r)),
+            BitAnd => Some(l & r),
+            BitOr => Some(l | r),
+            BitXor => Some(l ^ r),
+            Shl => Some(l << r).filter(|n| n >> r == l),
+            Shr => Some(l >> r),
+            Div | MatMul => unreachable!(),
+        },
+    };
+    n.map(Value::Int)
+        .ok_or_else(|| EvalError::overflow(loc.clone()))
+}
+
+// Division rounding towards negative infinity, e.g. `-7 // 2 == -4`.
+// The caller must check for zero and overflow.
+fn floor_div<T>(l: T, r: T) -> T
+where
+    T: Copy
+        + PartialOrd
+        + std::ops::Div<Output = T>
+        + std::ops::Rem<Output = T>
+        + std::ops::Sub<Output = T>
+        + From<i8>,
+{
+    let zero = T::from(0);
+    let q = l / r;
+    if l % r != zero && (l < zero) != (r < zero) {
+        q - T::from(1)
+    } else {
+        q
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Complex, EvalError, EvalResult, Formatter, Interpreter, Value};
+    use crate::calc::{lex, parse, Loc};
+
+    fn eval_str(input: &str) -> EvalResult {
+        Interpreter::new().eval(&parse(lex(input).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(Value::Int(7)));
+        assert_eq!(eval_str("-(2 - 5)"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("1 + 2.5"), Ok(Value::Float(3.5)));
+        assert_eq!(eval_str("1.5 * 2"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(eval_str("6 / 2"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("7 // 2"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("-7 // 2"), Ok(Value::Int(-4)));
+    }
+
+    #[test]
+    fn test_eval_complex() {
+        assert_eq!(
+            eval_str("2 + 3i"),
+            Ok(Value::Complex(Complex::new(2.0, 3.0)))
+        );
+        assert_eq!(
+            eval_str("(1 + 2i) * (3 - 1i)"),
+            Ok(Value::Complex(Complex::new(5.0, 5.0)))
+        );
+        assert_eq!(
+            eval_str("-(1i) / 1i"),
+            Ok(Value::Complex(Complex::new(-1.0, 0.0)))
+        );
+        assert_eq!(
+            eval_str("1 / (0i)"),
+            Err(EvalError::division_by_zero(Loc(2, 3)))
+        );
+        assert_eq!(
+            eval_str("[1, 2i]"),
+            Ok(Value::List(vec![
+                Value::Complex(Complex::new(1.0, 0.0)),
+                Value::Complex(Complex::new(0.0, 2.0)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_eval_bitwise() {
+        assert_eq!(eval_str("0b1100 & 0b1010"), Ok(Value::Int(0b1000)));
+        assert_eq!(eval_str("0b1100 | 0b1010"), Ok(Value::Int(0b1110)));
+        assert_eq!(eval_str("0b1100 xor 0b1010"), Ok(Value::Int(0b0110)));
+        assert_eq!(eval_str("~0"), Ok(Value::Int(-1)));
+        assert_eq!(eval_str("1 << 4 >> 2"), Ok(Value::Int(4)));
+        assert_eq!(eval_str("-8 >> 1"), Ok(Value::Int(-4)));
+        assert_eq!(
+            eval_str("1 << 64"),
+            Err(EvalError::invalid_shift(64, Loc(2, 4)))
+        );
+        assert_eq!(
+            eval_str("0x4000000000000000 << 1"),
+            Err(EvalError::overflow(Loc(0, 23)))
+        );
+    }
+
+    #[test]
+    fn test_eval_word_width() {
+        let eval_as = |word: &str, input: &str| {
+            let mut interp = Interpreter::new();
+            interp.set_word_width(Some(word.parse().unwrap()));
+            interp.eval(&parse(lex(input).unwrap()).unwrap())
+        };
+        assert_eq!(eval_as("u8", "255 + 1"), Ok(Value::Int(0)));
+        assert_eq!(eval_as("u8", "0 - 1"), Ok(Value::Int(255)));
+        assert_eq!(eval_as("u8", "~0"), Ok(Value::Int(255)));
+        assert_eq!(eval_as("u8", "0x80 >> 7"), Ok(Value::Int(1)));
+        assert_eq!(eval_as("i8", "127 + 1"), Ok(Value::Int(-128)));
+        assert_eq!(eval_as("i8", "0x80 >> 7"), Ok(Value::Int(-1)));
+        assert_eq!(eval_as("i16", "0x7fff * 2"), Ok(Value::Int(-2)));
+        assert_eq!(eval_as("u64", "0xffffffffffffffff"), Ok(Value::Int(-1)));
+        assert_eq!(
+            eval_as("u64", "0xffffffffffffffff >> 63"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            eval_as("u64", "0xffffffffffffffff / 1"),
+            Ok(Value::Float(u64::MAX as f64))
+        );
+        assert_eq!(
+            eval_as("u8", "1 << 8"),
+            Err(EvalError::invalid_shift(8, Loc(2, 4)))
+        );
+    }
+
+    #[test]
+    fn test_eval_list() {
+        assert_eq!(
+            eval_str("[1, 2.5]"),
+            Ok(Value::List(vec![Value::Float(1.0), Value::Float(2.5)]))
+        );
+        assert_eq!(eval_str("[1, 2, 3][1] * 2"), Ok(Value::Int(4)));
+        assert_eq!(eval_str("[1, 2, 3][-1]"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("[[1, 2], [3]][0][1]"), Ok(Value::Int(2)));
+        assert_eq!(
+            eval_str("[1, 2][2]"),
+            Err(EvalError::index_out_of_range(2, 2, Loc(7, 8)))
+        );
+    }
+
+    #[test]
+    fn test_eval_error() {
+        assert_eq!(
+            eval_str("1 / (2 - 2)"),
+            Err(EvalError::division_by_zero(Loc(2, 3)))
+        );
+        assert_eq!(
+            eval_str("1 // 0"),
+            Err(EvalError::division_by_zero(Loc(2, 4)))
+        );
+        assert_eq!(
+            eval_str("9223372036854775807 + 1"),
+            Err(EvalError::overflow(Loc(0, 23)))
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Int(3).to_string(), "3");
+        assert_eq!(Value::Float(3.0).to_string(), "3.0");
+        assert_eq!(Value::Float(0.25).to_string(), "0.25");
+        assert_eq!(
+            Value::List(vec![Value::Int(1), Value::List(vec![])]).to_string(),
+            "[1, []]"
+        );
+        let z = Value::Complex(Complex::new(0.0, 2.0));
+        assert_eq!(z.to_string(), "2i");
+        assert_eq!(
+            Value::List(vec![Value::Int(1), z]).to_polar_string(),
+            "[1, 2∠1.5707963267948966]"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let mut fmt = Formatter::new();
+        let v = Value::List(vec![
+            Value::Int(1234),
+            Value::Float(2.5),
+            Value::Complex(Complex::new(1.0, -0.5)),
+        ]);
+        assert_eq!(v.format(&fmt), v.to_string());
+        fmt.set("fixed", "2").unwrap();
+        fmt.set("decimal", ",").unwrap();
+        fmt.set("thousands", ".").unwrap();
+        assert_eq!(v.format(&fmt), "[1.234; 2,50; 1,00-0,50i]");
+    }
+}
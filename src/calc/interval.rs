@@ -0,0 +1,324 @@
+use super::{Ast, AstKind, BinOpKind, EvalError, EvalErrorKind, UniOpKind};
+
+// A closed interval `[lo, hi]` of reals. Every operation rounds its bounds
+// outwards, so the exact result is always contained in the computed one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+impl Interval {
+    pub fn new(lo: f64, hi: f64) -> Self {
+        assert!(lo <= hi, "lo must not be greater than hi");
+        Interval { lo, hi }
+    }
+
+    pub fn point(x: f64) -> Self {
+        Self::new(x, x)
+    }
+
+    fn entire() -> Self {
+        Self::new(f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    fn neg(self) -> Self {
+        Self::new(-self.hi, -self.lo)
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self::new(add_down(self.lo, other.lo), add_up(self.hi, other.hi))
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.add(other.neg())
+    }
+
+    fn mul(self, other: Self) -> Self {
+        let pairs = [
+            (self.lo, other.lo),
+            (self.lo, other.hi),
+            (self.hi, other.lo),
+            (self.hi, other.hi),
+        ];
+        let lo = pairs
+            .iter()
+            .map(|&(a, b)| mul_down(a, b))
+            .fold(f64::INFINITY, f64::min);
+        let hi = pairs
+            .iter()
+            .map(|&(a, b)| mul_up(a, b))
+            .fold(f64::NEG_INFINITY, f64::max);
+        Self::new(lo, hi)
+    }
+
+    // Returns `None` only if the divisor is exactly `[0, 0]`. A divisor
+    // containing zero otherwise yields a half-bounded or the entire interval.
+    fn div(self, other: Self) -> Option<Self> {
+        let (a, b) = (self.lo, self.hi);
+        let (c, d) = (other.lo, other.hi);
+        if c == 0.0 && d == 0.0 {
+            return None;
+        }
+        if !other.contains(0.0) {
+            let pairs = [(a, c), (a, d), (b, c), (b, d)];
+            let lo = pairs
+                .iter()
+                .map(|&(x, y)| div_down(x, y))
+                .fold(f64::INFINITY, f64::min);
+            let hi = pairs
+                .iter()
+                .map(|&(x, y)| div_up(x, y))
+                .fold(f64::NEG_INFINITY, f64::max);
+            return Some(Self::new(lo, hi));
+        }
+        let inf = f64::INFINITY;
+        let ret = if self.contains(0.0) || (c < 0.0 && 0.0 < d) {
+            // `[c, d]` splits into two parts around zero; their hull is everything
+            Self::entire()
+        } else if c == 0.0 {
+            if b < 0.0 {
+                Self::new(-inf, div_up(b, d))
+            } else {
+                Self::new(div_down(a, d), inf)
+            }
+        } else if b < 0.0 {
+            Self::new(div_down(b, c), inf)
+        } else {
+            Self::new(-inf, div_up(a, c))
+        };
+        Some(ret)
+    }
+
+    fn floor(self) -> Self {
+        Self::new(self.lo.floor(), self.hi.floor())
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[{:?}, {:?}]", self.lo, self.hi)
+    }
+}
+
+// Directed rounding: the error of each operation is computed exactly (with
+// the TwoSum algorithm or an FMA) and the result is moved by one ulp only if
+// it was rounded in the wrong direction.
+fn add_down(a: f64, b: f64) -> f64 {
+    let s = a + b;
+    if two_sum_err(a, b, s) < 0.0 {
+        s.next_down()
+    } else {
+        s
+    }
+}
+fn add_up(a: f64, b: f64) -> f64 {
+    let s = a + b;
+    if two_sum_err(a, b, s) > 0.0 {
+        s.next_up()
+    } else {
+        s
+    }
+}
+fn two_sum_err(a: f64, b: f64, s: f64) -> f64 {
+    let bb = s - a;
+    (a - (s - bb)) + (b - bb)
+}
+fn mul_down(a: f64, b: f64) -> f64 {
+    // `0 * inf` is 0 in interval arithmetic
+    if a == 0.0 || b == 0.0 {
+        return 0.0;
+    }
+    let p = a * b;
+    if a.mul_add(b, -p) < 0.0 {
+        p.next_down()
+    } else {
+        p
+    }
+}
+fn mul_up(a: f64, b: f64) -> f64 {
+    if a == 0.0 || b == 0.0 {
+        return 0.0;
+    }
+    let p = a * b;
+    if a.mul_add(b, -p) > 0.0 {
+        p.next_up()
+    } else {
+        p
+    }
+}
+// The exact quotient is `q + r / b` where `r = a - q * b`.
+fn div_down(a: f64, b: f64) -> f64 {
+    let q = a / b;
+    let r = (-q).mul_add(b, a);
+    if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        q.next_down()
+    } else {
+        q
+    }
+}
+fn div_up(a: f64, b: f64) -> f64 {
+    let q = a / b;
+    let r = (-q).mul_add(b, a);
+    if r != 0.0 && (r < 0.0) == (b < 0.0) {
+        q.next_up()
+    } else {
+        q
+    }
+}
+
+// A number literal as an interval. Floats are widened by an ulp on both sides
+// since a decimal literal like `0.1` is not exactly representable.
+fn literal(ast: &Ast) -> Interval {
+    match ast.value {
+        AstKind::Int(n) => {
+            let x = n as f64;
+            if x as u64 == n {
+                Interval::point(x)
+            } else {
+                Interval::new(x.next_down(), x.next_up())
+            }
+        }
+        AstKind::Float(f) => Interval::new(f.next_down(), f.next_up()),
+        _ => unreachable!("the parser only accepts number literals"),
+    }
+}
+
+// Evaluate the expression with every value being an interval.
+pub(super) fn eval(ast: &Ast) -> Result<Interval, EvalError> {
+    match &ast.value {
+        AstKind::Int(_) | AstKind::Float(_) => Ok(literal(ast)),
+        AstKind::PlusMinus { mid, rad } => {
+            let mid = literal(mid);
+            let rad = literal(rad).hi;
+            Ok(Interval::new(add_down(mid.lo, -rad), add_up(mid.hi, rad)))
+        }
+        AstKind::UniOp { op, e } => {
+            let e = eval(e)?;
+            match op.value {
+                UniOpKind::Plus => Ok(e),
+                UniOpKind::Minus => Ok(e.neg()),
+                UniOpKind::BitNot => Err(unsupported(ast)),
+            }
+        }
+        AstKind::BinOp { op, l, r } => {
+            let l = eval(l)?;
+            let r = eval(r)?;
+            let div = || {
+                l.div(r)
+                    .ok_or_else(|| EvalError::new(EvalErrorKind::DivisionByZero, op.loc.clone()))
+            };
+            match op.value {
+                BinOpKind::Add => Ok(l.add(r)),
+                BinOpKind::Sub => Ok(l.sub(r)),
+                BinOpKind::Mult => Ok(l.mul(r)),
+                BinOpKind::Div => div(),
+                BinOpKind::IntDiv => div().map(Interval::floor),
+                _ => Err(unsupported(ast)),
+            }
+        }
+        AstKind::Imag(_)
+        | AstKind::Var(_)
+        | AstKind::List(_)
+        | AstKind::Index { .. }
+        | AstKind::Call { .. }
+        | AstKind::Lambda { .. } => Err(unsupported(ast)),
+    }
+}
+
+fn unsupported(ast: &Ast) -> EvalError {
+    EvalError::new(EvalErrorKind::UnsupportedInIntervalMode, ast.loc.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interval;
+    use crate::calc::{eval_interval, eval_interval_with, CalcError, EvalErrorKind};
+    use crate::calc::{Interpreter, LimitErrorKind, Limits};
+
+    fn iv(input: &str) -> Interval {
+        eval_interval(input).unwrap()
+    }
+
+    #[test]
+    fn test_arith() {
+        assert_eq!(iv("1 + 2"), Interval::new(3.0, 3.0));
+        assert_eq!(iv("3±1 - 1±1"), Interval::new(0.0, 4.0));
+        assert_eq!(iv("(1 +/- 2) * (3 +/- 1)"), Interval::new(-4.0, 12.0));
+        assert_eq!(iv("-(2±1)"), Interval::new(-3.0, -1.0));
+        assert_eq!(iv("(4±2) / (2±1)"), Interval::new(2.0 / 3.0, 6.0));
+        assert_eq!(iv("(7±1) // 2"), Interval::new(3.0, 4.0));
+    }
+
+    #[test]
+    fn test_outward_rounding() {
+        let x = iv("0.1 + 0.2");
+        assert!(x.lo() < 0.3 && 0.3 < x.hi());
+        let x = iv("1 / 3");
+        assert!(x.lo() < x.hi());
+        assert!(x.lo() * 3.0 <= 1.0 && 1.0 <= x.hi() * 3.0);
+        let x = iv("2.5±0.1");
+        assert!(x.contains(2.4) && x.contains(2.6));
+    }
+
+    #[test]
+    fn test_div_by_zero_interval() {
+        let inf = f64::INFINITY;
+        assert_eq!(iv("1 / (1±1)"), Interval::new(0.5, inf));
+        assert_eq!(iv("1 / (-1±1)"), Interval::new(-inf, -0.5));
+        assert_eq!(iv("-1 / (1±1)"), Interval::new(-inf, -0.5));
+        assert_eq!(iv("1 / (0±1)"), Interval::new(-inf, inf));
+        assert_eq!(iv("(0±1) / (1±1)"), Interval::new(-inf, inf));
+        match eval_interval("1 / (1 - 1)") {
+            Err(CalcError::Eval(e)) => assert_eq!(e.value, EvalErrorKind::DivisionByZero),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_interval_limits() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::sandbox());
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        match eval_interval_with(&interp, &nested) {
+            Err(CalcError::Limit(e)) => assert_eq!(e.value, LimitErrorKind::TooDeep(64)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        let long = format!("{}1", "1 + ".repeat(2000));
+        match eval_interval_with(&interp, &long) {
+            Err(CalcError::Limit(e)) => {
+                assert_eq!(
+                    e.value,
+                    LimitErrorKind::InputTooLong {
+                        len: 8001,
+                        max: 4096
+                    }
+                )
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(eval_interval_with(&interp, "(1±1) * 2").is_ok());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        match eval_interval("sum([1])") {
+            Err(CalcError::Eval(e)) => {
+                assert_eq!(e.value, EvalErrorKind::UnsupportedInIntervalMode)
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use super::builtins;
+use super::forms;
+use super::types::{expect_real, ApplyType};
+use super::{Annot, Ast, AstKind, EvalError, Ident, Loc, Type, TypeError, Value};
+use std::rc::Rc;
+
+// Anonymous functions, `x -> x * 2` or `(a, b) -> a + b`, and the
+// functions which take them, `map`, `filter` and `reduce`.
+//
+// `closure.rs` shows how Rust decides what a closure captures and how. In
+// calc the rules are fixed:
+//
+// * every variable the body uses is captured by value when the lambda is
+//   created, as by a `move` closure. This holds for the parameters of the
+//   enclosing function or lambda, the variable of a form and the globals
+//   alike, so after `k = 2; f = x -> x * k; k = 3` the value of `f(1)` is
+//   still 2. A lambda cannot use the variable it is assigned to, which is
+//   not bound yet or holds an older value.
+// * a user-defined function is called by its name, as if the closure
+//   borrowed it, so redefining `g(x)` changes the lambdas which call `g`.
+// * nothing can be assigned in a body, so every lambda is like an `Fn`: it
+//   may be called any number of times, which `map` does.
+//
+// A variable holding a lambda is called like a function, `f(1)`, and is
+// called before a user-defined function of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    pub(super) params: Vec<String>,
+    pub(super) body: Rc<Ast>,
+    // the values of the variables of the body when the lambda was created
+    pub(super) captured: Vec<(String, Value)>,
+}
+
+impl Lambda {
+    pub(super) fn type_of(&self) -> FuncType {
+        FuncType {
+            params: self.params.clone(),
+            body: Rc::clone(&self.body),
+            captured: self
+                .captured
+                .iter()
+                .map(|(name, v)| (name.clone(), v.type_of()))
+                .collect(),
+        }
+    }
+}
+
+// The source of the lambda, without the captured values.
+impl std::fmt::Display for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write_lambda(f, &self.params, &self.body)
+    }
+}
+
+// The type of a lambda is the lambda itself with the types of the captured
+// values: like the body of a user-defined function, the body is checked
+// again with the types of the arguments at each call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncType {
+    pub(super) params: Vec<String>,
+    pub(super) body: Rc<Ast>,
+    pub(super) captured: Vec<(String, Type)>,
+}
+
+impl std::fmt::Display for FuncType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "fn({})", self.params.join(", "))
+    }
+}
+
+fn write_lambda(f: &mut std::fmt::Formatter, params: &[String], body: &Ast) -> std::fmt::Result {
+    match params {
+        [param] => write!(f, "{} -> {}", param, body),
+        _ => write!(f, "({}) -> {}", params.join(", "), body),
+    }
+}
+
+// A name the body of a lambda uses without binding it.
+pub(super) struct Free<'a> {
+    pub(super) name: &'a str,
+    pub(super) loc: &'a Loc,
+    // called as in `f(x)`, rather than used as a variable
+    pub(super) call: bool,
+}
+
+// The names which the lambda may capture, in the order they first appear.
+// A called name is one, unless it is a builtin, a form or one of the
+// functions below, as it may be a variable which holds a lambda.
+pub(super) fn free_names<'a>(params: &'a [Ident], body: &'a Ast) -> Vec<Free<'a>> {
+    fn walk<'a>(ast: &'a Ast, bound: &mut Vec<&'a str>, free: &mut Vec<Free<'a>>) {
+        let mut add = |name: &'a str, loc: &'a Loc, call: bool| {
+            let seen = free.iter().any(|u| u.name == name && u.call == call);
+            if !bound.contains(&name) && !seen {
+                free.push(Free { name, loc, call });
+            }
+        };
+        match &ast.value {
+            AstKind::Var(name) => add(name, &ast.loc, false),
+            AstKind::Call { func, args } if forms::lookup(&func.value, args.len()).is_some() => {
+                if let Some(x) = forms::bound_var(args) {
+                    bound.push(x);
+                    walk(&args[0], bound, free);
+                    bound.pop();
+                    for e in &args[2..] {
+                        walk(e, bound, free);
+                    }
+                    return;
+                }
+            }
+            AstKind::Call { func, .. }
+                if lookup(&func.value).is_none() && builtins::lookup(&func.value).is_none() =>
+            {
+                add(&func.value, &func.loc, true)
+            }
+            AstKind::Lambda { params, body } => {
+                let n = bound.len();
+                bound.extend(params.iter().map(|p| p.value.as_str()));
+                walk(body, bound, free);
+                bound.truncate(n);
+                return;
+            }
+            _ => {}
+        }
+        for e in ast.children() {
+            walk(e, bound, free);
+        }
+    }
+    let mut bound: Vec<_> = params.iter().map(|p| p.value.as_str()).collect();
+    let mut free = Vec::new();
+    walk(body, &mut bound, &mut free);
+    free
+}
+
+// A function of lambdas. Like the forms they are evaluated by the
+// interpreter, which applies the lambdas.
+pub(super) struct HigherOrder {
+    pub(super) name: &'static str,
+    pub(super) arity: usize,
+    // Computes the result type from the argument types, applying the type
+    // of the lambda to the types of its arguments.
+    pub(super) ret: fn(&[Annot<Type>], &mut ApplyType) -> Result<Type, TypeError>,
+    pub(super) eval: fn(Vec<Value>, &mut Apply) -> Result<Value, EvalError>,
+}
+
+// Calls a lambda with the arguments.
+pub(super) type Apply<'a> = dyn FnMut(&Lambda, Vec<Value>) -> Result<Value, EvalError> + 'a;
+
+const HIGHER_ORDER: &[HigherOrder] = &[
+    HigherOrder {
+        name: "map",
+        arity: 2,
+        ret: map_type,
+        eval: map,
+    },
+    // calc has no booleans, an element is kept if the lambda returns
+    // anything but zero, e.g. `filter(xs, x -> x - 1)` drops the ones
+    HigherOrder {
+        name: "filter",
+        arity: 2,
+        ret: filter_type,
+        eval: filter,
+    },
+    // `reduce(xs, (acc, x) -> acc + x, 0)` folds from the left
+    HigherOrder {
+        name: "reduce",
+        arity: 3,
+        ret: reduce_type,
+        eval: reduce,
+    },
+];
+
+pub(super) fn lookup(name: &str) -> Option<&'static HigherOrder> {
+    HIGHER_ORDER.iter().find(|f| f.name == name)
+}
+
+pub(super) fn names() -> impl Iterator<Item = &'static str> {
+    HIGHER_ORDER.iter().map(|f| f.name)
+}
+
+fn elem_type(t: &Annot<Type>) -> Result<Type, TypeError> {
+    match &t.value {
+        Type::List(t) => Ok((**t).clone()),
+        Type::Any => Ok(Type::Any),
+        found => Err(TypeError::not_list(found.clone(), t.loc.clone())),
+    }
+}
+
+fn func_type(t: &Annot<Type>) -> Result<&FuncType, TypeError> {
+    match &t.value {
+        Type::Func(f) => Ok(f),
+        found => Err(TypeError::not_function(found.clone(), t.loc.clone())),
+    }
+}
+
+fn map_type(args: &[Annot<Type>], apply: &mut ApplyType) -> Result<Type, TypeError> {
+    let t = elem_type(&args[0])?;
+    let f = func_type(&args[1])?;
+    Ok(Type::List(Box::new(apply(f, vec![t])?)))
+}
+
+fn filter_type(args: &[Annot<Type>], apply: &mut ApplyType) -> Result<Type, TypeError> {
+    let t = elem_type(&args[0])?;
+    let f = func_type(&args[1])?;
+    expect_real(apply(f, vec![t.clone()])?, &args[1].loc)?;
+    Ok(Type::List(Box::new(t)))
+}
+
+// The accumulator takes the common type of the initial value and of the
+// results, e.g. a `float` if it starts with `0` and adds floats.
+fn reduce_type(args: &[Annot<Type>], apply: &mut ApplyType) -> Result<Type, TypeError> {
+    let t = elem_type(&args[0])?;
+    let f = func_type(&args[1])?;
+    let mut acc = args[2].value.clone();
+    loop {
+        let r = apply(f, vec![acc.clone(), t.clone()])?;
+        let next = acc
+            .promote(&r)
+            .ok_or_else(|| TypeError::mismatch(acc.clone(), r, args[1].loc.clone()))?;
+        if next == acc {
+            return Ok(acc);
+        }
+        acc = next;
+    }
+}
+
+// The list, the lambda and the rest of the arguments.
+fn unpack(args: Vec<Value>) -> (Vec<Value>, Rc<Lambda>, Vec<Value>) {
+    let mut args = args.into_iter();
+    match (args.next(), args.next()) {
+        (Some(Value::List(xs)), Some(Value::Lambda(f))) => (xs, f, args.collect()),
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+fn map(args: Vec<Value>, apply: &mut Apply) -> Result<Value, EvalError> {
+    let (xs, f, _) = unpack(args);
+    let ys = xs.into_iter().map(|x| apply(&f, vec![x]));
+    Ok(Value::list(ys.collect::<Result<_, _>>()?))
+}
+
+fn filter(args: Vec<Value>, apply: &mut Apply) -> Result<Value, EvalError> {
+    let (xs, f, _) = unpack(args);
+    let mut kept = Vec::new();
+    for x in xs {
+        if apply(&f, vec![x.clone()])?.as_f64() != 0.0 {
+            kept.push(x);
+        }
+    }
+    Ok(Value::List(kept))
+}
+
+fn reduce(args: Vec<Value>, apply: &mut Apply) -> Result<Value, EvalError> {
+    let (xs, f, rest) = unpack(args);
+    let mut acc = rest.into_iter().next().unwrap();
+    for x in xs {
+        acc = apply(&f, vec![acc, x])?;
+    }
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calc::{compile, exec, lex, parse, CalcError, Interpreter, Value};
+    use crate::calc::{EvalErrorKind, Loc, Type, TypeErrorKind};
+
+    fn value(interp: &mut Interpreter, input: &str) -> Result<Value, CalcError> {
+        exec(interp, input).map(|v| v.unwrap())
+    }
+
+    fn ints(ns: &[i64]) -> Value {
+        Value::List(ns.iter().map(|&n| Value::Int(n)).collect())
+    }
+
+    #[test]
+    fn test_higher_order() {
+        let mut interp = Interpreter::new();
+        let mut run = |input| value(&mut interp, input);
+        assert_eq!(run("map([1, 2, 3], x -> x * 2)"), Ok(ints(&[2, 4, 6])));
+        assert_eq!(
+            run("map([1, 2], x -> x / 2)"),
+            Ok(Value::List(vec![Value::Float(0.5), Value::Float(1.0)]))
+        );
+        assert_eq!(run("filter([1, 2, 3, 1], x -> x - 1)"), Ok(ints(&[2, 3])));
+        assert_eq!(
+            run("reduce([1, 2, 3], (a, x) -> a * 10 + x, 0)"),
+            Ok(Value::Int(123))
+        );
+        // the accumulator is a float from the start
+        assert_eq!(
+            run("reduce([1, 2], (a, x) -> a + x / 2, 0)"),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(run("map([], x -> x * 2)"), Ok(ints(&[])));
+        assert_eq!(run("sum(map([1, 2, 3], (x) -> x * x))"), Ok(Value::Int(14)));
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut interp = Interpreter::new();
+        let mut run = |input| value(&mut interp, input);
+        run("k = 2").unwrap();
+        run("f = x -> x * k").unwrap();
+        run("k = 3").unwrap();
+        // captured by value when created
+        assert_eq!(run("f(1)"), Ok(Value::Int(2)));
+        // an older value of itself
+        run("f = x -> f(x) + 1").unwrap();
+        assert_eq!(run("f(1)"), Ok(Value::Int(3)));
+        // the parameters of a function and lambdas returning lambdas
+        exec(&mut interp, "scale(xs, s) = map(xs, x -> x * s)").unwrap();
+        let mut run = |input| value(&mut interp, input);
+        assert_eq!(run("scale([1, 2], 3)"), Ok(ints(&[3, 6])));
+        run("add = a -> b -> a + b").unwrap();
+        run("inc = add(1)").unwrap();
+        assert_eq!(run("inc(41)"), Ok(Value::Int(42)));
+        assert_eq!(
+            run("c = () -> 7").map(|v| v.to_string()),
+            Ok("() -> 7".to_string())
+        );
+        assert_eq!(run("c()"), Ok(Value::Int(7)));
+        // the variable of a form is captured too
+        assert_eq!(
+            run("sum(map([1], x -> x + i)[0], i, 1, 3)"),
+            Ok(Value::Int(9))
+        );
+    }
+
+    #[test]
+    fn test_apply_result() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "f = x -> x * 2").unwrap();
+        exec(&mut interp, "compose = (a, b) -> (x -> a(b(x)))").unwrap();
+        assert_eq!(value(&mut interp, "compose(f, f)(1)"), Ok(Value::Int(4)));
+        assert_eq!(value(&mut interp, "(x -> x + 1)(2) * 3"), Ok(Value::Int(9)));
+        // still a product when the left operand is a number
+        assert_eq!(value(&mut interp, "f(2)(3)"), Ok(Value::Int(12)));
+    }
+
+    #[test]
+    fn test_call_by_name() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "g(x) = x + 1").unwrap();
+        exec(&mut interp, "f = x -> g(x) * 2").unwrap();
+        assert_eq!(value(&mut interp, "f(1)"), Ok(Value::Int(4)));
+        exec(&mut interp, "g(x) = x + 2").unwrap();
+        assert_eq!(value(&mut interp, "f(1)"), Ok(Value::Int(6)));
+        // a variable holding a lambda is called before the function
+        exec(&mut interp, "g = x -> x").unwrap();
+        assert_eq!(value(&mut interp, "g(1)"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_lambda_error() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "f = x -> x * 2").unwrap();
+        exec(&mut interp, "w = f -> f(f)").unwrap();
+        let mut kind = |input| match exec(&mut interp, input) {
+            Err(CalcError::Type(e)) => e.value,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert!(matches!(
+            kind("f + 1"),
+            TypeErrorKind::NotNumber(Type::Func(_))
+        ));
+        assert_eq!(kind("map([1], 2)"), TypeErrorKind::NotFunction(Type::Int));
+        assert_eq!(
+            kind("f(1, 2)"),
+            TypeErrorKind::ArgCount {
+                expected: 1,
+                found: 2
+            }
+        );
+        assert_eq!(
+            kind("map([1.5], x -> x // 2)"),
+            TypeErrorKind::Mismatch {
+                expected: Type::Int,
+                found: Type::Float
+            }
+        );
+        assert_eq!(
+            kind("filter([1], x -> x * 1i)"),
+            TypeErrorKind::Mismatch {
+                expected: Type::Float,
+                found: Type::Complex
+            }
+        );
+        assert_eq!(
+            kind("h = x -> x + y"),
+            TypeErrorKind::UnknownVariable("y".to_string())
+        );
+        assert_eq!(
+            kind("h = x -> q(x)"),
+            TypeErrorKind::UnknownFunction("q".to_string())
+        );
+        assert_eq!(
+            kind("(x, x) -> x"),
+            TypeErrorKind::DuplicateParam("x".to_string())
+        );
+        exec(&mut interp, "g = x -> x(1)").unwrap();
+        match exec(&mut interp, "g(2)") {
+            Err(CalcError::Type(e)) => {
+                assert_eq!(e.value, TypeErrorKind::NotFunction(Type::Int));
+                assert_eq!(e.loc, Loc(2, 3));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        exec(&mut interp, "n = 2").unwrap();
+        let mut kind = |input| match exec(&mut interp, input) {
+            Err(CalcError::Type(e)) => e.value,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!(kind("n(1)"), TypeErrorKind::NotFunction(Type::Int));
+        assert_eq!(
+            kind("map([1], x -> x(1))"),
+            TypeErrorKind::NotFunction(Type::Int)
+        );
+        // `f` is applied to itself without an end
+        assert_eq!(
+            kind("w(w)"),
+            TypeErrorKind::RecursiveFunction("f".to_string())
+        );
+
+        match exec(&mut interp, "map([1, 0], x -> 1 // x)") {
+            Err(CalcError::Eval(e)) => {
+                assert_eq!(e.value, EvalErrorKind::DivisionByZero);
+                assert_eq!(e.loc, Loc(0, 24));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        let ast = parse(lex("map([1], x -> x)").unwrap()).unwrap();
+        match compile(&interp, &ast) {
+            Err(CalcError::Eval(e)) => assert_eq!(e.value, EvalErrorKind::UnsupportedInCompiled),
+            other => panic!("unexpected: {:?}", other.map(|_| ())),
+        }
+    }
+}
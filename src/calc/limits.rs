@@ -0,0 +1,262 @@
+use super::{builtins, Annot, Ast, AstKind, Env, Loc, Token, TokenKind};
+use std::time::Duration;
+
+// Bounds on the work done for one input, for evaluating expressions from
+// untrusted users. `None` is unlimited, which is the default.
+//
+// Integers are 64 bits wide, so the size of a value is the length of a
+// list. Calls of user-defined functions count as if their bodies were
+// written in place, so the depth and the steps of `f(x)` include those of
+// the body of `f`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    pub max_input_len: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_steps: Option<u64>,
+    pub max_list_len: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    // Limits for a single line typed by a user.
+    pub fn sandbox() -> Self {
+        Limits {
+            max_input_len: Some(4096),
+            max_depth: Some(64),
+            max_steps: Some(100_000),
+            max_list_len: Some(10_000),
+            timeout: Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitErrorKind {
+    InputTooLong { len: usize, max: usize },
+    TooDeep(usize),
+    TooManySteps(u64),
+    ListTooLong { len: usize, max: usize },
+    Timeout(Duration),
+}
+
+pub type LimitError = Annot<LimitErrorKind>;
+
+impl LimitError {
+    fn input_too_long(len: usize, max: usize, loc: Loc) -> Self {
+        Self::new(LimitErrorKind::InputTooLong { len, max }, loc)
+    }
+    fn too_deep(max: usize, loc: Loc) -> Self {
+        Self::new(LimitErrorKind::TooDeep(max), loc)
+    }
+    fn too_many_steps(max: u64, loc: Loc) -> Self {
+        Self::new(LimitErrorKind::TooManySteps(max), loc)
+    }
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let loc = &self.loc;
+        match &self.value {
+            LimitErrorKind::InputTooLong { len, max } => {
+                write!(f, "{}: input of {} bytes is longer than {}", loc, len, max)
+            }
+            LimitErrorKind::TooDeep(max) => {
+                write!(f, "{}: expression is nested deeper than {}", loc, max)
+            }
+            LimitErrorKind::TooManySteps(max) => {
+                write!(f, "{}: evaluation takes more than {} steps", loc, max)
+            }
+            LimitErrorKind::ListTooLong { len, max } => {
+                write!(f, "{}: list of length {} is longer than {}", loc, len, max)
+            }
+            LimitErrorKind::Timeout(t) => {
+                write!(f, "{}: evaluation takes longer than {:?}", loc, t)
+            }
+        }
+    }
+}
+
+pub(super) fn check_input(limits: &Limits, input: &str) -> Result<(), LimitError> {
+    match limits.max_input_len {
+        Some(max) if input.len() > max => {
+            let loc = Loc(max, input.len());
+            Err(LimitError::input_too_long(input.len(), max, loc))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Reject deeply nested tokens before parsing, as the parser recurses for
+// each bracket, each prefix operator and each lambda, whose body goes on
+// to the next `,` or closing bracket.
+pub(super) fn check_nesting(limits: &Limits, tokens: &[Token]) -> Result<(), LimitError> {
+    let max = match limits.max_depth {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    // the arrows of the lambdas open in each bracket, the outermost first
+    let mut arrows = vec![0usize];
+    let mut prefixes = 0;
+    for tok in tokens {
+        match tok.value {
+            TokenKind::LParen | TokenKind::LBracket => arrows.push(0),
+            TokenKind::RParen | TokenKind::RBracket if arrows.len() > 1 => {
+                arrows.pop();
+            }
+            TokenKind::Comma => *arrows.last_mut().unwrap() = 0,
+            TokenKind::Arrow => *arrows.last_mut().unwrap() += 1,
+            _ => {}
+        }
+        let brackets = arrows.len() - 1 + arrows.iter().sum::<usize>();
+        match tok.value {
+            TokenKind::Plus | TokenKind::Minus | TokenKind::Tilde => prefixes += 1,
+            _ => prefixes = 0,
+        }
+        if brackets + prefixes > max {
+            return Err(LimitError::too_deep(max, tok.loc.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Check the depth and the number of steps of an expression before it is
+// type checked, which also looks into the functions it calls.
+pub(super) fn check_ast(limits: &Limits, ast: &Ast, env: &Env) -> Result<(), LimitError> {
+    let (depth, steps) = measure(ast, env);
+    match (limits.max_depth, limits.max_steps) {
+        (Some(max), _) if depth > max => Err(LimitError::too_deep(max, ast.loc.clone())),
+        (_, Some(max)) if steps > max => Err(LimitError::too_many_steps(max, ast.loc.clone())),
+        _ => Ok(()),
+    }
+}
+
+// The depth and the number of nodes of the expression with the bodies of
+// user-defined functions inlined, which is the number of steps to evaluate
+// it. This does not recurse, so a long chain like `1 + 1 + ... + 1` can be
+// measured before it is rejected.
+pub(super) fn measure(ast: &Ast, env: &Env) -> (usize, u64) {
+    let mut depth = 0;
+    let mut steps = 0u64;
+    let mut stack = vec![(ast, 1)];
+    while let Some((e, d)) = stack.pop() {
+        depth = depth.max(d);
+        steps = steps.saturating_add(1);
+        if let AstKind::Call { func, .. } = &e.value {
+            if builtins::lookup(&func.value).is_none() {
+                if let Some(f) = env.get_func(&func.value) {
+                    depth = depth.max(d + f.depth());
+                    steps = steps.saturating_add(f.steps());
+                }
+            }
+        }
+        stack.extend(e.children().into_iter().map(|c| (c, d + 1)));
+    }
+    (depth, steps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LimitErrorKind, Limits};
+    use crate::calc::{eval_with, exec, lex, CalcError, Interpreter, Value};
+    use std::time::Duration;
+
+    fn limit_error(interp: &mut Interpreter, input: &str) -> LimitErrorKind {
+        match exec(interp, input) {
+            Err(CalcError::Limit(e)) => e.value,
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::sandbox());
+        assert_eq!(
+            limit_error(&mut interp, &"1 + ".repeat(2000)),
+            LimitErrorKind::InputTooLong {
+                len: 8000,
+                max: 4096
+            }
+        );
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert_eq!(
+            limit_error(&mut interp, &nested),
+            LimitErrorKind::TooDeep(64)
+        );
+        assert_eq!(
+            limit_error(&mut interp, &"-".repeat(100)),
+            LimitErrorKind::TooDeep(64)
+        );
+        assert_eq!(
+            limit_error(&mut interp, &format!("{}1", "1 + ".repeat(100))),
+            LimitErrorKind::TooDeep(64)
+        );
+        assert_eq!(
+            limit_error(&mut interp, &format!("{}a", "a->".repeat(1300))),
+            LimitErrorKind::TooDeep(64)
+        );
+        assert_eq!(
+            limit_error(&mut interp, &format!("f = {}1", "(x -> ".repeat(100))),
+            LimitErrorKind::TooDeep(64)
+        );
+        // lambdas side by side are not nested
+        let tokens = lex(&format!("[{}]", ["x -> x"; 100].join(", "))).unwrap();
+        assert_eq!(super::check_nesting(interp.limits(), &tokens), Ok(()));
+        assert_eq!(exec(&mut interp, "1 + 2"), Ok(Some(Value::Int(3))));
+    }
+
+    #[test]
+    fn test_limits_functions() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::sandbox());
+        // each function doubles the work of the previous one
+        exec(&mut interp, "f0(x) = x + 1").unwrap();
+        for i in 1..14 {
+            let def = format!("f{}(x) = f{}(x) + f{}(x)", i, i - 1, i - 1);
+            exec(&mut interp, &def).unwrap();
+        }
+        assert_eq!(eval_with(&mut interp, "f10(1)"), Ok(Value::Int(2048)));
+        assert_eq!(
+            limit_error(&mut interp, "f13(1) + f13(1)"),
+            LimitErrorKind::TooManySteps(100_000)
+        );
+        assert_eq!(
+            limit_error(&mut interp, "g(x) = f13(x) * f13(x)"),
+            LimitErrorKind::TooManySteps(100_000)
+        );
+        // the definitions were accepted without limits
+        interp.set_limits(Limits::default());
+        exec(&mut interp, "g(x) = f13(x) * f13(x)").unwrap();
+        interp.set_limits(Limits {
+            max_depth: Some(10),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit_error(&mut interp, "g(1)"),
+            LimitErrorKind::TooDeep(10)
+        );
+    }
+
+    #[test]
+    fn test_limits_runtime() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits {
+            max_list_len: Some(3),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit_error(&mut interp, "sort([1, 2, 3, 4])[0]"),
+            LimitErrorKind::ListTooLong { len: 4, max: 3 }
+        );
+        assert!(exec(&mut interp, "sort([3, 2, 1])[0]").is_ok());
+
+        interp.set_limits(Limits {
+            timeout: Some(Duration::from_secs(0)),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit_error(&mut interp, "1 + 2"),
+            LimitErrorKind::Timeout(Duration::from_secs(0))
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use super::parser::implicit_mult;
+use super::{lex_with, parse_stmt_with, Annot, Ast, AstKind, BinOpKind, Interpreter, Loc};
+use super::{Stmt, StmtKind};
+
+// Forms which are valid but may not mean what the user meant. They do not
+// stop the evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    // `1/2x` is `1/(2x)`, while some read it as `(1/2)x`
+    ImplicitDivisor,
+}
+
+pub type Warning = Annot<WarningKind>;
+
+impl Warning {
+    pub fn loc(&self) -> &Loc {
+        &self.loc
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.value {
+            WarningKind::ImplicitDivisor => write!(
+                f,
+                "{}: implicit product after a division is the divisor, e.g. `1/2x` is `1/(2x)`",
+                self.loc
+            ),
+        }
+    }
+}
+
+// The warnings for a line of input. An invalid line has none, its errors
+// are reported when it is executed.
+pub fn lint(interp: &Interpreter, input: &str) -> Vec<Warning> {
+    let ops = interp.operators();
+    match lex_with(input, ops).map(|tokens| parse_stmt_with(tokens, ops)) {
+        Ok(Ok(stmt)) => lint_stmt(&stmt),
+        _ => vec![],
+    }
+}
+
+pub(super) fn lint_stmt(stmt: &Stmt) -> Vec<Warning> {
+    let e = match &stmt.value {
+        StmtKind::Expr(e) | StmtKind::Let { e, .. } => e,
+        StmtKind::Def { body, .. } => body,
+    };
+    let mut warnings = Vec::new();
+    lint_ast(e, &mut warnings);
+    warnings
+}
+
+fn lint_ast(ast: &Ast, warnings: &mut Vec<Warning>) {
+    if let AstKind::BinOp { op, r, .. } = &ast.value {
+        if matches!(op.value, BinOpKind::Div | BinOpKind::IntDiv) && unparenthesized_implicit(r) {
+            warnings.push(Warning::new(
+                WarningKind::ImplicitDivisor,
+                Loc(ast.loc.0, r.loc.1),
+            ));
+        }
+    }
+    for e in ast.children() {
+        lint_ast(e, warnings);
+    }
+}
+
+// A parenthesised expression starts at its `(`, before its first operand.
+fn unparenthesized_implicit(e: &Ast) -> bool {
+    match &e.value {
+        AstKind::BinOp { op, l, .. } => implicit_mult(op) && l.loc.0 == e.loc.0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint, WarningKind};
+    use crate::calc::Value;
+    use crate::calc::{eval, eval_with, exec, lex_with, parse_stmt_with, Assoc, Interpreter, Loc};
+
+    #[test]
+    fn test_implicit_mult() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "x = 5").unwrap();
+        let mut eval = |input| eval_with(&mut interp, input);
+        assert_eq!(eval("2(3 + 4)"), Ok(Value::Int(14)));
+        assert_eq!(eval("(1 + 2)(3 + 4)"), Ok(Value::Int(21)));
+        assert_eq!(eval("3x"), Ok(Value::Int(15)));
+        assert_eq!(eval("3x + 1"), Ok(Value::Int(16)));
+        assert_eq!(eval("-2x"), Ok(Value::Int(-10)));
+        assert_eq!(eval("1/2x"), Ok(Value::Float(0.1)));
+        assert_eq!(eval("2x/5"), Ok(Value::Float(2.0)));
+        assert_eq!(eval("2 x x"), Ok(Value::Int(50)));
+        assert_eq!(eval("[1, 2][1]x"), Ok(Value::Int(10)));
+        // a call, not a product
+        assert_eq!(eval("abs(-1)(2)"), Ok(Value::Int(2)));
+        assert_eq!(eval("3i"), eval("3 * 1i"));
+        // neither operators nor numbers start an operand
+        assert_eq!(eval("7 xor 2"), Ok(Value::Int(5)));
+        assert!(eval("2 3").is_err());
+    }
+
+    #[test]
+    fn test_implicit_mult_display() {
+        let mut interp = Interpreter::new();
+        let ops = interp.operators_mut();
+        ops.infix("**", 65, Assoc::Right, "pow").unwrap();
+        let stmt = |input| {
+            let tokens = lex_with(input, interp.operators()).unwrap();
+            parse_stmt_with(tokens, interp.operators())
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(stmt("1/2x"), "1 / (2 * x)");
+        assert_eq!(stmt("2x**2"), "2 * pow(x, 2)");
+        assert_eq!(eval("1 / (2 * 4)"), eval("1/2(4)"));
+    }
+
+    #[test]
+    fn test_lint() {
+        let interp = Interpreter::new();
+        let warnings = lint(&interp, "1 + 1/2x");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].value, WarningKind::ImplicitDivisor);
+        assert_eq!(warnings[0].loc, Loc(4, 8));
+        assert_eq!(lint(&interp, "f(x) = 1 // 2(x + 1)").len(), 1);
+        assert!(lint(&interp, "1/(2x)").is_empty());
+        assert!(lint(&interp, "1/2 * x").is_empty());
+        assert!(lint(&interp, "2x/3").is_empty());
+        assert!(lint(&interp, "1/2 +").is_empty());
+    }
+}
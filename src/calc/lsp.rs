@@ -0,0 +1,513 @@
+use super::limits::{self, Limits};
+use super::{builtins, check_in, exec_stmt, lex, lint::lint_stmt, parse_stmt};
+use super::{Ast, AstKind, CalcError, Env, Ident, Interpreter, Loc, Stmt, StmtKind};
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// A calc file is executed line by line, each line being a statement.
+struct Line {
+    text: String,
+    stmt: Option<Stmt>,
+    // the variables and functions before the line is executed
+    env: Env,
+    error: Option<CalcError>,
+}
+
+// The document is untrusted, so it is executed with the limits for a user.
+fn analyze(text: &str) -> Vec<Line> {
+    let mut interp = Interpreter::new();
+    interp.set_limits(Limits::sandbox());
+    let mut lines = Vec::new();
+    for text in text.split('\n') {
+        let text = text.trim_end_matches('\r').to_string();
+        let env = interp.env().clone();
+        let mut stmt = None;
+        let mut error = None;
+        match parse_line(&interp, &text) {
+            Ok(Some(s)) => {
+                error = exec_stmt(&mut interp, &s).err();
+                stmt = Some(s);
+            }
+            Ok(None) => {}
+            Err(e) => error = Some(e),
+        }
+        lines.push(Line {
+            text,
+            stmt,
+            env,
+            error,
+        });
+    }
+    lines
+}
+
+// Same as the checks of `exec` before the statement is executed, `None`
+// for a line without one.
+fn parse_line(interp: &Interpreter, text: &str) -> Result<Option<Stmt>, CalcError> {
+    let limits = *interp.limits();
+    limits::check_input(&limits, text)?;
+    let tokens = lex(text)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    limits::check_nesting(&limits, &tokens)?;
+    let stmt = parse_stmt(tokens)?;
+    let e = match &stmt.value {
+        StmtKind::Expr(e) | StmtKind::Let { e, .. } => e,
+        StmtKind::Def { body, .. } => body,
+    };
+    limits::check_ast(&limits, e, interp.env())?;
+    Ok(Some(stmt))
+}
+
+// LSP positions count UTF-16 code units, `Loc` counts bytes.
+fn position(line: usize, text: &str, mut byte: usize) -> Json {
+    byte = byte.min(text.len());
+    while !text.is_char_boundary(byte) {
+        byte += 1;
+    }
+    json!({"line": line, "character": text[..byte].encode_utf16().count()})
+}
+
+fn range(line: usize, text: &str, loc: &Loc) -> Json {
+    json!({"start": position(line, text, loc.0), "end": position(line, text, loc.1)})
+}
+
+fn byte_offset(text: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+// Errors, and warnings for the lines without errors.
+fn diagnostics(lines: &[Line]) -> Vec<Json> {
+    let mut diags = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let diag = |loc: &Loc, severity: u8, message: String| {
+            json!({
+                "range": range(i, &line.text, loc),
+                "severity": severity,
+                "source": "calc",
+                "message": message,
+            })
+        };
+        match (&line.error, &line.stmt) {
+            (Some(e), _) => {
+                // e.g. an unexpected end of the line
+                let end = Loc(line.text.len(), line.text.len());
+                diags.push(diag(e.loc().unwrap_or(&end), 1, e.to_string()));
+            }
+            (None, Some(stmt)) => {
+                for w in lint_stmt(stmt) {
+                    diags.push(diag(&w.loc, 2, w.to_string()));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    diags
+}
+
+// The innermost subexpression at the byte offset.
+fn node_at(ast: &Ast, offset: usize) -> Option<&Ast> {
+    if !(ast.loc.0 <= offset && offset < ast.loc.1) {
+        return None;
+    }
+    let inner = ast.children().into_iter().find_map(|e| node_at(e, offset));
+    Some(inner.unwrap_or(ast))
+}
+
+fn contains(loc: &Loc, offset: usize) -> bool {
+    loc.0 <= offset && offset < loc.1
+}
+
+// The expression of the statement and the parameters in scope.
+fn stmt_expr(stmt: &Stmt) -> (&Ast, &[Ident]) {
+    match &stmt.value {
+        StmtKind::Expr(e) | StmtKind::Let { e, .. } => (e, &[]),
+        StmtKind::Def { params, body, .. } => (body, params),
+    }
+}
+
+// Evaluate the subexpression under the cursor with the variables of the line.
+fn hover(lines: &[Line], line: usize, offset: usize) -> Json {
+    let l = match lines.get(line) {
+        Some(l) => l,
+        None => return Json::Null,
+    };
+    let stmt = match &l.stmt {
+        Some(stmt) => stmt,
+        None => return Json::Null,
+    };
+    let (e, params) = stmt_expr(stmt);
+    let node = match &stmt.value {
+        StmtKind::Let { name, e } if contains(&name.loc, offset) => e,
+        _ => match node_at(e, offset) {
+            Some(node) => node,
+            None => return Json::Null,
+        },
+    };
+    // the types of the parameters are not known until the function is called
+    if uses(node, params) {
+        return Json::Null;
+    }
+    let t = match check_in(node, &l.env) {
+        Ok(t) => t,
+        Err(_) => return Json::Null,
+    };
+    let mut interp = Interpreter::new();
+    interp.set_limits(Limits::sandbox());
+    *interp.env_mut() = l.env.clone();
+    let value = match interp.eval(node) {
+        Ok(v) => v.to_string(),
+        Err(e) => e.to_string(),
+    };
+    let src = &l.text[node.loc.0..node.loc.1];
+    json!({
+        "contents": {
+            "kind": "markdown",
+            "value": format!("```\n{}\n```\n= `{}` : {}", src, value, t),
+        },
+        "range": range(line, &l.text, &node.loc),
+    })
+}
+
+fn uses(ast: &Ast, params: &[Ident]) -> bool {
+    match &ast.value {
+        AstKind::Var(name) => params.iter().any(|p| &p.value == name),
+        _ => ast.children().into_iter().any(|e| uses(e, params)),
+    }
+}
+
+// The most recent assignment of a variable or definition of a function
+// above the line, or the parameter of the function being defined.
+fn definition(uri: &str, lines: &[Line], line: usize, offset: usize) -> Json {
+    let stmt = match lines.get(line).and_then(|l| l.stmt.as_ref()) {
+        Some(stmt) => stmt,
+        None => return Json::Null,
+    };
+    let (e, params) = stmt_expr(stmt);
+    let (name, is_func) = match node_at(e, offset).map(|node| &node.value) {
+        Some(AstKind::Var(name)) => (name, false),
+        Some(AstKind::Call { func, .. }) if contains(&func.loc, offset) => (&func.value, true),
+        _ => return Json::Null,
+    };
+    let location =
+        |i: usize, loc: &Loc| json!({"uri": uri, "range": range(i, &lines[i].text, loc)});
+    if let Some(p) = params.iter().find(|p| &p.value == name && !is_func) {
+        return location(line, &p.loc);
+    }
+    for i in (0..line).rev() {
+        let found = match lines[i].stmt.as_ref().map(|s| &s.value) {
+            Some(StmtKind::Let { name: n, .. }) if !is_func && &n.value == name => n,
+            Some(StmtKind::Def { name: n, .. }) if is_func && &n.value == name => n,
+            _ => continue,
+        };
+        // a statement which failed did not define anything
+        if lines[i].error.is_none() {
+            return location(i, &found.loc);
+        }
+    }
+    Json::Null
+}
+
+fn completion(lines: &[Line], line: usize) -> Json {
+    const FUNCTION: u32 = 3;
+    const VARIABLE: u32 = 6;
+    let mut items: Vec<_> = builtins::names()
+        .map(|name| json!({"label": name, "kind": FUNCTION, "detail": "built-in"}))
+        .collect();
+    if let Some(l) = lines.get(line) {
+        for (name, f) in l.env.funcs() {
+            let params: Vec<_> = f.params().iter().map(|p| p.value.as_str()).collect();
+            let detail = format!("{}({})", name, params.join(", "));
+            items.push(json!({"label": name, "kind": FUNCTION, "detail": detail}));
+        }
+        for (name, v) in l.env.vars() {
+            items.push(json!({"label": name, "kind": VARIABLE, "detail": v.type_of().to_string()}));
+        }
+    }
+    Json::Array(items)
+}
+
+// Read a message framed with a `Content-Length` header. Returns `None` at
+// the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(n) = header.strip_prefix("Content-Length:") {
+            len = n.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Json::Null)))
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn publish_diagnostics<W: Write>(
+    output: &mut W,
+    uri: &str,
+    diagnostics: Vec<Json>,
+) -> io::Result<()> {
+    let params = json!({"uri": uri, "diagnostics": diagnostics});
+    write_message(
+        output,
+        &json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": params}),
+    )
+}
+
+// Serve the Language Server Protocol over the given streams until `exit`.
+//
+// Every change re-executes the whole document and publishes diagnostics for
+// the lines which fail to lex, parse, type check or evaluate.
+pub fn serve_lsp<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut docs: HashMap<String, Vec<Line>> = HashMap::new();
+    while let Some(msg) = read_message(&mut input)? {
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let offset = docs
+            .get(&uri)
+            .and_then(|lines| lines.get(line))
+            .map(|l| {
+                byte_offset(
+                    &l.text,
+                    params["position"]["character"].as_u64().unwrap_or(0) as usize,
+                )
+            })
+            .unwrap_or(0);
+        let result = match msg["method"].as_str().unwrap_or("") {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": {"name": "calc"},
+            }),
+            "shutdown" => Json::Null,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match params["contentChanges"].as_array() {
+                    // full sync, the last change is the whole document
+                    Some(changes) => changes.last().map(|c| &c["text"]),
+                    None => Some(&params["textDocument"]["text"]),
+                };
+                let lines = analyze(text.and_then(Json::as_str).unwrap_or(""));
+                publish_diagnostics(&mut output, &uri, diagnostics(&lines))?;
+                docs.insert(uri, lines);
+                continue;
+            }
+            "textDocument/didClose" => {
+                docs.remove(&uri);
+                publish_diagnostics(&mut output, &uri, vec![])?;
+                continue;
+            }
+            "textDocument/hover" => match docs.get(&uri) {
+                Some(lines) => hover(lines, line, offset),
+                None => Json::Null,
+            },
+            "textDocument/definition" => match docs.get(&uri) {
+                Some(lines) => definition(&uri, lines, line, offset),
+                None => Json::Null,
+            },
+            "textDocument/completion" => match docs.get(&uri) {
+                Some(lines) => completion(lines, line),
+                None => completion(&[], 0),
+            },
+            method => {
+                // notifications we do not handle are ignored
+                if let Some(id) = id {
+                    let error =
+                        json!({"code": -32601, "message": format!("unknown method: {}", method)});
+                    write_message(
+                        &mut output,
+                        &json!({"jsonrpc": "2.0", "id": id, "error": error}),
+                    )?;
+                }
+                continue;
+            }
+        };
+        if let Some(id) = id {
+            write_message(
+                &mut output,
+                &json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::serve_lsp;
+    use serde_json::{json, Value as Json};
+
+    // Run the server on the messages and return everything it sent.
+    fn drive(messages: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for msg in messages {
+            let body = msg.to_string();
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).bytes());
+        }
+        let mut output = Vec::new();
+        serve_lsp(&input[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|msg| serde_json::from_str(msg.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    fn open(text: &str) -> Json {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": "file:///a.calc", "text": text}},
+        })
+    }
+
+    fn request(id: u64, method: &str, line: u64, character: u64) -> Json {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": {"uri": "file:///a.calc"},
+                "position": {"line": line, "character": character},
+            },
+        })
+    }
+
+    #[test]
+    fn test_diagnostics_warning() {
+        let out = drive(&[open("x = 2\ny = 1/2x\nz = 1/2x +")]);
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["severity"], 2);
+        assert_eq!(
+            diags[0]["range"]["start"],
+            json!({"line": 1, "character": 4})
+        );
+        assert_eq!(diags[1]["severity"], 1);
+    }
+
+    #[test]
+    fn test_diagnostics_limits() {
+        let deep = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        let text = format!("x = sum(i, i, 1, 1000000000)\n{}\ny = 1", deep);
+        let out = drive(&[open(&text)]);
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 2);
+        for (i, diag) in diags.iter().enumerate() {
+            assert_eq!(diag["range"]["start"]["line"], i);
+            assert!(diag["message"].as_str().unwrap().starts_with("limit error"));
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let out = drive(&[open("x = 1\ny = x // 2.0\nz = 1 $ 2\n# ok\nw =")]);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0]["method"], "textDocument/publishDiagnostics");
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        let ranges: Vec<_> = diags.iter().map(|d| d["range"].clone()).collect();
+        let range = |line, s, e| json!({"start": {"line": line, "character": s}, "end": {"line": line, "character": e}});
+        assert_eq!(
+            ranges,
+            vec![range(1, 9, 12), range(2, 6, 7), range(4, 3, 3)]
+        );
+        assert!(diags[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("type error"));
+    }
+
+    #[test]
+    fn test_hover() {
+        let out = drive(&[
+            open("x = 2\nf(a) = a * 10\n(x + 1) * f(x)"),
+            request(1, "textDocument/hover", 2, 3),
+            request(2, "textDocument/hover", 2, 9),
+            request(3, "textDocument/hover", 1, 7),
+        ]);
+        assert_eq!(out[1]["id"], 1);
+        assert_eq!(
+            out[1]["result"]["contents"]["value"],
+            "```\n(x + 1)\n```\n= `3` : int"
+        );
+        assert_eq!(
+            out[2]["result"]["contents"]["value"],
+            "```\n(x + 1) * f(x)\n```\n= `60` : int"
+        );
+        assert_eq!(out[3]["result"], Json::Null);
+    }
+
+    #[test]
+    fn test_definition() {
+        let out = drive(&[
+            open("x = 2\nf(a) = a * x\nx = f(x)"),
+            request(1, "textDocument/definition", 2, 6),
+            request(2, "textDocument/definition", 2, 4),
+            request(3, "textDocument/definition", 1, 7),
+            request(4, "textDocument/definition", 1, 11),
+        ]);
+        let start = |msg: &Json| msg["result"]["range"]["start"].clone();
+        assert_eq!(start(&out[1]), json!({"line": 0, "character": 0}));
+        assert_eq!(start(&out[2]), json!({"line": 1, "character": 0}));
+        assert_eq!(start(&out[3]), json!({"line": 1, "character": 2}));
+        assert_eq!(start(&out[4]), json!({"line": 0, "character": 0}));
+    }
+
+    #[test]
+    fn test_completion() {
+        let out = drive(&[
+            open("x = 2\nf(a) = a\n"),
+            request(1, "textDocument/completion", 2, 0),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "foo"}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+            request(4, "textDocument/completion", 2, 0),
+        ]);
+        let labels: Vec<_> = out[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect();
+        assert!(labels.contains(&"sum".to_string()));
+        assert!(labels.contains(&"f".to_string()));
+        assert!(labels.contains(&"x".to_string()));
+        assert_eq!(out[2]["error"]["code"], -32601);
+        assert_eq!(out[3]["result"], Json::Null);
+        // nothing is answered after `exit`
+        assert_eq!(out.len(), 4);
+    }
+}
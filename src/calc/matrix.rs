@@ -0,0 +1,492 @@
+use super::interp::binop;
+use super::{BinOp, BinOpKind, EvalError, EvalErrorKind, Loc, Value, WordWidth};
+use std::convert::TryFrom;
+
+// Vectors are lists of numbers and matrices are lists of rows, e.g.
+// `[[1, 2], [3, 4]]`. The arithmetic operators work element by element and
+// `@` is the matrix product.
+
+type EvalResult = Result<Value, EvalError>;
+
+// Combine lists of the same length element by element, or each element of
+// a list with a number, e.g. `[1, 2] * 3`. A shape mismatch is reported at
+// the operator.
+pub(super) fn elementwise(
+    word: Option<WordWidth>,
+    op: &BinOp,
+    l: Value,
+    r: Value,
+    loc: &Loc,
+) -> EvalResult {
+    let elems = match (l, r) {
+        (Value::List(xs), Value::List(ys)) => {
+            if xs.len() != ys.len() {
+                let kind = EvalErrorKind::DimensionMismatch {
+                    left: xs.len(),
+                    right: ys.len(),
+                };
+                return Err(EvalError::new(kind, op.loc.clone()));
+            }
+            xs.into_iter()
+                .zip(ys)
+                .map(|(x, y)| binop(word, op, x, y, loc))
+                .collect::<Result<_, _>>()?
+        }
+        (Value::List(xs), y) => xs
+            .into_iter()
+            .map(|x| binop(word, op, x, y.clone(), loc))
+            .collect::<Result<_, _>>()?,
+        (x, Value::List(ys)) => ys
+            .into_iter()
+            .map(|y| binop(word, op, x.clone(), y, loc))
+            .collect::<Result<_, _>>()?,
+        _ => unreachable!("not a list"),
+    };
+    Ok(Value::list(elems))
+}
+
+// The matrix product `l @ r`. A vector on the left is a row and one on the
+// right is a column, so `v @ v` is the dot product and `m @ v` a vector.
+pub(super) fn matmul(
+    word: Option<WordWidth>,
+    op: &BinOp,
+    l: Value,
+    r: Value,
+    loc: &Loc,
+) -> EvalResult {
+    let at = |kind| EvalError::new(kind, op.loc.clone());
+    let (a, row) = rows(l, false).map_err(at)?;
+    let (b, col) = rows(r, true).map_err(at)?;
+    let n = a.first().map_or(0, Vec::len);
+    if n != b.len() {
+        return Err(at(EvalErrorKind::DimensionMismatch {
+            left: n,
+            right: b.len(),
+        }));
+    }
+    // `[]` has no rows to tell the size of the product
+    if n == 0 {
+        return Ok(match (row, col) {
+            (true, true) => Value::Int(0),
+            _ => Value::List(Vec::new()),
+        });
+    }
+    let m = b.first().map_or(0, Vec::len);
+    let mult = BinOp::new(BinOpKind::Mult, op.loc.clone());
+    let add = BinOp::new(BinOpKind::Add, op.loc.clone());
+    let mut c = Vec::new();
+    for a_row in &a {
+        let mut c_row = Vec::new();
+        for j in 0..m {
+            let mut acc = Value::Int(0);
+            for (x, b_row) in a_row.iter().zip(&b) {
+                let p = binop(word, &mult, x.clone(), b_row[j].clone(), loc)?;
+                acc = binop(word, &add, acc, p, loc)?;
+            }
+            c_row.push(acc);
+        }
+        c.push(c_row);
+    }
+    Ok(match (row, col) {
+        (true, true) => c.remove(0).remove(0),
+        (true, false) => Value::list(c.remove(0)),
+        (false, true) => Value::list(c.into_iter().map(|mut r| r.remove(0)).collect()),
+        (false, false) => Value::List(c.into_iter().map(Value::list).collect()),
+    })
+}
+
+// The number of multiply-adds of `l @ r`, which are counted as steps.
+pub(super) fn matmul_steps(l: &Value, r: &Value) -> u64 {
+    let (rows, n) = match l {
+        Value::List(xs) => match xs.first() {
+            Some(Value::List(row)) => (xs.len(), row.len()),
+            _ => (1, xs.len()),
+        },
+        _ => (0, 0),
+    };
+    let m = match r {
+        Value::List(xs) => match xs.first() {
+            Some(Value::List(row)) => row.len(),
+            _ => 1,
+        },
+        _ => 0,
+    };
+    (rows as u64)
+        .saturating_mul(n as u64)
+        .saturating_mul(m as u64)
+}
+
+// The steps of a builtin, about n³ for the elimination of an n × n matrix.
+pub(super) fn builtin_steps(name: &str, args: &[Value]) -> u64 {
+    match (name, args.first()) {
+        ("det" | "inv" | "linsolve", Some(Value::List(rows))) => {
+            (rows.len() as u64).saturating_pow(3)
+        }
+        _ => 0,
+    }
+}
+
+// The rows of a matrix, or of a vector as a single row or as a column, and
+// whether it was a vector.
+fn rows(v: Value, column: bool) -> Result<(Vec<Vec<Value>>, bool), EvalErrorKind> {
+    let xs = match v {
+        Value::List(xs) => xs,
+        _ => unreachable!("rejected by the type checker"),
+    };
+    if let Some(Value::List(_)) = xs.first() {
+        let rows: Vec<_> = xs.into_iter().map(elements).collect();
+        check_rectangular(&rows)?;
+        Ok((rows, false))
+    } else if column {
+        Ok((xs.into_iter().map(|x| vec![x]).collect(), true))
+    } else {
+        Ok((vec![xs], true))
+    }
+}
+
+fn elements(v: Value) -> Vec<Value> {
+    match v {
+        Value::List(xs) => xs,
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+fn check_rectangular<T>(rows: &[Vec<T>]) -> Result<(), EvalErrorKind> {
+    match rows.iter().find(|r| r.len() != rows[0].len()) {
+        Some(r) => Err(EvalErrorKind::RaggedMatrix {
+            expected: rows[0].len(),
+            found: r.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn floats(v: &Value) -> Result<Vec<Vec<f64>>, EvalErrorKind> {
+    let rows: Vec<Vec<f64>> = match v {
+        Value::List(rows) => rows
+            .iter()
+            .map(|r| elements(r.clone()).iter().map(Value::as_f64).collect())
+            .collect(),
+        _ => unreachable!("rejected by the type checker"),
+    };
+    check_rectangular(&rows)?;
+    Ok(rows)
+}
+
+fn square(v: &Value) -> Result<Vec<Vec<f64>>, EvalErrorKind> {
+    let a = floats(v)?;
+    let cols = a.first().map_or(0, Vec::len);
+    if a.len() != cols {
+        return Err(EvalErrorKind::NotSquare {
+            rows: a.len(),
+            cols,
+        });
+    }
+    Ok(a)
+}
+
+pub(super) fn transpose(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let (rows, _) = rows(args[0].clone(), false)?;
+    let cols = rows.first().map_or(0, Vec::len);
+    let t = (0..cols)
+        .map(|j| Value::List(rows.iter().map(|r| r[j].clone()).collect()))
+        .collect();
+    Ok(Value::List(t))
+}
+
+// The determinant, computed exactly for a matrix of integers.
+pub(super) fn det(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let a = square(&args[0])?;
+    let ints: Option<Vec<Vec<i128>>> = elements(args[0].clone())
+        .into_iter()
+        .map(|r| {
+            elements(r)
+                .into_iter()
+                .map(|x| match x {
+                    Value::Int(n) => Some(n as i128),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+    match ints {
+        Some(a) => bareiss(a)
+            .and_then(|d| i64::try_from(d).ok())
+            .map(Value::Int)
+            .ok_or(EvalErrorKind::Overflow),
+        None => Ok(Value::Float(lu_det(a))),
+    }
+}
+
+// Fraction-free elimination, where every division is exact. `None` on
+// overflow.
+fn bareiss(mut a: Vec<Vec<i128>>) -> Option<i128> {
+    let n = a.len();
+    let mut sign = 1;
+    let mut prev = 1;
+    for k in 0..n.saturating_sub(1) {
+        if a[k][k] == 0 {
+            match (k + 1..n).find(|&i| a[i][k] != 0) {
+                Some(i) => {
+                    a.swap(i, k);
+                    sign = -sign;
+                }
+                None => return Some(0),
+            }
+        }
+        for i in k + 1..n {
+            for j in k + 1..n {
+                let x = a[i][j]
+                    .checked_mul(a[k][k])?
+                    .checked_sub(a[i][k].checked_mul(a[k][j])?)?;
+                a[i][j] = x / prev;
+            }
+        }
+        prev = a[k][k];
+    }
+    match n {
+        0 => Some(1),
+        n => a[n - 1][n - 1].checked_mul(sign),
+    }
+}
+
+// Gaussian elimination with partial pivoting.
+fn lu_det(mut a: Vec<Vec<f64>>) -> f64 {
+    let n = a.len();
+    let mut det = 1.0;
+    for k in 0..n {
+        let p = pivot(&a, k);
+        if a[p][k] == 0.0 {
+            return 0.0;
+        }
+        if p != k {
+            a.swap(p, k);
+            det = -det;
+        }
+        det *= a[k][k];
+        for i in k + 1..n {
+            let f = a[i][k] / a[k][k];
+            sub_row(&mut a, i, k, f);
+        }
+    }
+    det
+}
+
+pub(super) fn inv(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let a = square(&args[0])?;
+    let n = a.len();
+    let id = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let x = gauss_jordan(a, id)?;
+    Ok(Value::List(x.into_iter().map(float_list).collect()))
+}
+
+// The solution `x` of `a @ x = b`.
+pub(super) fn linsolve(args: &[Value]) -> Result<Value, EvalErrorKind> {
+    let a = square(&args[0])?;
+    let b: Vec<f64> = elements(args[1].clone())
+        .iter()
+        .map(Value::as_f64)
+        .collect();
+    if a.len() != b.len() {
+        return Err(EvalErrorKind::DimensionMismatch {
+            left: a.len(),
+            right: b.len(),
+        });
+    }
+    let x = gauss_jordan(a, b.into_iter().map(|x| vec![x]).collect())?;
+    Ok(float_list(x.into_iter().map(|r| r[0]).collect()))
+}
+
+fn float_list(xs: Vec<f64>) -> Value {
+    Value::List(xs.into_iter().map(Value::Float).collect())
+}
+
+// Reduce `a` to the identity, applying the same row operations to `b`.
+fn gauss_jordan(
+    mut a: Vec<Vec<f64>>,
+    mut b: Vec<Vec<f64>>,
+) -> Result<Vec<Vec<f64>>, EvalErrorKind> {
+    let n = a.len();
+    let scale = a.iter().flatten().fold(0.0f64, |m, x| m.max(x.abs()));
+    let eps = scale * n as f64 * f64::EPSILON;
+    for k in 0..n {
+        let p = pivot(&a, k);
+        if a[p][k].abs() <= eps {
+            return Err(EvalErrorKind::SingularMatrix);
+        }
+        a.swap(p, k);
+        b.swap(p, k);
+        let d = a[k][k];
+        a[k].iter_mut().for_each(|x| *x /= d);
+        b[k].iter_mut().for_each(|x| *x /= d);
+        for i in (0..n).filter(|&i| i != k) {
+            let f = a[i][k];
+            sub_row(&mut a, i, k, f);
+            sub_row(&mut b, i, k, f);
+        }
+    }
+    Ok(b)
+}
+
+// Subtract `f` times row `k` from row `i`.
+fn sub_row(a: &mut [Vec<f64>], i: usize, k: usize, f: f64) {
+    let row = a[k].clone();
+    for (x, y) in a[i].iter_mut().zip(row) {
+        *x -= f * y;
+    }
+}
+
+// The row from `k` down with the largest entry in column `k`.
+fn pivot(a: &[Vec<f64>], k: usize) -> usize {
+    (k..a.len())
+        .max_by(|&i, &j| a[i][k].abs().total_cmp(&a[j][k].abs()))
+        .unwrap_or(k)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calc::{eval, eval_with, CalcError, EvalErrorKind, Loc, Value};
+    use crate::calc::{Interpreter, LimitErrorKind, Limits};
+
+    fn ints(rows: &[&[i64]]) -> Value {
+        Value::List(
+            rows.iter()
+                .map(|r| Value::List(r.iter().map(|&n| Value::Int(n)).collect()))
+                .collect(),
+        )
+    }
+
+    fn eval_error(input: &str) -> (EvalErrorKind, Loc) {
+        match eval(input) {
+            Err(CalcError::Eval(e)) => (e.value, e.loc),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let m = "[[1, 2], [3, 4]]";
+        assert_eq!(
+            eval(&format!("{} + {}", m, m)),
+            Ok(ints(&[&[2, 4], &[6, 8]]))
+        );
+        assert_eq!(
+            eval(&format!("10 * {}", m)),
+            Ok(ints(&[&[10, 20], &[30, 40]]))
+        );
+        assert_eq!(eval(&format!("-{}", m)), Ok(ints(&[&[-1, -2], &[-3, -4]])));
+        assert_eq!(
+            eval("[1, 2] / 2"),
+            Ok(Value::List(vec![Value::Float(0.5), Value::Float(1.0)]))
+        );
+        assert_eq!(
+            eval_error("[1, 2] + [1, 2, 3]"),
+            (
+                EvalErrorKind::DimensionMismatch { left: 2, right: 3 },
+                Loc(7, 8)
+            )
+        );
+        assert!(eval("[1, 2] // 2").is_err());
+    }
+
+    #[test]
+    fn test_matmul() {
+        let m = "[[1, 2], [3, 4]]";
+        assert_eq!(
+            eval(&format!("{} @ {}", m, m)),
+            Ok(ints(&[&[7, 10], &[15, 22]]))
+        );
+        assert_eq!(
+            eval(&format!("{} @ [1, 1]", m)),
+            Ok(Value::List(vec![Value::Int(3), Value::Int(7)]))
+        );
+        assert_eq!(
+            eval(&format!("[1, 1] @ {}", m)),
+            Ok(Value::List(vec![Value::Int(4), Value::Int(6)]))
+        );
+        assert_eq!(eval("[1, 2, 3] @ [4, 5, 6]"), Ok(Value::Int(32)));
+        assert_eq!(
+            eval_error("[[1, 2, 3]] @ [[1], [2]]"),
+            (
+                EvalErrorKind::DimensionMismatch { left: 3, right: 2 },
+                Loc(12, 13)
+            )
+        );
+        assert_eq!(
+            eval_error("[[1, 2], [3]] @ [1, 2]"),
+            (
+                EvalErrorKind::RaggedMatrix {
+                    expected: 2,
+                    found: 1
+                },
+                Loc(14, 15)
+            )
+        );
+        assert!(eval("1 @ [1]").is_err());
+        assert_eq!(eval("[] @ []"), Ok(Value::Int(0)));
+        assert_eq!(eval("[[]] @ []"), Ok(Value::List(Vec::new())));
+    }
+
+    #[test]
+    fn test_matrix_limits() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::sandbox());
+        let row = |n| Value::List((0..n).map(Value::Int).collect());
+        let square = |n| Value::List((0..n).map(|_| row(n)).collect());
+        interp.env_mut().set("m", square(1000));
+        interp.env_mut().set("a", square(100));
+        for input in &["m @ m @ m", "det(a)", "inv(a)", "linsolve(a, a[0])"] {
+            match eval_with(&mut interp, input) {
+                Err(CalcError::Limit(e)) => {
+                    assert_eq!(e.value, LimitErrorKind::TooManySteps(100_000), "{}", input)
+                }
+                other => panic!("unexpected: {:?}", other),
+            }
+        }
+        interp.env_mut().set("a", square(40));
+        assert!(eval_with(&mut interp, "a @ a").is_ok());
+        assert!(eval_with(&mut interp, "det(a)").is_ok());
+    }
+
+    #[test]
+    fn test_linear_algebra() {
+        assert_eq!(
+            eval("transpose([[1, 2, 3], [4, 5, 6]])"),
+            Ok(ints(&[&[1, 4], &[2, 5], &[3, 6]]))
+        );
+        assert_eq!(eval("det([[1, 2], [3, 4]])"), Ok(Value::Int(-2)));
+        assert_eq!(
+            eval("det([[2, 0, 1], [1, 3, 2], [1, 1, 2]])"),
+            Ok(Value::Int(6))
+        );
+        assert_eq!(eval("det([[0, 1], [1, 0]])"), Ok(Value::Int(-1)));
+        assert_eq!(eval("det([[0.5, 0], [0, 4]])"), Ok(Value::Float(2.0)));
+        assert_eq!(
+            eval("inv([[2, 0], [0, 4]])"),
+            Ok(Value::List(vec![
+                Value::List(vec![Value::Float(0.5), Value::Float(0.0)]),
+                Value::List(vec![Value::Float(0.0), Value::Float(0.25)]),
+            ]))
+        );
+        // x + y = 3, x - y = 1
+        assert_eq!(
+            eval("linsolve([[1, 1], [1, -1]], [3, 1])"),
+            Ok(Value::List(vec![Value::Float(2.0), Value::Float(1.0)]))
+        );
+        assert_eq!(
+            eval_error("inv([[1, 2], [2, 4]])").0,
+            EvalErrorKind::SingularMatrix
+        );
+        assert_eq!(
+            eval_error("det([[1, 2, 3], [4, 5, 6]])").0,
+            EvalErrorKind::NotSquare { rows: 2, cols: 3 }
+        );
+        assert_eq!(
+            eval_error("linsolve([[1, 0], [0, 1]], [1, 2, 3])").0,
+            EvalErrorKind::DimensionMismatch { left: 2, right: 3 }
+        );
+        assert!(eval("det([1, 2])").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use super::{BinOpKind, UniOpKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+// What an operator is parsed into. Operators registered by applications
+// become calls, e.g. `5!` is parsed as `fact(5)`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Action {
+    Uni(UniOpKind),
+    Bin(BinOpKind),
+    Call(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    pub(super) symbol: String,
+    pub(super) fixity: Fixity,
+    pub(super) prec: u8,
+    pub(super) assoc: Assoc,
+    pub(super) action: Action,
+}
+
+// The precedence of prefix operators and of indexing, which binds tighter
+// than any operator.
+pub(super) const PREFIX_PREC: u8 = 70;
+// Implicit multiplication as in `2x` binds tighter than `*` and `/`, so
+// `1/2x` is `1/(2x)` as in formulas from papers, but looser than prefix
+// operators, so `-2x` is `(-2)x`.
+pub(super) const IMPLICIT_PREC: u8 = 61;
+pub(super) const INDEX_PREC: u8 = 80;
+
+// The operators known to the parser, with their precedence: a higher one
+// binds tighter. The built-in operators range from 10 for `|` to 60 for `*`
+// and 70 for prefix `-`, see `BinOpKind::prec`.
+//
+// A symbol is either a word like `mod`, which is lexed as an identifier
+// like `xor`, or a run of punctuation like `!` or `**`. The lexer takes the
+// longest symbol, and a built-in one on a tie, so `**` does not change how
+// `*` is lexed but `/` cannot be registered over `//`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorTable {
+    ops: Vec<Operator>,
+}
+
+impl OperatorTable {
+    // The built-in operators.
+    pub fn new() -> Self {
+        use self::BinOpKind::*;
+        let mut ops = Vec::new();
+        for &(symbol, kind) in &[
+            ("|", BitOr),
+            ("xor", BitXor),
+            ("&", BitAnd),
+            ("<<", Shl),
+            (">>", Shr),
+            ("+", Add),
+            ("-", Sub),
+            ("*", Mult),
+            ("@", MatMul),
+            ("/", Div),
+            ("//", IntDiv),
+        ] {
+            ops.push(Operator {
+                symbol: symbol.to_string(),
+                fixity: Fixity::Infix,
+                prec: kind.prec(),
+                assoc: Assoc::Left,
+                action: Action::Bin(kind),
+            });
+        }
+        for &(symbol, kind) in &[
+            ("+", UniOpKind::Plus),
+            ("-", UniOpKind::Minus),
+            ("~", UniOpKind::BitNot),
+        ] {
+            ops.push(Operator {
+                symbol: symbol.to_string(),
+                fixity: Fixity::Prefix,
+                prec: PREFIX_PREC,
+                assoc: Assoc::Right,
+                action: Action::Uni(kind),
+            });
+        }
+        OperatorTable { ops }
+    }
+
+    // Register an operator which calls the function `func` with its
+    // operands. A symbol can not be both infix and postfix, as `a % b`
+    // would be ambiguous, and the built-in operators can not be replaced.
+    pub fn add(
+        &mut self,
+        symbol: &str,
+        fixity: Fixity,
+        prec: u8,
+        assoc: Assoc,
+        func: &str,
+    ) -> Result<(), String> {
+        if !is_word(symbol) && !is_punct(symbol) {
+            return Err(format!("invalid operator symbol: {}", symbol));
+        }
+        if prec >= INDEX_PREC {
+            return Err(format!("precedence must be less than {}", INDEX_PREC));
+        }
+        let clash = |f: Fixity| match fixity {
+            Fixity::Prefix => f == Fixity::Prefix,
+            Fixity::Infix | Fixity::Postfix => f != Fixity::Prefix,
+        };
+        if self
+            .ops
+            .iter()
+            .any(|op| op.symbol == symbol && clash(op.fixity))
+        {
+            return Err(format!("operator {} is already defined", symbol));
+        }
+        self.ops.push(Operator {
+            symbol: symbol.to_string(),
+            fixity,
+            prec,
+            assoc,
+            action: Action::Call(func.to_string()),
+        });
+        Ok(())
+    }
+
+    pub fn prefix(&mut self, symbol: &str, prec: u8, func: &str) -> Result<(), String> {
+        self.add(symbol, Fixity::Prefix, prec, Assoc::Right, func)
+    }
+
+    pub fn infix(
+        &mut self,
+        symbol: &str,
+        prec: u8,
+        assoc: Assoc,
+        func: &str,
+    ) -> Result<(), String> {
+        self.add(symbol, Fixity::Infix, prec, assoc, func)
+    }
+
+    pub fn postfix(&mut self, symbol: &str, prec: u8, func: &str) -> Result<(), String> {
+        self.add(symbol, Fixity::Postfix, prec, Assoc::Left, func)
+    }
+
+    pub(super) fn find(&self, symbol: &str, fixity: Fixity) -> Option<&Operator> {
+        self.ops
+            .iter()
+            .find(|op| op.symbol == symbol && op.fixity == fixity)
+    }
+
+    // The punctuation symbols registered by applications, for the lexer.
+    pub(super) fn symbols(&self) -> Vec<&str> {
+        self.ops
+            .iter()
+            .filter(|op| matches!(op.action, Action::Call(_)) && is_punct(&op.symbol))
+            .map(|op| op.symbol.as_str())
+            .collect()
+    }
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_word(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Brackets, commas and `#` keep their meaning.
+fn is_punct(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_punctuation() && !"()[],#".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Assoc, OperatorTable};
+    use crate::calc::{eval_with, exec, Interpreter, Value};
+
+    #[test]
+    fn test_operators() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "pct(x) = x / 100").unwrap();
+        exec(&mut interp, "pow(a, b) = a * b * 1000").unwrap();
+        let ops = interp.operators_mut();
+        ops.postfix("!", 75, "fact").unwrap();
+        ops.postfix("%", 75, "pct").unwrap();
+        ops.infix("**", 65, Assoc::Right, "pow").unwrap();
+        ops.infix("mod", 60, Assoc::Left, "rem").unwrap();
+
+        assert_eq!(eval_with(&mut interp, "3! * 2"), Ok(Value::Int(12)));
+        // the postfix operator binds tighter than the prefix minus
+        assert_eq!(eval_with(&mut interp, "-3!"), Ok(Value::Int(-6)));
+        assert_eq!(eval_with(&mut interp, "50%"), Ok(Value::Float(0.5)));
+        assert_eq!(eval_with(&mut interp, "2 * 3 ** 4"), Ok(Value::Int(24000)));
+        // right associative: 1 ** (2 ** 3)
+        assert_eq!(
+            eval_with(&mut interp, "1 ** 2 ** 3"),
+            Ok(Value::Int(6_000_000))
+        );
+        assert!(eval_with(&mut interp, "7 mod 2").is_err());
+        assert_eq!(eval_with(&mut interp, "2 ** 3 * 2"), Ok(Value::Int(12000)));
+    }
+
+    #[test]
+    fn test_operators_invalid() {
+        let mut ops = OperatorTable::new();
+        assert!(ops.postfix("!", 75, "fact").is_ok());
+        assert!(ops.postfix("!", 75, "fact").is_err());
+        assert!(ops.infix("!", 75, Assoc::Left, "f").is_err());
+        assert!(ops.prefix("!", 75, "not").is_ok());
+        assert!(ops.postfix("-", 75, "f").is_err());
+        assert!(ops.postfix("(", 75, "f").is_err());
+        assert!(ops.postfix("a!", 75, "f").is_err());
+        assert!(ops.postfix("^", 80, "f").is_err());
+    }
+}
@@ -0,0 +1,953 @@
+use super::operators::{Action, Assoc, Fixity, Operator, OperatorTable};
+use super::operators::{IMPLICIT_PREC, INDEX_PREC, PREFIX_PREC};
+use super::{Annot, Loc, Token, TokenKind};
+use std::iter::Peekable;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstKind {
+    Int(u64),
+    Float(f64),
+    Imag(f64),
+    Var(String),
+    // `mid ± rad`, only allowed in interval mode. Both sides are number literals.
+    PlusMinus { mid: Box<Ast>, rad: Box<Ast> },
+    List(Vec<Ast>),
+    Index { e: Box<Ast>, index: Box<Ast> },
+    Call { func: Ident, args: Vec<Ast> },
+    UniOp { op: UniOp, e: Box<Ast> },
+    BinOp { op: BinOp, l: Box<Ast>, r: Box<Ast> },
+    // `x -> x * 2` or `(a, b) -> a + b`, see `lambda.rs`
+    Lambda { params: Vec<Ident>, body: Box<Ast> },
+}
+
+pub type Ast = Annot<AstKind>;
+
+pub type Ident = Annot<String>;
+
+impl Ast {
+    fn int(n: u64, loc: Loc) -> Self {
+        Self::new(AstKind::Int(n), loc)
+    }
+    fn float(f: f64, loc: Loc) -> Self {
+        Self::new(AstKind::Float(f), loc)
+    }
+    fn imag(f: f64, loc: Loc) -> Self {
+        Self::new(AstKind::Imag(f), loc)
+    }
+    fn var(name: String, loc: Loc) -> Self {
+        Self::new(AstKind::Var(name), loc)
+    }
+    fn plus_minus(mid: Ast, rad: Ast, loc: Loc) -> Self {
+        Self::new(
+            AstKind::PlusMinus {
+                mid: Box::new(mid),
+                rad: Box::new(rad),
+            },
+            loc,
+        )
+    }
+    fn list(elems: Vec<Ast>, loc: Loc) -> Self {
+        Self::new(AstKind::List(elems), loc)
+    }
+    fn index(e: Ast, index: Ast, loc: Loc) -> Self {
+        Self::new(
+            AstKind::Index {
+                e: Box::new(e),
+                index: Box::new(index),
+            },
+            loc,
+        )
+    }
+    fn call(func: Ident, args: Vec<Ast>, loc: Loc) -> Self {
+        Self::new(AstKind::Call { func, args }, loc)
+    }
+    fn uniop(op: UniOp, e: Ast, loc: Loc) -> Self {
+        Self::new(AstKind::UniOp { op, e: Box::new(e) }, loc)
+    }
+    fn lambda(params: Vec<Ident>, body: Ast, loc: Loc) -> Self {
+        Self::new(
+            AstKind::Lambda {
+                params,
+                body: Box::new(body),
+            },
+            loc,
+        )
+    }
+    // The direct subexpressions in source order.
+    pub(super) fn children(&self) -> Vec<&Ast> {
+        match &self.value {
+            AstKind::Int(_) | AstKind::Float(_) | AstKind::Imag(_) | AstKind::Var(_) => vec![],
+            AstKind::PlusMinus { mid, rad } => vec![mid, rad],
+            AstKind::List(elems) => elems.iter().collect(),
+            AstKind::Index { e, index } => vec![e, index],
+            AstKind::Call { args, .. } => args.iter().collect(),
+            AstKind::UniOp { e, .. } => vec![e],
+            AstKind::BinOp { l, r, .. } => vec![l, r],
+            AstKind::Lambda { body, .. } => vec![body],
+        }
+    }
+    fn binop(op: BinOp, l: Ast, r: Ast, loc: Loc) -> Self {
+        Self::new(
+            AstKind::BinOp {
+                op,
+                l: Box::new(l),
+                r: Box::new(r),
+            },
+            loc,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UniOpKind {
+    Plus,
+    Minus,
+    BitNot,
+}
+
+pub type UniOp = Annot<UniOpKind>;
+
+#[cfg(test)]
+impl UniOp {
+    fn minus(loc: Loc) -> Self {
+        Self::new(UniOpKind::Minus, loc)
+    }
+    fn bit_not(loc: Loc) -> Self {
+        Self::new(UniOpKind::BitNot, loc)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOpKind {
+    Add,
+    Sub,
+    Mult,
+    // matrix product
+    MatMul,
+    Div,
+    IntDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+pub type BinOp = Annot<BinOpKind>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Expr(Ast),
+    // `x = 1 + 2`
+    Let {
+        name: Ident,
+        e: Ast,
+    },
+    // `f(x, y) = x * y`
+    Def {
+        name: Ident,
+        params: Vec<Ident>,
+        body: Ast,
+    },
+}
+
+pub type Stmt = Annot<StmtKind>;
+
+impl Stmt {
+    fn expr(e: Ast) -> Self {
+        let loc = e.loc.clone();
+        Self::new(StmtKind::Expr(e), loc)
+    }
+    fn let_(name: Ident, e: Ast, loc: Loc) -> Self {
+        Self::new(StmtKind::Let { name, e }, loc)
+    }
+    fn def(name: Ident, params: Vec<Ident>, body: Ast, loc: Loc) -> Self {
+        Self::new(StmtKind::Def { name, params, body }, loc)
+    }
+}
+
+impl BinOpKind {
+    fn symbol(self) -> &'static str {
+        use self::BinOpKind::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mult => "*",
+            MatMul => "@",
+            Div => "/",
+            IntDiv => "//",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "xor",
+            Shl => "<<",
+            Shr => ">>",
+        }
+    }
+    // The precedence in the default operator table.
+    pub(super) fn prec(self) -> u8 {
+        use self::BinOpKind::*;
+        match self {
+            BitOr => 10,
+            BitXor => 20,
+            BitAnd => 30,
+            Shl | Shr => 40,
+            Add | Sub => 50,
+            Mult | MatMul | Div | IntDiv => 60,
+        }
+    }
+}
+
+impl Ast {
+    fn prec(&self) -> u8 {
+        match &self.value {
+            _ if self.is_application() => INDEX_PREC,
+            AstKind::BinOp { op, .. } => op.value.prec(),
+            AstKind::UniOp { .. } => PREFIX_PREC,
+            // the body extends as far as it can
+            AstKind::Lambda { .. } => 0,
+            _ => INDEX_PREC,
+        }
+    }
+
+    // An implicit product which may apply a lambda, as in `compose(f, g)(1)`,
+    // and is written so.
+    fn is_application(&self) -> bool {
+        match &self.value {
+            AstKind::BinOp { op, l, .. } if implicit_mult(op) => {
+                matches!(
+                    l.value,
+                    AstKind::Call { .. } | AstKind::Index { .. } | AstKind::Lambda { .. }
+                ) || l.is_application()
+            }
+            _ => false,
+        }
+    }
+}
+
+// Rendered as calc source with the parentheses the precedence requires, so
+// that it parses back to the same tree.
+impl std::fmt::Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let paren = |f: &mut std::fmt::Formatter, e: &Ast, min: u8| {
+            if e.prec() < min {
+                write!(f, "({})", e)
+            } else {
+                write!(f, "{}", e)
+            }
+        };
+        let list = |f: &mut std::fmt::Formatter, es: &[Ast]| {
+            for (i, e) in es.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", e)?;
+            }
+            Ok(())
+        };
+        match &self.value {
+            AstKind::Int(n) => write!(f, "{}", n),
+            AstKind::Float(x) => write!(f, "{:?}", x),
+            AstKind::Imag(x) => write!(f, "{:?}i", x),
+            AstKind::Var(name) => write!(f, "{}", name),
+            AstKind::PlusMinus { mid, rad } => write!(f, "{} ± {}", mid, rad),
+            AstKind::List(elems) => {
+                write!(f, "[")?;
+                list(f, elems)?;
+                write!(f, "]")
+            }
+            AstKind::Index { e, index } => {
+                paren(f, e, INDEX_PREC)?;
+                write!(f, "[{}]", index)
+            }
+            AstKind::Call { func, args } => {
+                write!(f, "{}(", func.value)?;
+                list(f, args)?;
+                write!(f, ")")
+            }
+            AstKind::UniOp { op, e } => {
+                let symbol = match op.value {
+                    UniOpKind::Plus => "+",
+                    UniOpKind::Minus => "-",
+                    UniOpKind::BitNot => "~",
+                };
+                write!(f, "{}", symbol)?;
+                // `-(-x)` rather than `--x`
+                paren(f, e, INDEX_PREC)
+            }
+            AstKind::BinOp { l, r, .. } if self.is_application() => {
+                paren(f, l, INDEX_PREC)?;
+                write!(f, "({})", r)
+            }
+            AstKind::BinOp { op, l, r } => {
+                let prec = op.value.prec();
+                paren(f, l, prec)?;
+                write!(f, " {} ", op.value.symbol())?;
+                paren(f, r, prec + 1)
+            }
+            AstKind::Lambda { params, body } => match params.as_slice() {
+                [param] => write!(f, "{} -> {}", param.value, body),
+                _ => {
+                    let params: Vec<_> = params.iter().map(|p| p.value.as_str()).collect();
+                    write!(f, "({}) -> {}", params.join(", "), body)
+                }
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.value {
+            StmtKind::Expr(e) => write!(f, "{}", e),
+            StmtKind::Let { name, e } => write!(f, "{} = {}", name.value, e),
+            StmtKind::Def { name, params, body } => {
+                let params: Vec<_> = params.iter().map(|p| p.value.as_str()).collect();
+                write!(f, "{}({}) = {}", name.value, params.join(", "), body)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken(Token),
+    NotExpression(Token),
+    NotOperator(Token),
+    UnclosedOpenParen(Token),
+    RedundantExpression(Token),
+    Eof,
+}
+
+impl ParseError {
+    pub fn loc(&self) -> Option<&Loc> {
+        use self::ParseError::*;
+        match self {
+            UnexpectedToken(tok)
+            | NotExpression(tok)
+            | NotOperator(tok)
+            | UnclosedOpenParen(tok)
+            | RedundantExpression(tok) => Some(&tok.loc),
+            Eof => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::ParseError::*;
+        match self {
+            UnexpectedToken(tok) => write!(f, "{}: {:?} is not expected", tok.loc, tok.value),
+            NotExpression(tok) => write!(
+                f,
+                "{}: '{:?}' is not a start of expression",
+                tok.loc, tok.value
+            ),
+            NotOperator(tok) => write!(f, "{}: '{:?}' is not an operator", tok.loc, tok.value),
+            UnclosedOpenParen(tok) => write!(f, "{}: '{:?}' is not closed", tok.loc, tok.value),
+            RedundantExpression(tok) => write!(
+                f,
+                "{}: expression after '{:?}' is redundant",
+                tok.loc, tok.value
+            ),
+            Eof => write!(f, "End of file"),
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+fn default_ops() -> &'static OperatorTable {
+    static OPS: OnceLock<OperatorTable> = OnceLock::new();
+    OPS.get_or_init(OperatorTable::new)
+}
+
+pub fn parse(tokens: Vec<Token>) -> ParseResult<Ast> {
+    parse_with(tokens, default_ops())
+}
+
+// Same as `parse`, with the operators of the table.
+pub fn parse_with(tokens: Vec<Token>, ops: &OperatorTable) -> ParseResult<Ast> {
+    let mut tokens = tokens.into_iter().peekable();
+    let ret = parse_expr(&mut tokens, ops)?;
+    match tokens.next() {
+        Some(tok) => Err(ParseError::RedundantExpression(tok)),
+        None => Ok(ret),
+    }
+}
+
+// STMT   = IDENT "=" EXPR | IDENT "(" PARAMS? ")" "=" EXPR | EXPR ;
+// PARAMS = IDENT ("," IDENT)* ;
+pub fn parse_stmt(tokens: Vec<Token>) -> ParseResult<Stmt> {
+    parse_stmt_with(tokens, default_ops())
+}
+
+pub fn parse_stmt_with(tokens: Vec<Token>, ops: &OperatorTable) -> ParseResult<Stmt> {
+    if !tokens.iter().any(|tok| tok.value == TokenKind::Eq) {
+        return parse_with(tokens, ops).map(Stmt::expr);
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let name = match tokens.next() {
+        Some(Token {
+            value: TokenKind::Ident(name),
+            loc,
+        }) => Ident::new(name, loc),
+        Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+        None => return Err(ParseError::Eof),
+    };
+    let params = match tokens.peek() {
+        Some(Token {
+            value: TokenKind::LParen,
+            ..
+        }) => {
+            let open = tokens.next().unwrap();
+            Some(parse_params(&mut tokens, open)?)
+        }
+        _ => None,
+    };
+    match tokens.next() {
+        Some(Token {
+            value: TokenKind::Eq,
+            ..
+        }) => {}
+        Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+        None => return Err(ParseError::Eof),
+    }
+    let e = parse_expr(&mut tokens, ops)?;
+    if let Some(tok) = tokens.next() {
+        return Err(ParseError::RedundantExpression(tok));
+    }
+    let loc = name.loc.merge(&e.loc);
+    match params {
+        Some(params) => Ok(Stmt::def(name, params, e, loc)),
+        None => Ok(Stmt::let_(name, e, loc)),
+    }
+}
+
+// EXPR    = PREFIX EXPR | POSTFIX (INFIX EXPR | SUFFIX | IMPLICIT)* ;
+// POSTFIX = ATOM ("[" EXPR "]")* ;
+// ATOM    = LITERAL ("±" LITERAL)? | IMAG | IDENT ("(" ARGS? ")")? | "(" EXPR ")" | "[" ARGS? "]"
+//         | LAMBDA ;
+// LAMBDA  = (IDENT | "(" PARAMS? ")") "->" EXPR ;
+// ARGS    = EXPR ("," EXPR)* ;
+// LITERAL = NUMBER | FLOAT ;
+//
+// PREFIX, INFIX and SUFFIX are the operators in the table, which decides
+// their precedence, e.g. `1 + 2 * -3` with the built-in ones. IMPLICIT is
+// an operand which starts with a name or a parenthesis right after another
+// one, as in `2x` or `(1 + 2)(3 + 4)`, and multiplies it, or is the argument
+// of a lambda on the left, as in `compose(f, g)(1)`, which is known from the
+// type. Its operator has an empty location between the operands, see
+// `implicit_mult`.
+fn parse_expr<Tokens>(tokens: &mut Peekable<Tokens>, ops: &OperatorTable) -> ParseResult<Ast>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    parse_prec(tokens, ops, 0)
+}
+
+// Parse an expression whose operators bind at least as tight as `min`.
+fn parse_prec<Tokens>(
+    tokens: &mut Peekable<Tokens>,
+    ops: &OperatorTable,
+    min: u8,
+) -> ParseResult<Ast>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let prefix = tokens
+        .peek()
+        .and_then(|tok| ops.find(symbol(&tok.value)?, Fixity::Prefix));
+    let mut e = match prefix {
+        Some(op) => {
+            let tok = tokens.next().unwrap();
+            let e = parse_prec(tokens, ops, op.prec)?;
+            let loc = tok.loc.merge(&e.loc);
+            apply(op, tok.loc, vec![e], loc)
+        }
+        None => parse_postfix(tokens, ops)?,
+    };
+    loop {
+        let op = tokens.peek().and_then(|tok| {
+            let s = symbol(&tok.value)?;
+            ops.find(s, Fixity::Infix)
+                .or_else(|| ops.find(s, Fixity::Postfix))
+        });
+        let op = match op {
+            Some(op) if op.prec >= min => op,
+            None if IMPLICIT_PREC >= min && starts_operand(tokens.peek(), ops) => {
+                let r = parse_prec(tokens, ops, IMPLICIT_PREC + 1)?;
+                let op = BinOp::new(BinOpKind::Mult, Loc(r.loc.0, r.loc.0));
+                let loc = e.loc.merge(&r.loc);
+                e = Ast::binop(op, e, r, loc);
+                continue;
+            }
+            _ => break,
+        };
+        let tok = tokens.next().unwrap();
+        if op.fixity == Fixity::Postfix {
+            let loc = e.loc.merge(&tok.loc);
+            e = apply(op, tok.loc, vec![e], loc);
+            continue;
+        }
+        let next = match op.assoc {
+            Assoc::Left => op.prec + 1,
+            Assoc::Right => op.prec,
+        };
+        let r = parse_prec(tokens, ops, next)?;
+        let loc = e.loc.merge(&r.loc);
+        e = apply(op, tok.loc, vec![e, r], loc);
+    }
+    Ok(e)
+}
+
+// Whether the token starts the operand of an implicit multiplication. A
+// number does not, as `2 3` is more likely a typo than a product.
+fn starts_operand(tok: Option<&Token>, ops: &OperatorTable) -> bool {
+    match tok.map(|tok| &tok.value) {
+        Some(TokenKind::LParen) => true,
+        Some(TokenKind::Ident(name)) => [Fixity::Prefix, Fixity::Infix, Fixity::Postfix]
+            .iter()
+            .all(|&f| ops.find(name, f).is_none()),
+        _ => false,
+    }
+}
+
+// Whether the product was written without an operator, as in `2x`.
+pub(super) fn implicit_mult(op: &BinOp) -> bool {
+    op.value == BinOpKind::Mult && op.loc.0 == op.loc.1
+}
+
+// The text of a token which may be an operator.
+fn symbol(tok: &TokenKind) -> Option<&str> {
+    let s = match tok {
+        TokenKind::Plus => "+",
+        TokenKind::Minus => "-",
+        TokenKind::Asterisk => "*",
+        TokenKind::At => "@",
+        TokenKind::Slash => "/",
+        TokenKind::SlashSlash => "//",
+        TokenKind::Amp => "&",
+        TokenKind::Pipe => "|",
+        TokenKind::Tilde => "~",
+        TokenKind::Shl => "<<",
+        TokenKind::Shr => ">>",
+        TokenKind::Ident(s) | TokenKind::Operator(s) => s,
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn apply(op: &Operator, op_loc: Loc, mut args: Vec<Ast>, loc: Loc) -> Ast {
+    match &op.action {
+        Action::Uni(kind) => Ast::uniop(UniOp::new(*kind, op_loc), args.remove(0), loc),
+        Action::Bin(kind) => {
+            let r = args.pop().unwrap();
+            let l = args.pop().unwrap();
+            Ast::binop(BinOp::new(*kind, op_loc), l, r, loc)
+        }
+        Action::Call(func) => Ast::call(Ident::new(func.clone(), op_loc), args, loc),
+    }
+}
+
+fn parse_postfix<Tokens>(tokens: &mut Peekable<Tokens>, ops: &OperatorTable) -> ParseResult<Ast>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let mut e = parse_atom(tokens, ops)?;
+    while let Some(Token {
+        value: TokenKind::LBracket,
+        ..
+    }) = tokens.peek()
+    {
+        let open = tokens.next().unwrap();
+        let index = parse_expr(tokens, ops)?;
+        let close = expect_close(tokens, open, TokenKind::RBracket)?;
+        let loc = e.loc.merge(&close.loc);
+        e = Ast::index(e, index, loc);
+    }
+    Ok(e)
+}
+
+fn parse_atom<Tokens>(tokens: &mut Peekable<Tokens>, ops: &OperatorTable) -> ParseResult<Ast>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let tok = tokens.next().ok_or(ParseError::Eof)?;
+    match tok.value {
+        TokenKind::Number(_) | TokenKind::Float(_) => {
+            let mid = parse_number(tok)?;
+            match tokens.peek() {
+                Some(Token {
+                    value: TokenKind::PlusMinus,
+                    ..
+                }) => {
+                    tokens.next();
+                    let rad = parse_number(tokens.next().ok_or(ParseError::Eof)?)?;
+                    let loc = mid.loc.merge(&rad.loc);
+                    Ok(Ast::plus_minus(mid, rad, loc))
+                }
+                _ => Ok(mid),
+            }
+        }
+        TokenKind::Imag(f) => Ok(Ast::imag(f, tok.loc)),
+        TokenKind::Ident(name) => {
+            let func = Ident::new(name, tok.loc);
+            match tokens.peek().map(|tok| &tok.value) {
+                Some(TokenKind::LParen) => {
+                    let open = tokens.next().unwrap();
+                    let (args, close) = parse_args(tokens, ops, open, TokenKind::RParen)?;
+                    let loc = func.loc.merge(&close.loc);
+                    Ok(Ast::call(func, args, loc))
+                }
+                Some(TokenKind::Arrow) => {
+                    tokens.next();
+                    let start = func.loc.clone();
+                    parse_lambda(tokens, ops, vec![func], start)
+                }
+                _ => Ok(Ast::var(func.value, func.loc)),
+            }
+        }
+        TokenKind::LParen => {
+            let start = tok.loc.clone();
+            // `() -> 1`
+            if let Some(TokenKind::RParen) = tokens.peek().map(|tok| &tok.value) {
+                let close = tokens.next().unwrap();
+                return match tokens.peek().map(|tok| &tok.value) {
+                    Some(TokenKind::Arrow) => {
+                        tokens.next();
+                        parse_lambda(tokens, ops, vec![], start)
+                    }
+                    _ => Err(ParseError::NotExpression(close)),
+                };
+            }
+            let e = parse_expr(tokens, ops)?;
+            // `(a, b) -> a + b`, where the first parameter was read as a variable
+            if let (AstKind::Var(name), Some(TokenKind::Comma)) =
+                (&e.value, tokens.peek().map(|tok| &tok.value))
+            {
+                let comma = tokens.next().unwrap();
+                if let Some(TokenKind::RParen) = tokens.peek().map(|tok| &tok.value) {
+                    return Err(ParseError::UnexpectedToken(comma));
+                }
+                let mut params = vec![Ident::new(name.clone(), e.loc)];
+                params.extend(parse_params(tokens, tok)?);
+                expect_arrow(tokens)?;
+                return parse_lambda(tokens, ops, params, start);
+            }
+            let close = expect_close(tokens, tok, TokenKind::RParen)?;
+            match (e.value, tokens.peek().map(|tok| &tok.value)) {
+                // `(x) -> x`
+                (AstKind::Var(name), Some(TokenKind::Arrow)) => {
+                    tokens.next();
+                    let param = Ident::new(name, e.loc);
+                    parse_lambda(tokens, ops, vec![param], start)
+                }
+                (value, _) => Ok(Ast::new(value, start.merge(&close.loc))),
+            }
+        }
+        TokenKind::LBracket => {
+            let start = tok.loc.clone();
+            let (elems, close) = parse_args(tokens, ops, tok, TokenKind::RBracket)?;
+            Ok(Ast::list(elems, start.merge(&close.loc)))
+        }
+        _ => Err(ParseError::NotExpression(tok)),
+    }
+}
+
+// Parse the body of a lambda after its `->`.
+fn parse_lambda<Tokens>(
+    tokens: &mut Peekable<Tokens>,
+    ops: &OperatorTable,
+    params: Vec<Ident>,
+    start: Loc,
+) -> ParseResult<Ast>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let body = parse_expr(tokens, ops)?;
+    let loc = start.merge(&body.loc);
+    Ok(Ast::lambda(params, body, loc))
+}
+
+// The `->` after the parameters of a lambda.
+fn expect_arrow<Tokens>(tokens: &mut Peekable<Tokens>) -> ParseResult<()>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    match tokens.next() {
+        Some(Token {
+            value: TokenKind::Arrow,
+            ..
+        }) => Ok(()),
+        Some(tok) => Err(ParseError::UnexpectedToken(tok)),
+        None => Err(ParseError::Eof),
+    }
+}
+
+fn parse_number(tok: Token) -> ParseResult<Ast> {
+    match tok.value {
+        TokenKind::Number(n) => Ok(Ast::int(n, tok.loc)),
+        TokenKind::Float(f) => Ok(Ast::float(f, tok.loc)),
+        _ => Err(ParseError::NotExpression(tok)),
+    }
+}
+
+// Parse comma separated expressions up to the `close` token, e.g. `1, 2, 3]`.
+fn parse_args<Tokens>(
+    tokens: &mut Peekable<Tokens>,
+    ops: &OperatorTable,
+    open: Token,
+    close: TokenKind,
+) -> ParseResult<(Vec<Ast>, Token)>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let mut args = Vec::new();
+    if tokens.peek().map(|tok| tok.value == close) == Some(true) {
+        return Ok((args, tokens.next().unwrap()));
+    }
+    loop {
+        args.push(parse_expr(tokens, ops)?);
+        match tokens.next() {
+            Some(Token {
+                value: TokenKind::Comma,
+                ..
+            }) => continue,
+            Some(tok) if tok.value == close => return Ok((args, tok)),
+            Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+            None => return Err(ParseError::UnclosedOpenParen(open)),
+        }
+    }
+}
+
+// Parse comma separated parameter names up to `)`, e.g. `x, y)`.
+fn parse_params<Tokens>(tokens: &mut Peekable<Tokens>, open: Token) -> ParseResult<Vec<Ident>>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let mut params = Vec::new();
+    loop {
+        match tokens.next() {
+            Some(Token {
+                value: TokenKind::RParen,
+                ..
+            }) if params.is_empty() => return Ok(params),
+            Some(Token {
+                value: TokenKind::Ident(name),
+                loc,
+            }) => params.push(Ident::new(name, loc)),
+            Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+            None => return Err(ParseError::UnclosedOpenParen(open)),
+        }
+        match tokens.next() {
+            Some(Token {
+                value: TokenKind::Comma,
+                ..
+            }) => continue,
+            Some(Token {
+                value: TokenKind::RParen,
+                ..
+            }) => return Ok(params),
+            Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+            None => return Err(ParseError::UnclosedOpenParen(open)),
+        }
+    }
+}
+
+fn expect_close<Tokens>(
+    tokens: &mut Peekable<Tokens>,
+    open: Token,
+    close: TokenKind,
+) -> ParseResult<Token>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    match tokens.next() {
+        Some(tok) if tok.value == close => Ok(tok),
+        Some(tok) => Err(ParseError::RedundantExpression(tok)),
+        None => Err(ParseError::UnclosedOpenParen(open)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse, parse_stmt, Ast, AstKind, BinOp, BinOpKind, Ident, ParseError, Stmt, UniOp,
+    };
+    use crate::calc::{lex, Loc};
+
+    #[test]
+    fn test_parse() {
+        let ast = parse(lex("1 + 2 * -3").unwrap());
+        assert_eq!(
+            ast,
+            Ok(Ast::binop(
+                BinOp::new(BinOpKind::Add, Loc(2, 3)),
+                Ast::int(1, Loc(0, 1)),
+                Ast::binop(
+                    BinOp::new(BinOpKind::Mult, Loc(6, 7)),
+                    Ast::int(2, Loc(4, 5)),
+                    Ast::uniop(UniOp::minus(Loc(8, 9)), Ast::int(3, Loc(9, 10)), Loc(8, 10)),
+                    Loc(4, 10),
+                ),
+                Loc(0, 10),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_paren() {
+        let ast = parse(lex("(1.5 - 2) // 3").unwrap()).unwrap();
+        assert_eq!(ast.loc, Loc(0, 14));
+        assert_eq!(
+            ast,
+            Ast::binop(
+                BinOp::new(BinOpKind::IntDiv, Loc(10, 12)),
+                Ast::binop(
+                    BinOp::new(BinOpKind::Sub, Loc(5, 6)),
+                    Ast::float(1.5, Loc(1, 4)),
+                    Ast::int(2, Loc(7, 8)),
+                    Loc(0, 9),
+                ),
+                Ast::int(3, Loc(13, 14)),
+                Loc(0, 14),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_bitwise() {
+        // `|` < `xor` < `&` < shifts < `+`
+        let ast = parse(lex("1 | 2 xor 3 & 4 << 5 + 6").unwrap()).unwrap();
+        let kinds = |ast: &Ast| match &ast.value {
+            AstKind::BinOp { op, r, .. } => (op.value, r.loc.clone()),
+            _ => panic!("not a binop"),
+        };
+        assert_eq!(kinds(&ast), (BinOpKind::BitOr, Loc(4, 24)));
+        match &ast.value {
+            AstKind::BinOp { r, .. } => {
+                assert_eq!(kinds(r), (BinOpKind::BitXor, Loc(10, 24)));
+            }
+            _ => unreachable!(),
+        }
+        let ast = parse(lex("~1").unwrap()).unwrap();
+        assert_eq!(
+            ast,
+            Ast::uniop(UniOp::bit_not(Loc(0, 1)), Ast::int(1, Loc(1, 2)), Loc(0, 2))
+        );
+    }
+
+    #[test]
+    fn test_parse_var() {
+        let ast = parse(lex("x * f(y)").unwrap()).unwrap();
+        assert_eq!(
+            ast,
+            Ast::binop(
+                BinOp::new(BinOpKind::Mult, Loc(2, 3)),
+                Ast::var("x".to_string(), Loc(0, 1)),
+                Ast::call(
+                    Ident::new("f".to_string(), Loc(4, 5)),
+                    vec![Ast::var("y".to_string(), Loc(6, 7))],
+                    Loc(4, 8)
+                ),
+                Loc(0, 8)
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_stmt() {
+        let ident = |name: &str, loc| Ident::new(name.to_string(), loc);
+        assert_eq!(
+            parse_stmt(lex("x = 1").unwrap()),
+            Ok(Stmt::let_(
+                ident("x", Loc(0, 1)),
+                Ast::int(1, Loc(4, 5)),
+                Loc(0, 5)
+            ))
+        );
+        assert_eq!(
+            parse_stmt(lex("f(a, b) = a").unwrap()),
+            Ok(Stmt::def(
+                ident("f", Loc(0, 1)),
+                vec![ident("a", Loc(2, 3)), ident("b", Loc(5, 6))],
+                Ast::var("a".to_string(), Loc(10, 11)),
+                Loc(0, 11)
+            ))
+        );
+        assert_eq!(
+            parse_stmt(lex("1 + 2").unwrap()),
+            Ok(Stmt::expr(Ast::binop(
+                BinOp::new(BinOpKind::Add, Loc(2, 3)),
+                Ast::int(1, Loc(0, 1)),
+                Ast::int(2, Loc(4, 5)),
+                Loc(0, 5)
+            )))
+        );
+        match parse_stmt(lex("f(1) = 2").unwrap()) {
+            Err(ParseError::UnexpectedToken(tok)) => assert_eq!(tok.loc, Loc(2, 3)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match parse_stmt(lex("x + 1 = 2").unwrap()) {
+            Err(ParseError::UnexpectedToken(tok)) => assert_eq!(tok.loc, Loc(2, 3)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(parse_stmt(lex("x =").unwrap()), Err(ParseError::Eof));
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("1-(2-3)", "1 - (2 - 3)"),
+            ("(1-2)-3", "1 - 2 - 3"),
+            ("- -x", "-(-x)"),
+            ("-(1+2)[0]", "-(1 + 2)[0]"),
+            ("[1, 2.5, 3i][x]", "[1, 2.5, 3.0i][x]"),
+            (
+                "max([1,2]) << 1 & 7 xor 1 | 8",
+                "max([1, 2]) << 1 & 7 xor 1 | 8",
+            ),
+            ("1 ± 0.1 * 2", "1 ± 0.1 * 2"),
+            ("map(xs, x->x*2)", "map(xs, x -> x * 2)"),
+            ("(a,b)->a+b", "(a, b) -> a + b"),
+            ("(x) -> y -> x - y", "x -> y -> x - y"),
+            ("(x -> x) + 1", "(x -> x) + 1"),
+            ("()->1", "() -> 1"),
+            ("compose(f,g)(1)(x+1)", "compose(f, g)(1)(x + 1)"),
+            ("(x->x)(2) + fs[0](1)", "(x -> x)(2) + fs[0](1)"),
+            ("2x", "2 * x"),
+        ];
+        for (input, expected) in cases.iter() {
+            let ast = parse(lex(input).unwrap()).unwrap();
+            assert_eq!(ast.to_string(), *expected);
+            let again = parse(lex(expected).unwrap()).unwrap();
+            assert_eq!(again.to_string(), *expected);
+        }
+        let stmt = parse_stmt(lex("f(a,b)=a*(b+1)").unwrap()).unwrap();
+        assert_eq!(stmt.to_string(), "f(a, b) = a * (b + 1)");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(parse(lex("1 +").unwrap()), Err(ParseError::Eof));
+        match parse(lex("(1 + 2").unwrap()) {
+            Err(ParseError::UnclosedOpenParen(tok)) => assert_eq!(tok.loc, Loc(0, 1)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match parse(lex("1 2").unwrap()) {
+            Err(ParseError::RedundantExpression(tok)) => assert_eq!(tok.loc, Loc(2, 3)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match parse(lex("(a, 1) -> a").unwrap()) {
+            Err(ParseError::UnexpectedToken(tok)) => assert_eq!(tok.loc, Loc(4, 5)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match parse(lex("(a, b) + 1").unwrap()) {
+            Err(ParseError::UnexpectedToken(tok)) => assert_eq!(tok.loc, Loc(7, 8)),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use super::interp::unwrap_word;
+use super::{check_in, compile, forms, lex_with, limits, parse_with};
+use super::{Ast, AstKind, CalcError, EvalError, EvalErrorKind, Interpreter, Loc, Token};
+use super::{Type, TypeError, Value};
+use crate::format::{Formatter, Notation};
+
+// The size of the area a function is drawn in, in characters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chart {
+    pub width: usize,
+    pub height: usize,
+    // Unicode braille characters hold 2 x 4 dots each, otherwise every
+    // character is a single point drawn as `*`.
+    pub braille: bool,
+}
+
+impl Chart {
+    pub fn new() -> Self {
+        Chart {
+            width: 64,
+            height: 16,
+            braille: true,
+        }
+    }
+
+    // The number of points across and down.
+    fn dots(&self) -> (usize, usize) {
+        if self.braille {
+            (self.width * 2, self.height * 4)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+impl Default for Chart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Draw `expr, x, lo, hi`, the real expression as a function of the
+// variable `x` over `[lo, hi]` with one sample per column of dots. A sample
+// where the expression is undefined, e.g. a division by zero, leaves a gap.
+// The labels are rounded to 3 significant digits and written in `fmt`.
+pub fn plot(
+    interp: &mut Interpreter,
+    input: &str,
+    chart: &Chart,
+    fmt: &Formatter,
+) -> Result<String, CalcError> {
+    let args = parse_args(interp, input)?;
+    if args.len() != 4 {
+        return Err(TypeError::arg_count(4, args.len(), Loc(0, input.len())).into());
+    }
+    let x =
+        forms::bound_var(&args).ok_or_else(|| TypeError::expected_variable(args[1].loc.clone()))?;
+    let lo = real(interp, &args[2])?;
+    let hi = real(interp, &args[3])?;
+    if lo >= hi || !(hi - lo).is_finite() {
+        let kind = EvalErrorKind::InvalidInterval { lo, hi };
+        return Err(EvalError::new(kind, Loc(args[2].loc.0, args[3].loc.1)).into());
+    }
+
+    // the variable shadows one of the same name while the samples are taken
+    let mut scope = Interpreter::new();
+    scope.set_word_width(interp.word_width());
+    *scope.env_mut() = interp.env().clone();
+    scope.env_mut().set(x, Value::Float(lo));
+    limits::check_ast(interp.limits(), &args[0], scope.env())?;
+    expect_real(check_in(&args[0], scope.env())?, &args[0].loc)?;
+    let f = compile(&scope, &args[0])?;
+    let mut env = scope.env().clone();
+
+    let (cols, rows) = chart.dots();
+    let mut samples = Vec::with_capacity(cols);
+    for i in 0..cols {
+        let v = lo + (hi - lo) * i as f64 / (cols - 1).max(1) as f64;
+        env.set(x, Value::Float(v));
+        samples.push(match f(&env) {
+            Ok(y) => Some(unwrap_word(scope.word_width(), y).as_f64()).filter(|y| y.is_finite()),
+            Err(CalcError::Eval(_)) => None,
+            Err(e) => return Err(e),
+        });
+    }
+
+    let (ymin, ymax) = y_range(&samples);
+    let row = |y: f64| {
+        let r = ((ymax - y) / (ymax - ymin) * (rows - 1) as f64).round();
+        if (0.0..=(rows - 1) as f64).contains(&r) {
+            Some(r as usize)
+        } else {
+            None
+        }
+    };
+    let mut canvas = Canvas::new(chart);
+    let mut last = None;
+    for (col, y) in samples.iter().enumerate() {
+        let r = y.and_then(row);
+        if let Some(r) = r {
+            canvas.set(col, r);
+            // join the points of a steep part, but not across a pole
+            if let Some(prev) = last.filter(|&prev: &usize| prev.abs_diff(r) <= rows / 2) {
+                for between in prev.min(r) + 1..prev.max(r) {
+                    let near = if between.abs_diff(prev) <= between.abs_diff(r) {
+                        col - 1
+                    } else {
+                        col
+                    };
+                    canvas.set(near, between);
+                }
+            }
+        }
+        last = r;
+    }
+
+    let axis_row = row(0.0).map(|r| r / (rows / chart.height));
+    let axis_col = if lo <= 0.0 && 0.0 <= hi {
+        let c = (-lo / (hi - lo) * (cols - 1) as f64).round() as usize;
+        Some(c / (cols / chart.width))
+    } else {
+        None
+    };
+    // rounded to 3 digits, in full unless that would take too many
+    let label = |y: f64| {
+        let y: f64 = format!("{:.2e}", y).parse().unwrap();
+        let notation = if y == 0.0 || (1e-5..1e15).contains(&y.abs()) {
+            Notation::Auto
+        } else {
+            Notation::Scientific(2)
+        };
+        Formatter { notation, ..*fmt }.real(y)
+    };
+    Ok(canvas.render(
+        (&label(ymax), &label(ymin)),
+        (&label(lo), x, &label(hi)),
+        (axis_row, axis_col),
+    ))
+}
+
+// The arguments are parsed like those of a call, so that a comma inside
+// a list or a call does not split them. The call is not in the input,
+// the locations are those of the input.
+fn parse_args(interp: &Interpreter, input: &str) -> Result<Vec<Ast>, CalcError> {
+    let limits = interp.limits();
+    limits::check_input(limits, input)?;
+    let mut tokens = vec![Token::ident("plot", Loc(0, 0)), Token::lparen(Loc(0, 0))];
+    tokens.extend(lex_with(input, interp.operators())?);
+    tokens.push(Token::rparen(Loc(input.len(), input.len())));
+    limits::check_nesting(limits, &tokens)?;
+    match parse_with(tokens, interp.operators())?.value {
+        AstKind::Call { args, .. } => Ok(args),
+        _ => unreachable!("parsed as a call"),
+    }
+}
+
+fn real(interp: &mut Interpreter, e: &Ast) -> Result<f64, CalcError> {
+    expect_real(check_in(e, interp.env())?, &e.loc)?;
+    let v = interp.eval(e)?;
+    Ok(unwrap_word(interp.word_width(), v).as_f64())
+}
+
+fn expect_real(t: Type, loc: &Loc) -> Result<(), TypeError> {
+    if t.is_real() {
+        Ok(())
+    } else {
+        Err(TypeError::mismatch(Type::Float, t, loc.clone()))
+    }
+}
+
+// The values shown, those of all samples unless a few of them are far
+// apart from the rest, as near a pole of `1 / x`. Those are cut off.
+fn y_range(samples: &[Option<f64>]) -> (f64, f64) {
+    let mut ys: Vec<f64> = samples.iter().flatten().copied().collect();
+    ys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (min, max) = match (ys.first(), ys.last()) {
+        (Some(&min), Some(&max)) => (min, max),
+        _ => return (-1.0, 1.0),
+    };
+    let cut = ys.len() / 20;
+    let (lo, hi) = (ys[cut], ys[ys.len() - 1 - cut]);
+    let (min, max) = if hi > lo && max - min > 10.0 * (hi - lo) {
+        (lo - (hi - lo) / 10.0, hi + (hi - lo) / 10.0)
+    } else {
+        (min, max)
+    };
+    if min == max {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    }
+}
+
+// The dots of a chart, a bit per dot of each character.
+struct Canvas {
+    braille: bool,
+    cells: Vec<Vec<u8>>,
+}
+
+impl Canvas {
+    fn new(chart: &Chart) -> Self {
+        Canvas {
+            braille: chart.braille,
+            cells: vec![vec![0; chart.width]; chart.height],
+        }
+    }
+
+    fn set(&mut self, col: usize, row: usize) {
+        if self.braille {
+            // the dots of a braille character are numbered down the left
+            // column, then down the right one, with the bottom row last
+            const BITS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+            self.cells[row / 4][col / 2] |= BITS[row % 4][col % 2];
+        } else {
+            self.cells[row][col] = 1;
+        }
+    }
+
+    // The chart with the values at the top and the bottom on the left, and
+    // those of the ends and the name of the variable underneath. The axes
+    // are drawn where they are in sight and there is no dot.
+    fn render(
+        &self,
+        (top, bottom): (&str, &str),
+        (lo, x, hi): (&str, &str, &str),
+        (axis_row, axis_col): (Option<usize>, Option<usize>),
+    ) -> String {
+        let (h, v, cross, tick, corner) = if self.braille {
+            ('─', '│', '┼', '┤', '└')
+        } else {
+            ('-', '|', '+', '+', '+')
+        };
+        let height = self.cells.len();
+        let width = self.cells[0].len();
+        let margin = top.len().max(bottom.len()).max(1);
+        let mut out = String::new();
+        for (r, cells) in self.cells.iter().enumerate() {
+            let (label, edge) = match r {
+                0 => (top, tick),
+                _ if r == height - 1 => (bottom, tick),
+                _ if Some(r) == axis_row => ("0", tick),
+                _ => ("", v),
+            };
+            out.push_str(&format!("{:>w$} {}", label, edge, w = margin));
+            for (c, &bits) in cells.iter().enumerate() {
+                out.push(match (bits, Some(r) == axis_row, Some(c) == axis_col) {
+                    (0, true, true) => cross,
+                    (0, true, false) => h,
+                    (0, false, true) => v,
+                    (0, false, false) => ' ',
+                    _ if self.braille => std::char::from_u32(0x2800 + bits as u32).unwrap(),
+                    _ => '*',
+                });
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!("{:>w$} {}", "", corner, w = margin));
+        out.extend(std::iter::repeat_n(h, width));
+        out.push('\n');
+
+        // `lo` and `hi` under the ends, `x` in the middle if there is room
+        let mut axis = format!("{:>w$}  {}", "", lo, w = margin);
+        let middle = margin + 2 + width / 2 - x.len() / 2;
+        if axis.len() + 1 < middle && middle + x.len() + 1 + hi.len() <= margin + 2 + width {
+            axis.push_str(&" ".repeat(middle - axis.len()));
+            axis.push_str(x);
+        }
+        let end = (margin + 2 + width).saturating_sub(hi.len());
+        axis.push_str(&" ".repeat(end.saturating_sub(axis.len()).max(1)));
+        axis.push_str(hi);
+        out.push_str(&axis);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{plot, Chart};
+    use crate::calc::{exec, CalcError, EvalErrorKind, Interpreter, TypeErrorKind};
+    use crate::format::Formatter;
+
+    fn ascii(width: usize, height: usize) -> Chart {
+        Chart {
+            width,
+            height,
+            braille: false,
+        }
+    }
+
+    fn lines(chart: &str) -> Vec<&str> {
+        chart.lines().collect()
+    }
+
+    #[test]
+    fn test_plot() {
+        let mut interp = Interpreter::new();
+        let fmt = Formatter::new();
+        let chart = plot(&mut interp, "x, x, -2, 2", &ascii(5, 5), &fmt).unwrap();
+        let expected = [
+            " 2 +  | *",
+            "   |  |* ",
+            " 0 +--*--",
+            "   | *|  ",
+            "-2 +* |  ",
+            "   +-----",
+            "    -2  2",
+        ];
+        assert_eq!(lines(&chart), expected);
+
+        // the labels are rounded
+        exec(&mut interp, "f(t) = t * t * t").unwrap();
+        let chart = plot(&mut interp, "f(x) / 3, x, 0, 1", &ascii(12, 6), &fmt).unwrap();
+        let expected = [
+            "0.333 +|          *",
+            "      ||         * ",
+            "      ||        *  ",
+            "      ||       *   ",
+            "      ||     **    ",
+            "    0 +******------",
+            "      +------------",
+            "       0     x    1",
+        ];
+        assert_eq!(lines(&chart), expected);
+    }
+
+    #[test]
+    fn test_plot_extreme_labels() {
+        let mut interp = Interpreter::new();
+        let fmt = Formatter::new();
+        let chart = plot(&mut interp, "1e308 * x, x, -10, 10", &ascii(12, 4), &fmt).unwrap();
+        let expected = [
+            " 9.09e307 +------*-----",
+            "          |      |     ",
+            "          |      |     ",
+            "-9.09e307 +      |     ",
+            "          +------------",
+            "           -10   x   10",
+        ];
+        assert_eq!(lines(&chart), expected);
+        let chart = plot(&mut interp, "x, x, 1e-300, 2e-300", &ascii(12, 4), &fmt).unwrap();
+        let expected = [
+            "2.00e-300 +          **",
+            "          |      ****  ",
+            "          |  ****      ",
+            "1.00e-300 +**          ",
+            "          +------------",
+            "           1.00e-300 2.00e-300",
+        ];
+        assert_eq!(lines(&chart), expected);
+    }
+
+    #[test]
+    fn test_plot_undefined() {
+        let mut interp = Interpreter::new();
+        let chart = plot(
+            &mut interp,
+            "1 / x, x, -1, 1",
+            &ascii(21, 7),
+            &Formatter::new(),
+        )
+        .unwrap();
+        // the pole at 0 is a gap, the values around it are cut off
+        for line in &lines(&chart)[..7] {
+            assert_ne!(
+                line.chars().nth(line.find(['+', '|']).unwrap() + 11),
+                Some('*')
+            );
+        }
+        assert!(chart.contains('*'));
+
+        let chart = plot(
+            &mut interp,
+            "1 / (x - x), x, 0, 1",
+            &Chart::new(),
+            &Formatter::new(),
+        )
+        .unwrap();
+        assert!(!chart
+            .chars()
+            .any(|c| ('\u{2801}'..='\u{28ff}').contains(&c)));
+    }
+
+    #[test]
+    fn test_plot_errors() {
+        let mut interp = Interpreter::new();
+        let mut error =
+            |input| plot(&mut interp, input, &Chart::new(), &Formatter::new()).unwrap_err();
+        match error("x, x, 1") {
+            CalcError::Type(e) => {
+                assert_eq!(
+                    e.value,
+                    TypeErrorKind::ArgCount {
+                        expected: 4,
+                        found: 3
+                    }
+                )
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        match error("x, 1, 0, 1") {
+            CalcError::Type(e) => assert_eq!(e.value, TypeErrorKind::ExpectedVariable),
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(matches!(error("x * 1i, x, 0, 1"), CalcError::Type(_)));
+        assert!(matches!(error("y, x, 0, 1"), CalcError::Type(_)));
+        match error("x, x, 1, 0") {
+            CalcError::Eval(e) => {
+                assert_eq!(e.value, EvalErrorKind::InvalidInterval { lo: 1.0, hi: 0.0 })
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(matches!(error("x, x, (0, 1"), CalcError::Parse(_)));
+    }
+}
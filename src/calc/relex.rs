@@ -0,0 +1,153 @@
+use super::{lex_symbols, Annot, LexError, Loc, OperatorTable, Token};
+use std::ops::Range;
+
+// A change of the text, replacing the bytes in the range of the old text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: &str) -> Self {
+        TextEdit {
+            range,
+            text: text.to_string(),
+        }
+    }
+}
+
+// Same as `lex` of the edited input, given the tokens of the input before
+// the edit, but lexing only the lines the edit touches.
+pub fn relex(input: &str, tokens: &[Token], edit: &TextEdit) -> Result<Vec<Token>, LexError> {
+    relex_symbols(input, tokens, edit, &[])
+}
+
+// Same as `relex`, for the tokens from `lex_with`.
+pub fn relex_with(
+    input: &str,
+    tokens: &[Token],
+    edit: &TextEdit,
+    ops: &OperatorTable,
+) -> Result<Vec<Token>, LexError> {
+    relex_symbols(input, tokens, edit, &ops.symbols())
+}
+
+// No token, not even a comment, goes over a newline, so the lexer is at the
+// same state at the start of each line whatever came before it. The lines
+// from the one of the start of the edit to the one of its end are lexed
+// again, and the tokens after them are moved by the change of the length.
+// An edit which does not fit the input, as from a client out of step, is
+// ignored and the whole input lexed.
+fn relex_symbols(
+    input: &str,
+    tokens: &[Token],
+    edit: &TextEdit,
+    symbols: &[&str],
+) -> Result<Vec<Token>, LexError> {
+    let bytes = input.as_bytes();
+    let new_end = edit.range.start.saturating_add(edit.text.len());
+    if edit.range.start > edit.range.end || new_end > bytes.len() {
+        return lex_symbols(input, symbols);
+    }
+    let from = bytes[..edit.range.start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |p| p + 1);
+    let to = bytes[new_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |p| new_end + p + 1);
+    // the position in the old input of `to`
+    let old_to = to - new_end + edit.range.end;
+
+    let before = tokens.partition_point(|t| t.loc.0 < from);
+    let after = tokens.partition_point(|t| t.loc.0 < old_to);
+    let lexed = lex_symbols(&input[from..to], symbols).map_err(|e| shift(e, 0, from))?;
+    let mut relexed = tokens[..before].to_vec();
+    relexed.extend(lexed.into_iter().map(|t| shift(t, 0, from)));
+    relexed.extend(
+        tokens[after..]
+            .iter()
+            .cloned()
+            .map(|t| shift(t, old_to, to)),
+    );
+    Ok(relexed)
+}
+
+// Move what is at `old` to `new`.
+fn shift<T>(mut annot: Annot<T>, old: usize, new: usize) -> Annot<T> {
+    annot.loc = Loc(annot.loc.0 - old + new, annot.loc.1 - old + new);
+    annot
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calc::{lex, lex_with, Assoc};
+
+    // Every edit of the input replacing up to 4 bytes with the text.
+    fn check_edits(input: &str, text: &str) {
+        let tokens = lex(input).unwrap();
+        for start in 0..=input.len() {
+            for end in start..=(start + 4).min(input.len()) {
+                let edited = format!("{}{}{}", &input[..start], text, &input[end..]);
+                let edit = TextEdit::new(start..end, text);
+                assert_eq!(
+                    relex(&edited, &tokens, &edit),
+                    lex(&edited),
+                    "{:?} edited by {:?}",
+                    input,
+                    edit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_relex() {
+        let script =
+            "x = 0x1f + 2.5e-3\n# sum\nf(a, b) = a // b # div\n\n  3i * [1, 2]\nf(x, 1e3) -> y";
+        for text in &["", "1", "e", "+", "#", "\n", "-", "x\ny", " ", "0x", "// 7"] {
+            check_edits(script, text);
+        }
+    }
+
+    #[test]
+    fn test_relex_error() {
+        let input = "1 + 2\n3 * 4";
+        let tokens = lex(input).unwrap();
+        let edit = TextEdit::new(8..9, "$");
+        let e = relex("1 + 2\n3 $ 4", &tokens, &edit).unwrap_err();
+        assert_eq!(e, LexError::invalid_char('$', Loc(8, 9)));
+    }
+
+    #[test]
+    fn test_relex_bad_edit() {
+        let input = "1 + 2\n3 * 4";
+        let tokens = lex(input).unwrap();
+        for edit in &[
+            TextEdit::new(Range { start: 5, end: 2 }, ""),
+            TextEdit::new(20..20, ""),
+            TextEdit::new(8..9, "too long for the input"),
+            TextEdit::new(usize::MAX..usize::MAX, "x"),
+        ] {
+            assert_eq!(relex(input, &tokens, edit), lex(input), "{:?}", edit);
+        }
+    }
+
+    #[test]
+    fn test_relex_with() {
+        let mut ops = OperatorTable::new();
+        ops.infix("++", 60, Assoc::Left, "add").unwrap();
+        ops.infix("+++", 60, Assoc::Left, "add").unwrap();
+        let input = "1 ++ 2\n3";
+        let tokens = lex_with(input, &ops).unwrap();
+        let edited = "1 +++ 2\n3";
+        let edit = TextEdit::new(4..4, "+");
+        assert_eq!(
+            relex_with(edited, &tokens, &edit, &ops),
+            lex_with(edited, &ops)
+        );
+    }
+}
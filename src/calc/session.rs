@@ -0,0 +1,261 @@
+use super::{exec, Ast, AstKind, CalcError, Interpreter, Lambda, Value};
+
+const HISTORY: &str = "# history: ";
+
+// Write the variables, user-defined functions and history as calc source:
+// the history as comments, then one `x = value` per variable and one
+// definition per function. The variables holding lambdas and the functions
+// come last, each after the functions it calls.
+pub fn save_session(interp: &Interpreter, history: &[String]) -> String {
+    let mut s = String::from("# calc session\n");
+    for line in history {
+        s.push_str(&format!("{}{}\n", HISTORY, line));
+    }
+    let env = interp.env();
+    // the name, the source and the names used
+    let mut pending: Vec<(&str, String, Vec<&str>)> = Vec::new();
+    for (name, value) in env.vars() {
+        match value {
+            Value::Lambda(f) => {
+                let source = format!("{} = {}\n", name, lambda_source(f));
+                pending.push((name, source, lambda_callees(f)));
+            }
+            _ => s.push_str(&format!("{} = {}\n", name, value_source(value))),
+        }
+    }
+    for (name, f) in env.funcs() {
+        let params: Vec<_> = f.params().iter().map(|p| p.value.as_str()).collect();
+        let source = format!("{}({}) = {}\n", name, params.join(", "), f.body());
+        pending.push((name, source, callees(f.body())));
+    }
+    while !pending.is_empty() {
+        // functions and lambdas may call each other by name, then the cycle
+        // is broken anywhere
+        let ready = |(name, _, uses): &(&str, String, Vec<&str>)| {
+            let waits = |u: &&str| u != name && pending.iter().any(|(n, _, _)| n == u);
+            !uses.iter().any(waits)
+        };
+        let i = pending.iter().position(ready).unwrap_or(0);
+        s.push_str(&pending.remove(i).1);
+    }
+    s
+}
+
+// The value as calc source which evaluates to it.
+fn value_source(v: &Value) -> String {
+    match v {
+        // `-9223372036854775808` is the negation of a number out of range
+        Value::Int(i64::MIN) => format!("{} - 1", i64::MIN + 1),
+        Value::Float(x) => float_source(*x),
+        Value::Complex(z) if !z.re.is_finite() || !z.im.is_finite() => {
+            format!("{} + {}i", float_source(z.re), float_source(z.im))
+        }
+        Value::List(xs) => {
+            let xs: Vec<_> = xs.iter().map(value_source).collect();
+            format!("[{}]", xs.join(", "))
+        }
+        Value::Lambda(f) => lambda_source(f),
+        v => v.to_string(),
+    }
+}
+
+// `inf` and `NaN` are not literals, so they are written as the overflow of
+// one, e.g. `-1e400` for `-inf`.
+fn float_source(x: f64) -> String {
+    if x.is_nan() {
+        "0 * 1e400".to_string()
+    } else if x.is_infinite() {
+        format!("{}1e400", if x < 0.0 { "-" } else { "" })
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+// A lambda applied to the values it captured, one lambda per value, e.g.
+// `(x -> y -> y + x)(3)` for `y -> y + x` which captured `x = 3`, so that it
+// captures the same values when it is loaded.
+fn lambda_source(f: &Lambda) -> String {
+    let mut s = f.to_string();
+    for (name, v) in f.captured.iter().rev() {
+        s = format!("({} -> {})({})", name, s, value_source(v));
+    }
+    s
+}
+
+// The functions called by name in the lambda and the ones it captured.
+fn lambda_callees(f: &Lambda) -> Vec<&str> {
+    let mut names = callees(&f.body);
+    for (_, v) in &f.captured {
+        if let Value::Lambda(g) = v {
+            names.extend(lambda_callees(g));
+        }
+    }
+    names
+}
+
+// The user-defined functions called in `ast`.
+fn callees(ast: &Ast) -> Vec<&str> {
+    let mut names = Vec::new();
+    if let AstKind::Call { func, .. } = &ast.value {
+        if super::builtins::lookup(&func.value).is_none() {
+            names.push(func.value.as_str());
+        }
+    }
+    for e in ast.children() {
+        names.extend(callees(e));
+    }
+    names
+}
+
+// Execute a saved session and return its history. On an error the
+// interpreter is left unchanged and the error is returned with its line
+// number, counted from 1.
+pub fn load_session(
+    interp: &mut Interpreter,
+    source: &str,
+) -> Result<Vec<String>, (usize, CalcError)> {
+    let env = interp.env().clone();
+    let mut history = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        if let Some(entry) = line.strip_prefix(HISTORY) {
+            history.push(entry.to_string());
+            continue;
+        }
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if let Err(e) = exec(interp, line) {
+            *interp.env_mut() = env;
+            return Err((i + 1, e));
+        }
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_session, save_session};
+    use crate::calc::{exec, CalcError, Interpreter, Value};
+
+    #[test]
+    fn test_save_load() {
+        let mut interp = Interpreter::new();
+        for line in &[
+            "x = 1.5",
+            "z = [1 + 2i, -3]",
+            "sq(a) = a * a",
+            "f(a, b) = sq(a - b) / (x + 1)",
+            "n = -4",
+            "m = [-9223372036854775807 - 1]",
+        ] {
+            exec(&mut interp, line).unwrap();
+        }
+        let history = vec!["x = 1.5".to_string(), "f(1, 2) # note".to_string()];
+        let source = save_session(&interp, &history);
+        assert_eq!(
+            source,
+            "# calc session\n\
+             # history: x = 1.5\n\
+             # history: f(1, 2) # note\n\
+             m = [-9223372036854775807 - 1]\n\
+             n = -4\n\
+             x = 1.5\n\
+             z = [1+2i, -3+0i]\n\
+             sq(a) = a * a\n\
+             f(a, b) = sq(a - b) / (x + 1)\n"
+        );
+
+        let mut loaded = Interpreter::new();
+        assert_eq!(load_session(&mut loaded, &source), Ok(history));
+        assert_eq!(loaded.env(), interp.env());
+        assert_eq!(
+            exec(&mut loaded, "f(5, 2)"),
+            Ok(Some(Value::Float(9.0 / 2.5)))
+        );
+    }
+
+    #[test]
+    fn test_save_non_finite() {
+        let mut interp = Interpreter::new();
+        for line in &[
+            "a = 1e400",
+            "b = -1e400",
+            "c = 0 * 1e400",
+            "d = [1e400, 2.5]",
+            "e = 1 - 1e400i",
+        ] {
+            exec(&mut interp, line).unwrap();
+        }
+        let source = save_session(&interp, &[]);
+        assert_eq!(
+            source,
+            "# calc session\n\
+             a = 1e400\n\
+             b = -1e400\n\
+             c = 0 * 1e400\n\
+             d = [1e400, 2.5]\n\
+             e = 1.0 + -1e400i\n"
+        );
+        let mut loaded = Interpreter::new();
+        assert_eq!(load_session(&mut loaded, &source), Ok(vec![]));
+        let get = |name| loaded.env().get(name).cloned();
+        assert_eq!(get("a"), Some(Value::Float(f64::INFINITY)));
+        assert_eq!(get("b"), Some(Value::Float(f64::NEG_INFINITY)));
+        assert!(matches!(get("c"), Some(Value::Float(x)) if x.is_nan()));
+        assert_eq!(get("d"), interp.env().get("d").cloned());
+        assert_eq!(get("e"), interp.env().get("e").cloned());
+    }
+
+    #[test]
+    fn test_save_lambda() {
+        let mut interp = Interpreter::new();
+        for line in &[
+            "k = 2",
+            "twice(x) = x * k",
+            "f = x -> twice(x) + k",
+            "g = xs -> map(xs, f)",
+        ] {
+            exec(&mut interp, line).unwrap();
+        }
+        let source = save_session(&interp, &[]);
+        assert_eq!(
+            source,
+            "# calc session\n\
+             k = 2\n\
+             twice(x) = x * k\n\
+             f = (k -> x -> twice(x) + k)(2)\n\
+             g = (f -> xs -> map(xs, f))((k -> x -> twice(x) + k)(2))\n"
+        );
+        // the same up to the locations in the bodies
+        let mut loaded = Interpreter::new();
+        load_session(&mut loaded, &source).unwrap();
+        assert_eq!(save_session(&loaded, &[]), source);
+        assert_eq!(
+            exec(&mut loaded, "g([1, 2])"),
+            exec(&mut interp, "g([1, 2])")
+        );
+
+        // the captured value, not the one assigned since then
+        let mut interp = Interpreter::new();
+        for line in &["x = 3", "f = y -> y + x", "x = 5"] {
+            exec(&mut interp, line).unwrap();
+        }
+        let source = save_session(&interp, &[]);
+        assert_eq!(source, "# calc session\nx = 5\nf = (x -> y -> y + x)(3)\n");
+        let mut loaded = Interpreter::new();
+        load_session(&mut loaded, &source).unwrap();
+        assert_eq!(exec(&mut loaded, "f(1)"), Ok(Some(Value::Int(4))));
+    }
+
+    #[test]
+    fn test_load_error() {
+        let mut interp = Interpreter::new();
+        exec(&mut interp, "y = 2").unwrap();
+        let env = interp.env().clone();
+        match load_session(&mut interp, "x = 1\n\ny = x +\n") {
+            Err((3, CalcError::Parse(_))) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(interp.env(), &env);
+    }
+}
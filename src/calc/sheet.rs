@@ -0,0 +1,583 @@
+use super::batch::csv_field;
+use super::lambda::free_names;
+use super::{check_in, lex_with, parse_with, Ast, CalcError, Interpreter, Loc, Value};
+use crate::format::Formatter;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+// The name of a cell, `A1` is the column 0 and the row 0. Columns go on
+// with `Z`, `AA`, `AB` and so on, rows are counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellRef {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl CellRef {
+    pub fn new(col: usize, row: usize) -> Self {
+        CellRef { row, col }
+    }
+
+    // Only upper case letters name a column, so that `x1` stays a variable.
+    fn parse(name: &str) -> Option<CellRef> {
+        let digits = name.find(|c: char| c.is_ascii_digit())?;
+        let (letters, row) = name.split_at(digits);
+        if letters.is_empty()
+            || letters.len() > 3
+            || !letters.bytes().all(|b| b.is_ascii_uppercase())
+        {
+            return None;
+        }
+        if !row.bytes().all(|b| b.is_ascii_digit()) || row.starts_with('0') {
+            return None;
+        }
+        let col = letters
+            .bytes()
+            .fold(0, |col, b| col * 26 + (b - b'A') as usize + 1);
+        Some(CellRef::new(col - 1, row.parse::<usize>().ok()? - 1))
+    }
+}
+
+impl std::str::FromStr for CellRef {
+    type Err = SheetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CellRef::parse(s).ok_or_else(|| SheetError::InvalidCell(s.to_string()))
+    }
+}
+
+impl std::fmt::Display for CellRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut letters = Vec::new();
+        let mut n = self.col + 1;
+        while n > 0 {
+            letters.push(b'A' + ((n - 1) % 26) as u8);
+            n = (n - 1) / 26;
+        }
+        letters.reverse();
+        write!(f, "{}{}", String::from_utf8(letters).unwrap(), self.row + 1)
+    }
+}
+
+// An edit which was rejected, the sheet is left unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SheetError {
+    // not a cell name like `B2`
+    InvalidCell(String),
+    // a lex or parse error in the expression of the cell
+    Calc { cell: CellRef, error: CalcError },
+    // The cells from the edited one back to itself, each referring to the
+    // next one. The location is the reference which closes the cycle in
+    // the expression of the edited cell.
+    Cycle { path: Vec<CellRef>, loc: Loc },
+    // an unclosed quote on the line of CSV, counted from 1
+    Csv(usize),
+}
+
+impl SheetError {
+    // The location in the expression of the edited cell.
+    pub fn loc(&self) -> Option<&Loc> {
+        match self {
+            SheetError::Calc { error, .. } => error.loc(),
+            SheetError::Cycle { loc, .. } => Some(loc),
+            SheetError::InvalidCell(_) | SheetError::Csv(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SheetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SheetError::InvalidCell(name) => write!(f, "invalid cell name '{}'", name),
+            SheetError::Calc { cell, error } => write!(f, "{}: {}", cell, error),
+            SheetError::Cycle { path, loc } => {
+                let path: Vec<_> = path.iter().map(CellRef::to_string).collect();
+                write!(
+                    f,
+                    "{}: {}: circular reference {}",
+                    path[0],
+                    loc,
+                    path.join(" -> ")
+                )
+            }
+            SheetError::Csv(line) => write!(f, "line {}: unclosed quote", line),
+        }
+    }
+}
+
+// Why a cell has no value. It is kept in the cell, as in other spreadsheets
+// the cell shows an error until it or the cells it refers to change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellError {
+    // a type or eval error of the expression
+    Calc(CalcError),
+    // a cell the expression refers to has an error
+    Ref(CellRef),
+}
+
+impl std::fmt::Display for CellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CellError::Calc(e) => write!(f, "{}", e),
+            CellError::Ref(cell) => write!(f, "{} has an error", cell),
+        }
+    }
+}
+
+struct Cell {
+    source: String,
+    ast: Ast,
+    // the cells the expression refers to, with the first location of each
+    refs: Vec<(CellRef, Loc)>,
+    value: Result<Value, CellError>,
+}
+
+// Cells holding calc expressions, e.g. `A3` holding `A1 * B1 + 1`.
+//
+// A cell refers to another one by its name. The references form a graph,
+// and a change recalculates only the cells which depend on the changed one,
+// each after the cells it refers to. An edit which would make a cell refer
+// to itself, directly or not, is rejected. An empty cell is `0`.
+//
+// The expressions are evaluated by an interpreter of the sheet, whose
+// functions and variables they may use, see `interpreter_mut`.
+pub struct Sheet {
+    interp: Interpreter,
+    cells: HashMap<CellRef, Cell>,
+    // the cells which refer to each cell, empty ones included
+    dependents: HashMap<CellRef, BTreeSet<CellRef>>,
+}
+
+impl Sheet {
+    pub fn new() -> Self {
+        Sheet {
+            interp: Interpreter::new(),
+            cells: HashMap::new(),
+            dependents: HashMap::new(),
+        }
+    }
+
+    // After changing the functions or the variables, call `recalc_all`.
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+
+    pub fn source(&self, cell: CellRef) -> Option<&str> {
+        self.cells.get(&cell).map(|c| c.source.as_str())
+    }
+
+    // `None` for an empty cell.
+    pub fn value(&self, cell: CellRef) -> Option<&Result<Value, CellError>> {
+        self.cells.get(&cell).map(|c| &c.value)
+    }
+
+    // Set the expression of the cell, or clear it with an empty one, and
+    // return the cells recalculated, in the order they were.
+    pub fn set(&mut self, cell: CellRef, source: &str) -> Result<Vec<CellRef>, SheetError> {
+        let source = source.trim();
+        if source.is_empty() {
+            if let Some(old) = self.cells.remove(&cell) {
+                self.unlink(cell, &old.refs);
+            }
+            return Ok(self.recalc(cell));
+        }
+        let ops = self.interp.operators();
+        let ast = lex_with(source, ops)
+            .map_err(CalcError::from)
+            .and_then(|tokens| parse_with(tokens, ops).map_err(CalcError::from))
+            .map_err(|error| SheetError::Calc { cell, error })?;
+        let refs = references(&ast);
+        if let Some((path, loc)) = self.find_cycle(cell, &refs) {
+            return Err(SheetError::Cycle { path, loc });
+        }
+        if let Some(old) = self.cells.remove(&cell) {
+            self.unlink(cell, &old.refs);
+        }
+        for (r, _) in &refs {
+            self.dependents.entry(*r).or_default().insert(cell);
+        }
+        let value = Ok(Value::Int(0));
+        let source = source.to_string();
+        let c = Cell {
+            source,
+            ast,
+            refs,
+            value,
+        };
+        self.cells.insert(cell, c);
+        Ok(self.recalc(cell))
+    }
+
+    // Recalculate every cell, e.g. after redefining a function.
+    pub fn recalc_all(&mut self) {
+        let mut cells: Vec<_> = self.cells.keys().copied().collect();
+        cells.sort();
+        let mut done = HashSet::new();
+        for cell in cells {
+            if !done.contains(&cell) {
+                done.extend(self.recalc(cell));
+            }
+        }
+    }
+
+    fn unlink(&mut self, cell: CellRef, refs: &[(CellRef, Loc)]) {
+        for (r, _) in refs {
+            if let Some(ds) = self.dependents.get_mut(r) {
+                ds.remove(&cell);
+            }
+        }
+    }
+
+    // The path from `cell` back to itself through one of the new `refs`,
+    // which is the shortest one found first.
+    fn find_cycle(&self, cell: CellRef, refs: &[(CellRef, Loc)]) -> Option<(Vec<CellRef>, Loc)> {
+        // cells which were searched without reaching `cell`
+        let mut seen = HashSet::new();
+        for (r, loc) in refs {
+            let mut prev = HashMap::new();
+            let mut queue = VecDeque::new();
+            if seen.insert(*r) {
+                queue.push_back(*r);
+            }
+            while let Some(c) = queue.pop_front() {
+                if c == cell {
+                    let mut path = vec![c];
+                    while let Some(p) = prev.get(path.last().unwrap()) {
+                        path.push(*p);
+                    }
+                    path.push(cell);
+                    path.reverse();
+                    return Some((path, loc.clone()));
+                }
+                for (next, _) in self.cells.get(&c).map_or(&[][..], |c| &c.refs) {
+                    if seen.insert(*next) {
+                        prev.insert(*next, c);
+                        queue.push_back(*next);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Evaluate the cell and the cells which depend on it, each after the
+    // cells it refers to, and return them in that order.
+    fn recalc(&mut self, cell: CellRef) -> Vec<CellRef> {
+        let order = self.affected(cell);
+        for c in &order {
+            self.evaluate(*c);
+        }
+        order
+    }
+
+    // A depth first search of the dependents, in reverse post order. The
+    // references have no cycles, so a cell comes after the ones it refers
+    // to. The stack holds whether the dependents of a cell were pushed.
+    fn affected(&self, cell: CellRef) -> Vec<CellRef> {
+        let mut order = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![(cell, false)];
+        while let Some((c, expanded)) = stack.pop() {
+            if expanded {
+                order.push(c);
+                continue;
+            }
+            if !seen.insert(c) {
+                continue;
+            }
+            stack.push((c, true));
+            for d in self.dependents.get(&c).into_iter().flatten().rev() {
+                if !seen.contains(d) {
+                    stack.push((*d, false));
+                }
+            }
+        }
+        order.reverse();
+        order.retain(|c| self.cells.contains_key(c));
+        order
+    }
+
+    // The referred cells are variables named after them while the
+    // expression is evaluated, and only then, so they do not outlive the
+    // cells or show in the interpreter.
+    fn evaluate(&mut self, cell: CellRef) {
+        let c = &self.cells[&cell];
+        let mut bound = Vec::new();
+        let mut value = None;
+        for (r, _) in &c.refs {
+            match self.cells.get(r).map(|c| &c.value) {
+                Some(Ok(v)) => bound.push((r.to_string(), v.clone())),
+                Some(Err(_)) => {
+                    value = Some(Err(CellError::Ref(*r)));
+                    break;
+                }
+                None => bound.push((r.to_string(), Value::Int(0))),
+            }
+        }
+        let ast = c.ast.clone();
+        let value = value.unwrap_or_else(|| {
+            let env = self.interp.env_mut();
+            let mut shadowed = Vec::new();
+            for (name, v) in bound {
+                shadowed.push((name.clone(), env.get(&name).cloned()));
+                env.set(&name, v);
+            }
+            let value = check_in(&ast, self.interp.env())
+                .map_err(CalcError::from)
+                .and_then(|_| self.interp.eval(&ast).map_err(CalcError::from))
+                .map_err(CellError::Calc);
+            let env = self.interp.env_mut();
+            for (name, old) in shadowed {
+                match old {
+                    Some(v) => env.set(&name, v),
+                    None => {
+                        env.remove(&name);
+                    }
+                }
+            }
+            value
+        });
+        self.cells.get_mut(&cell).unwrap().value = value;
+    }
+
+    // The rows of expressions, with empty fields for empty cells. Lines
+    // are split at commas outside of `"` quotes, `""` is a quote.
+    pub fn from_csv(input: &str) -> Result<Sheet, SheetError> {
+        let mut sheet = Sheet::new();
+        for (row, line) in input.lines().enumerate() {
+            let fields = split_csv(line).ok_or(SheetError::Csv(row + 1))?;
+            for (col, field) in fields.iter().enumerate() {
+                if !field.trim().is_empty() {
+                    sheet.set(CellRef::new(col, row), field)?;
+                }
+            }
+        }
+        Ok(sheet)
+    }
+
+    // The expressions, which `from_csv` reads back.
+    pub fn to_csv(&self) -> String {
+        self.write_csv(|c| c.source.clone())
+    }
+
+    // The values written with `fmt`, and `#ERROR` for a cell with an error.
+    pub fn values_to_csv(&self, fmt: &Formatter) -> String {
+        self.write_csv(|c| match &c.value {
+            Ok(v) => v.format(fmt),
+            Err(_) => "#ERROR".to_string(),
+        })
+    }
+
+    fn write_csv(&self, field: impl Fn(&Cell) -> String) -> String {
+        let rows = self.cells.keys().map(|c| c.row + 1).max().unwrap_or(0);
+        let cols = self.cells.keys().map(|c| c.col + 1).max().unwrap_or(0);
+        let mut s = String::new();
+        for row in 0..rows {
+            let fields: Vec<_> = (0..cols)
+                .map(|col| match self.cells.get(&CellRef::new(col, row)) {
+                    Some(c) => csv_field(&field(c)),
+                    None => String::new(),
+                })
+                .collect();
+            s.push_str(&fields.join(","));
+            s.push('\n');
+        }
+        s
+    }
+}
+
+impl Default for Sheet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The cells an expression refers to. A parameter of a lambda or the
+// variable of a form named like a cell is not a reference.
+fn references(ast: &Ast) -> Vec<(CellRef, Loc)> {
+    free_names(&[], ast)
+        .into_iter()
+        .filter(|free| !free.call)
+        .filter_map(|free| Some((CellRef::parse(free.name)?, free.loc.clone())))
+        .collect()
+}
+
+// `None` if a quote is not closed.
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    if quoted {
+        None
+    } else {
+        Some(fields)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calc::{eval_with, exec};
+
+    fn cell(name: &str) -> CellRef {
+        name.parse().unwrap()
+    }
+
+    fn cells(names: &[&str]) -> Vec<CellRef> {
+        names.iter().map(|n| cell(n)).collect()
+    }
+
+    fn value(sheet: &Sheet, name: &str) -> Value {
+        sheet.value(cell(name)).unwrap().clone().unwrap()
+    }
+
+    #[test]
+    fn test_cell_ref() {
+        assert_eq!(cell("A1"), CellRef::new(0, 0));
+        assert_eq!(cell("B12"), CellRef::new(1, 11));
+        assert_eq!(cell("AA3"), CellRef::new(26, 2));
+        for name in &["A1", "Z9", "AA1", "AZ10", "BA2", "ZZZ100"] {
+            assert_eq!(cell(name).to_string(), *name);
+        }
+        for name in &["a1", "A0", "A", "1", "AAAA1", "A1B", "A01"] {
+            assert_eq!(
+                name.parse::<CellRef>(),
+                Err(SheetError::InvalidCell(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_recalc() {
+        let mut sheet = Sheet::new();
+        assert_eq!(sheet.set(cell("A1"), "2"), Ok(cells(&["A1"])));
+        assert_eq!(sheet.set(cell("B1"), "A1 * 10"), Ok(cells(&["B1"])));
+        assert_eq!(sheet.set(cell("C1"), "A1 + B1"), Ok(cells(&["C1"])));
+        assert_eq!(sheet.set(cell("A2"), "7"), Ok(cells(&["A2"])));
+        assert_eq!(value(&sheet, "C1"), Value::Int(22));
+
+        // only the dependents, each after the cells it refers to
+        assert_eq!(sheet.set(cell("A1"), "3"), Ok(cells(&["A1", "B1", "C1"])));
+        assert_eq!(value(&sheet, "C1"), Value::Int(33));
+
+        // an empty cell is 0
+        assert_eq!(sheet.set(cell("D1"), "D2 + 1"), Ok(cells(&["D1"])));
+        assert_eq!(value(&sheet, "D1"), Value::Int(1));
+        assert_eq!(sheet.set(cell("D2"), "4"), Ok(cells(&["D2", "D1"])));
+        assert_eq!(value(&sheet, "D1"), Value::Int(5));
+        assert_eq!(sheet.set(cell("D2"), ""), Ok(cells(&["D1"])));
+        assert_eq!(sheet.value(cell("D2")), None);
+        assert_eq!(value(&sheet, "D1"), Value::Int(1));
+
+        // a lambda parameter named like a cell is not a reference
+        sheet
+            .set(cell("E1"), "map([1, 2], A1 -> A1 * A2)[1]")
+            .unwrap();
+        assert_eq!(value(&sheet, "E1"), Value::Int(14));
+        assert_eq!(sheet.set(cell("A2"), "1"), Ok(cells(&["A2", "E1"])));
+    }
+
+    #[test]
+    fn test_cell_scope() {
+        let mut sheet = Sheet::new();
+        sheet.set(cell("A1"), "2").unwrap();
+        sheet.set(cell("B1"), "A1 + 1").unwrap();
+        // the cells are not variables of the interpreter
+        let interp = sheet.interpreter_mut();
+        assert_eq!(interp.env().get("A1"), None);
+        assert!(eval_with(interp, "A1").is_err());
+
+        // nor are deleted ones, nor do they hide a variable of the name
+        exec(sheet.interpreter_mut(), "C1 = 10").unwrap();
+        sheet.set(cell("C1"), "5").unwrap();
+        sheet.set(cell("D1"), "C1 * 2").unwrap();
+        assert_eq!(value(&sheet, "D1"), Value::Int(10));
+        sheet.set(cell("C1"), "").unwrap();
+        assert_eq!(value(&sheet, "D1"), Value::Int(0));
+        sheet.set(cell("B1"), "2").unwrap();
+        sheet.set(cell("A1"), "").unwrap();
+        let interp = sheet.interpreter_mut();
+        assert_eq!(interp.env().get("A1"), None);
+        assert_eq!(interp.env().get("C1"), Some(&Value::Int(10)));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut sheet = Sheet::new();
+        sheet.set(cell("A1"), "1").unwrap();
+        sheet.set(cell("B1"), "A1 + 1").unwrap();
+        sheet.set(cell("C1"), "B1 * 2").unwrap();
+        let e = sheet.set(cell("A1"), "1 + C1").unwrap_err();
+        assert_eq!(
+            e,
+            SheetError::Cycle {
+                path: cells(&["A1", "C1", "B1", "A1"]),
+                loc: Loc(4, 6)
+            }
+        );
+        assert_eq!(
+            e.to_string(),
+            "A1: 4-6: circular reference A1 -> C1 -> B1 -> A1"
+        );
+        // the sheet is unchanged
+        assert_eq!(sheet.source(cell("A1")), Some("1"));
+        assert_eq!(value(&sheet, "C1"), Value::Int(4));
+
+        let e = sheet.set(cell("D1"), "D1").unwrap_err();
+        assert_eq!(e.loc(), Some(&Loc(0, 2)));
+        assert_eq!(sheet.value(cell("D1")), None);
+    }
+
+    #[test]
+    fn test_error() {
+        let mut sheet = Sheet::new();
+        assert!(matches!(
+            sheet.set(cell("A1"), "1 +"),
+            Err(SheetError::Calc { .. })
+        ));
+        sheet.set(cell("A1"), "1 / A2").unwrap();
+        sheet.set(cell("B1"), "A1 + 1").unwrap();
+        assert!(matches!(
+            sheet.value(cell("A1")),
+            Some(Err(CellError::Calc(CalcError::Eval(_))))
+        ));
+        assert_eq!(
+            sheet.value(cell("B1")),
+            Some(&Err(CellError::Ref(cell("A1"))))
+        );
+        sheet.set(cell("A2"), "4").unwrap();
+        assert_eq!(value(&sheet, "B1"), Value::Float(1.25));
+    }
+
+    #[test]
+    fn test_csv() {
+        let input = "1,2,A1 + B1\n,\"max([A1, B1])\"\n";
+        let sheet = Sheet::from_csv(input).unwrap();
+        assert_eq!(sheet.source(cell("B2")), Some("max([A1, B1])"));
+        assert_eq!(value(&sheet, "C1"), Value::Int(3));
+        assert_eq!(value(&sheet, "B2"), Value::Int(2));
+        assert_eq!(sheet.to_csv(), "1,2,A1 + B1\n,\"max([A1, B1])\",\n");
+        let fmt = Formatter::new();
+        assert_eq!(sheet.values_to_csv(&fmt), "1,2,3\n,2,\n");
+        assert_eq!(
+            Sheet::from_csv(&sheet.to_csv()).unwrap().to_csv(),
+            sheet.to_csv()
+        );
+
+        assert_eq!(Sheet::from_csv("1\n\"2").err(), Some(SheetError::Csv(2)));
+        assert!(matches!(
+            Sheet::from_csv("1,A1 +").err(),
+            Some(SheetError::Calc { .. })
+        ));
+    }
+}
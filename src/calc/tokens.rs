@@ -0,0 +1,156 @@
+// Declare a set of tokens once, e.g.
+//
+//     tokens! {
+//         #[derive(Debug, Clone, PartialEq)]
+//         pub enum Kind for Token {
+//             Number(u64),
+//             // shown as `"a"`
+//             Str(String) = "{:?}",
+//         }
+//         symbols {
+//             "+" => Plus, plus;
+//             "->" => Arrow, arrow;
+//         }
+//     }
+//
+// makes the enum `Kind` with the variants holding a value followed by those
+// of the symbols, `Display` for it showing the symbols and the values, and
+// for each symbol a constructor `Token::plus(loc)`. `Token` is a type of the
+// crate with `Token::new(kind, loc)`, like `Annot<Kind>` in this one.
+//
+// The lexing of the symbols is `Kind::lex_symbol`, the values have to be
+// lexed by hand.
+#[macro_export]
+macro_rules! tokens {
+    (@fmt $f:ident, $v:ident) => {
+        write!($f, "{}", $v)
+    };
+    (@fmt $f:ident, $v:ident, $fmt:literal) => {
+        write!($f, $fmt, $v)
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $kind:ident for $token:ty {
+            $(
+                $(#[$value_attr:meta])*
+                $value:ident($ty:ty) $(= $fmt:literal)?
+            ),* $(,)?
+        }
+        symbols {
+            $(
+                $(#[$symbol_attr:meta])*
+                $symbol:literal => $name:ident, $ctor:ident;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $kind {
+            $($(#[$value_attr])* $value($ty),)*
+            $($(#[$symbol_attr])* $name,)*
+        }
+
+        impl $kind {
+            // The symbols in the order they were declared.
+            pub const SYMBOLS: &'static [&'static str] = &[$($symbol),*];
+
+            pub fn from_symbol(symbol: &str) -> Option<Self> {
+                match symbol {
+                    $($symbol => Some($kind::$name),)*
+                    _ => None,
+                }
+            }
+
+            // The token of the longest symbol at the start of the input and
+            // its length. If there is none, the error is the symbol with the
+            // longest start there and the length of that start, e.g. `<<`
+            // and 1 for `<` when only `<<` is a symbol, or `None` when the
+            // input does not start like a symbol.
+            pub fn lex_symbol(
+                input: &[u8],
+            ) -> Result<(Self, usize), Option<(&'static str, usize)>> {
+                let matches = |s: &&&str| input.starts_with(s.as_bytes());
+                match Self::SYMBOLS.iter().filter(matches).max_by_key(|s| s.len()) {
+                    Some(s) => Ok((Self::from_symbol(s).unwrap(), s.len())),
+                    None => Err(Self::SYMBOLS
+                        .iter()
+                        .map(|s| (*s, s.bytes().zip(input).take_while(|(a, b)| a == *b).count()))
+                        .filter(|&(_, len)| len > 0)
+                        // the first declared of the longest
+                        .rev()
+                        .max_by_key(|&(_, len)| len)),
+                }
+            }
+        }
+
+        impl $token {
+            $(
+                $vis fn $ctor(loc: $crate::calc::Loc) -> Self {
+                    Self::new($kind::$name, loc)
+                }
+            )*
+        }
+
+        impl std::fmt::Display for $kind {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match self {
+                    $($kind::$value(v) => $crate::tokens!(@fmt f, v $(, $fmt)?),)*
+                    $($kind::$name => f.write_str($symbol),)*
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calc::Loc;
+
+    // a token of another crate, which cannot be an `Annot`
+    #[derive(Debug, PartialEq)]
+    struct Token {
+        kind: Kind,
+        loc: Loc,
+    }
+
+    impl Token {
+        fn new(kind: Kind, loc: Loc) -> Self {
+            Token { kind, loc }
+        }
+    }
+
+    crate::tokens! {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Kind for Token {
+            Word(String),
+            Quoted(String) = "{:?}",
+        }
+        symbols {
+            ":" => Colon, colon;
+            "::" => ColonColon, colon_colon;
+            "=>" => FatArrow, fat_arrow;
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(Kind::SYMBOLS, &[":", "::", "=>"]);
+        assert_eq!(Kind::lex_symbol(b"::x"), Ok((Kind::ColonColon, 2)));
+        assert_eq!(Kind::lex_symbol(b":x"), Ok((Kind::Colon, 1)));
+        assert_eq!(Kind::lex_symbol(b"=x"), Err(Some(("=>", 1))));
+        assert_eq!(Kind::lex_symbol(b"x"), Err(None));
+        assert_eq!(Kind::from_symbol("=>"), Some(Kind::FatArrow));
+        assert_eq!(
+            Token::fat_arrow(Loc::new(1, 3)),
+            Token::new(Kind::FatArrow, Loc::new(1, 3))
+        );
+        let kinds = [
+            Kind::Word("a".to_string()),
+            Kind::Quoted("b".to_string()),
+            Kind::ColonColon,
+        ];
+        let shown: Vec<_> = kinds.iter().map(Kind::to_string).collect();
+        assert_eq!(shown, vec!["a", "\"b\"", "::"]);
+        assert_eq!(Token::colon(Loc::new(0, 1)).kind, Kind::Colon);
+        assert_eq!(Token::colon_colon(Loc::new(0, 2)).loc, Loc::new(0, 2));
+    }
+}
@@ -0,0 +1,241 @@
+use super::{check_in, parse_limited, CalcError, Interpreter, Loc, Value};
+
+// One reduction: the subexpression at `loc` in the input evaluated to `value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    loc: Loc,
+    redex: String,
+    value: Value,
+    expr: String,
+}
+
+impl Step {
+    // The span of the reduced subexpression in the original input.
+    pub fn loc(&self) -> &Loc {
+        &self.loc
+    }
+
+    // The subexpression as it read right before this step, e.g. `3 * 3`.
+    pub fn redex(&self) -> &str {
+        &self.redex
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    // The whole expression after this step.
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+}
+
+// How a result was derived, e.g. `(1 + 2) * 3` => `3 * 3` => `9`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    input: String,
+    steps: Vec<Step>,
+    result: Value,
+}
+
+impl Trace {
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn result(&self) -> &Value {
+        &self.result
+    }
+
+    pub fn to_json(&self) -> String {
+        let steps: Vec<_> = self
+            .steps
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "span": [s.loc.0, s.loc.1],
+                    "redex": s.redex,
+                    "value": s.value.to_string(),
+                    "expr": s.expr,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "input": self.input,
+            "steps": steps,
+            "result": self.result.to_string(),
+        })
+        .to_string()
+    }
+}
+
+// One line per step with the rewritten expression on the left and the
+// reduction on the right:
+//
+// ```text
+//    (1 + 2) * 3
+// => 3 * 3          (1 + 2) = 3 at 0-7
+// => 9              3 * 3 = 9 at 0-11
+// ```
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let width = self
+            .steps
+            .iter()
+            .map(|s| s.expr.chars().count())
+            .max()
+            .unwrap_or(0);
+        write!(f, "   {}", self.input)?;
+        for s in &self.steps {
+            write!(
+                f,
+                "\n=> {:w$}  {} = {} at {}",
+                s.expr,
+                s.redex,
+                s.value,
+                s.loc,
+                w = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Evaluate the input like `eval_with` and record each reduction.
+pub fn trace(interp: &mut Interpreter, input: &str) -> Result<Trace, CalcError> {
+    let ast = parse_limited(interp, input)?;
+    check_in(&ast, interp.env())?;
+    interp.begin_trace();
+    let result = interp.eval(&ast);
+    let reductions = interp.end_trace();
+    let result = result?;
+
+    // the input with the reduced spans replaced by their values; a reduction
+    // replaces the ones nested inside of it
+    let mut replaced: Vec<(Loc, String)> = Vec::new();
+    let mut steps = Vec::new();
+    let mut prev = input.to_string();
+    for (loc, value) in reductions {
+        let redex = render(input, &replaced, &loc);
+        replaced.retain(|(l, _)| !(loc.0 <= l.0 && l.1 <= loc.1));
+        replaced.push((loc.clone(), value.to_string()));
+        let expr = render(input, &replaced, &Loc(0, input.len()));
+        // e.g. `-1` reduces to itself
+        if expr == prev {
+            continue;
+        }
+        prev = expr.clone();
+        steps.push(Step {
+            loc,
+            redex,
+            value,
+            expr,
+        });
+    }
+    Ok(Trace {
+        input: input.to_string(),
+        steps,
+        result,
+    })
+}
+
+// The part of the input at `loc` with the replacements applied.
+fn render(input: &str, replaced: &[(Loc, String)], loc: &Loc) -> String {
+    let mut inner: Vec<_> = replaced
+        .iter()
+        .filter(|(l, _)| loc.0 <= l.0 && l.1 <= loc.1)
+        .collect();
+    inner.sort_by_key(|(l, _)| l.0);
+    let mut s = String::new();
+    let mut pos = loc.0;
+    for (l, v) in inner {
+        s.push_str(&input[pos..l.0]);
+        s.push_str(v);
+        pos = l.1;
+    }
+    s.push_str(&input[pos..loc.1]);
+    s.trim().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::trace;
+    use crate::calc::{exec, CalcError, Interpreter, LimitErrorKind, Limits, Loc, Value};
+
+    fn exprs(input: &str) -> Vec<String> {
+        let t = trace(&mut Interpreter::new(), input).unwrap();
+        t.steps().iter().map(|s| s.expr().to_string()).collect()
+    }
+
+    #[test]
+    fn test_trace() {
+        let t = trace(&mut Interpreter::new(), "(1 + 2) * 3").unwrap();
+        assert_eq!(t.result(), &Value::Int(9));
+        assert_eq!(t.steps().len(), 2);
+        assert_eq!(t.steps()[0].loc(), &Loc(0, 7));
+        assert_eq!(t.steps()[0].redex(), "(1 + 2)");
+        assert_eq!(t.steps()[0].expr(), "3 * 3");
+        assert_eq!(t.steps()[1].loc(), &Loc(0, 11));
+        assert_eq!(t.steps()[1].redex(), "3 * 3");
+        assert_eq!(t.steps()[1].expr(), "9");
+        assert_eq!(
+            t.to_string(),
+            "   (1 + 2) * 3\n=> 3 * 3  (1 + 2) = 3 at 0-7\n=> 9      3 * 3 = 9 at 0-11"
+        );
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        assert_eq!(exprs("1 * 2 + 3 * 4"), vec!["2 + 3 * 4", "2 + 12", "14"]);
+        assert_eq!(exprs("-1 + 2"), vec!["1"]);
+        assert_eq!(exprs("max([1, 2 + 3])"), vec!["max([1, 5])", "5"]);
+        assert!(exprs("42").is_empty());
+    }
+
+    #[test]
+    fn test_trace_json() {
+        let t = trace(&mut Interpreter::new(), "1 / 2").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&t.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "input": "1 / 2",
+                "steps": [{"span": [0, 5], "redex": "1 / 2", "value": "0.5", "expr": "0.5"}],
+                "result": "0.5",
+            })
+        );
+    }
+
+    #[test]
+    fn test_trace_error() {
+        assert!(trace(&mut Interpreter::new(), "1 // 0").is_err());
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::sandbox());
+        let nested = format!("{}1{}", "(".repeat(4000), ")".repeat(4000));
+        match trace(&mut interp, &nested) {
+            Err(CalcError::Limit(e)) => assert_eq!(
+                e.value,
+                LimitErrorKind::InputTooLong {
+                    len: 8001,
+                    max: 4096
+                }
+            ),
+            other => panic!("unexpected: {:?}", other),
+        }
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        match trace(&mut interp, &nested) {
+            Err(CalcError::Limit(e)) => assert_eq!(e.value, LimitErrorKind::TooDeep(64)),
+            other => panic!("unexpected: {:?}", other),
+        }
+        exec(&mut interp, "f(n) = sum(i, i, 1, n)").unwrap();
+        // the steps or the time, whichever runs out first
+        assert!(matches!(
+            trace(&mut interp, "f(1000000)"),
+            Err(CalcError::Limit(_))
+        ));
+    }
+}
@@ -0,0 +1,765 @@
+use super::builtins::{self, Param};
+use super::forms;
+use super::lambda::{self, FuncType};
+use super::parser::implicit_mult;
+use super::{Annot, Ast, AstKind, Env, Ident, Loc, Stmt, StmtKind, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Complex,
+    List(Box<Type>),
+    // The element type of an empty list, only known at runtime.
+    Any,
+    // a lambda, see `lambda.rs`
+    Func(Rc<FuncType>),
+}
+
+impl Type {
+    pub(super) fn is_number(&self) -> bool {
+        self.is_real() || *self == Type::Complex
+    }
+
+    pub(super) fn is_real(&self) -> bool {
+        match self {
+            Type::Int | Type::Float | Type::Any => true,
+            Type::Complex | Type::List(_) | Type::Func(_) => false,
+        }
+    }
+
+    // The common type of both sides, numbers are promoted along
+    // `Int` -> `Float` -> `Complex`. Returns `None` if they are incompatible.
+    pub(super) fn promote(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Any, t) | (t, Type::Any) => Some(t.clone()),
+            (Type::Int, Type::Int) => Some(Type::Int),
+            (Type::Complex, t) | (t, Type::Complex) if t.is_number() => Some(Type::Complex),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) | (Type::Float, Type::Float) => {
+                Some(Type::Float)
+            }
+            (Type::List(l), Type::List(r)) => l.promote(r).map(|t| Type::List(Box::new(t))),
+            (Type::Func(l), Type::Func(r)) if l == r => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Complex => write!(f, "complex"),
+            Type::List(t) => write!(f, "list[{}]", t),
+            Type::Any => write!(f, "any"),
+            Type::Func(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeErrorKind {
+    Mismatch { expected: Type, found: Type },
+    NotNumber(Type),
+    NotList(Type),
+    NotFunction(Type),
+    UnknownFunction(String),
+    UnknownVariable(String),
+    ArgCount { expected: usize, found: usize },
+    BuiltinRedefined(String),
+    DuplicateParam(String),
+    RecursiveFunction(String),
+    IntervalLiteral,
+    // the variable of a form like `solve(x * x - 2, x, 1)`
+    ExpectedVariable,
+}
+
+pub type TypeError = Annot<TypeErrorKind>;
+
+impl TypeError {
+    pub(super) fn mismatch(expected: Type, found: Type, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::Mismatch { expected, found }, loc)
+    }
+    fn not_number(found: Type, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::NotNumber(found), loc)
+    }
+    pub(super) fn not_list(found: Type, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::NotList(found), loc)
+    }
+    pub(super) fn not_function(found: Type, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::NotFunction(found), loc)
+    }
+    fn unknown_function(name: &str, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::UnknownFunction(name.to_string()), loc)
+    }
+    fn unknown_variable(name: &str, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::UnknownVariable(name.to_string()), loc)
+    }
+    pub(super) fn arg_count(expected: usize, found: usize, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::ArgCount { expected, found }, loc)
+    }
+    fn builtin_redefined(name: &str, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::BuiltinRedefined(name.to_string()), loc)
+    }
+    fn duplicate_param(name: &str, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::DuplicateParam(name.to_string()), loc)
+    }
+    fn recursive_function(name: &str, loc: Loc) -> Self {
+        Self::new(TypeErrorKind::RecursiveFunction(name.to_string()), loc)
+    }
+    fn interval_literal(loc: Loc) -> Self {
+        Self::new(TypeErrorKind::IntervalLiteral, loc)
+    }
+    pub(super) fn expected_variable(loc: Loc) -> Self {
+        Self::new(TypeErrorKind::ExpectedVariable, loc)
+    }
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let loc = &self.loc;
+        match &self.value {
+            TypeErrorKind::Mismatch { expected, found } => {
+                write!(f, "{}: expected {} but found {}", loc, expected, found)
+            }
+            TypeErrorKind::NotNumber(found) => {
+                write!(f, "{}: expected number but found {}", loc, found)
+            }
+            TypeErrorKind::NotList(found) => {
+                write!(f, "{}: expected list but found {}", loc, found)
+            }
+            TypeErrorKind::NotFunction(found) => {
+                write!(f, "{}: expected function but found {}", loc, found)
+            }
+            TypeErrorKind::UnknownFunction(name) => {
+                write!(f, "{}: unknown function '{}'", loc, name)
+            }
+            TypeErrorKind::UnknownVariable(name) => {
+                write!(f, "{}: unknown variable '{}'", loc, name)
+            }
+            TypeErrorKind::ArgCount { expected, found } => write!(
+                f,
+                "{}: expected {} argument(s) but found {}",
+                loc, expected, found
+            ),
+            TypeErrorKind::BuiltinRedefined(name) => {
+                write!(
+                    f,
+                    "{}: cannot redefine the built-in function '{}'",
+                    loc, name
+                )
+            }
+            TypeErrorKind::DuplicateParam(name) => {
+                write!(f, "{}: duplicate parameter '{}'", loc, name)
+            }
+            TypeErrorKind::RecursiveFunction(name) => {
+                write!(f, "{}: '{}' would call itself", loc, name)
+            }
+            TypeErrorKind::IntervalLiteral => write!(
+                f,
+                "{}: interval literals are only allowed in interval mode",
+                loc
+            ),
+            TypeErrorKind::ExpectedVariable => write!(f, "{}: expected a variable name", loc),
+        }
+    }
+}
+
+// Infer the type of the expression without evaluating it.
+//
+// * `+`, `-` and `*` promote `Int` to `Float` if either side is a `Float`,
+//   and to `Complex` if either side is a `Complex`
+// * `/` is true division and results in a `Float` (or a `Complex`)
+// * `//` is integer division and accepts `Int` operands only, so do the
+//   bitwise operators `&`, `|`, `xor`, `~`, `<<` and `>>`
+// * list elements are promoted to their common type, `[1, 2.5]` is a `list[float]`
+pub fn check(ast: &Ast) -> Result<Type, TypeError> {
+    check_in(ast, &Env::new())
+}
+
+// Same as `check`, with variables typed by their values in `env`.
+//
+// A call of a user-defined function checks its body with the types of the
+// arguments, so `f(x) = x // 2` accepts `f(3)` but rejects `f(1.5)`. The
+// error is reported at the call.
+pub fn check_in(ast: &Ast, env: &Env) -> Result<Type, TypeError> {
+    check_with(ast, env, &HashMap::new())
+}
+
+// Check a statement before it is executed. For `f(x) = ...` only the names
+// in the body are resolved since the type of `x` is not known until `f` is
+// called.
+pub fn check_stmt(stmt: &Stmt, env: &Env) -> Result<(), TypeError> {
+    match &stmt.value {
+        StmtKind::Expr(e) | StmtKind::Let { e, .. } => check_in(e, env).map(|_| ()),
+        StmtKind::Def { name, params, body } => {
+            if builtins::is_builtin(&name.value) {
+                return Err(TypeError::builtin_redefined(&name.value, name.loc.clone()));
+            }
+            check_params(params)?;
+            resolve(body, env, params, &name.value)
+        }
+    }
+}
+
+fn check_params(params: &[Ident]) -> Result<(), TypeError> {
+    for (i, p) in params.iter().enumerate() {
+        if params[..i].iter().any(|q| q.value == p.value) {
+            return Err(TypeError::duplicate_param(&p.value, p.loc.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Types of the arguments of the function being checked.
+type Locals = HashMap<String, Type>;
+
+// Every variable in the body of `name` must be a parameter or a global, and
+// every function must be known. `name` itself must not be reachable, which
+// keeps the functions free of cycles even when one is redefined.
+fn resolve(ast: &Ast, env: &Env, params: &[Ident], name: &str) -> Result<(), TypeError> {
+    match &ast.value {
+        AstKind::Var(v) if !params.iter().any(|p| &p.value == v) && env.get(v).is_none() => {
+            return Err(TypeError::unknown_variable(v, ast.loc.clone()));
+        }
+        AstKind::Call { func, args } if forms::lookup(&func.value, args.len()).is_some() => {
+            let form = forms::lookup(&func.value, args.len()).unwrap();
+            if form.params.len() + 2 != args.len() {
+                return Err(TypeError::arg_count(
+                    form.params.len() + 2,
+                    args.len(),
+                    ast.loc.clone(),
+                ));
+            }
+            let x = forms::bound_var(args)
+                .ok_or_else(|| TypeError::expected_variable(args[1].loc.clone()))?;
+            let mut inner = params.to_vec();
+            inner.push(Ident::new(x.to_string(), args[1].loc.clone()));
+            resolve(&args[0], env, &inner, name)?;
+            for e in &args[2..] {
+                resolve(e, env, params, name)?;
+            }
+            return Ok(());
+        }
+        AstKind::Call { func, args } => {
+            let global = match env.get(&func.value) {
+                Some(Value::Lambda(f)) => Some(f),
+                _ => None,
+            };
+            let arity = match builtins::lookup(&func.value) {
+                Some(builtin) => Some(builtin.params.len()),
+                None if lambda::lookup(&func.value).is_some() => {
+                    lambda::lookup(&func.value).map(|f| f.arity)
+                }
+                // a parameter may hold a lambda, which is checked at the call
+                None if params.iter().any(|p| p.value == func.value) => None,
+                None if global.is_some() => global.map(|f| f.params.len()),
+                None if func.value == name || calls(env, &func.value, name) => {
+                    return Err(TypeError::recursive_function(name, func.loc.clone()));
+                }
+                None => match env.get_func(&func.value) {
+                    Some(f) => Some(f.params().len()),
+                    None => return Err(TypeError::unknown_function(&func.value, func.loc.clone())),
+                },
+            };
+            match arity {
+                Some(arity) if arity != args.len() => {
+                    return Err(TypeError::arg_count(arity, args.len(), ast.loc.clone()));
+                }
+                _ => {}
+            }
+        }
+        AstKind::Lambda {
+            params: inner,
+            body,
+        } => {
+            let mut params = params.to_vec();
+            params.extend(inner.iter().cloned());
+            return resolve(body, env, &params, name);
+        }
+        _ => {}
+    }
+    for e in ast.children() {
+        resolve(e, env, params, name)?;
+    }
+    Ok(())
+}
+
+// Whether the user-defined function `f` calls `name`, directly or not.
+fn calls(env: &Env, f: &str, name: &str) -> bool {
+    fn walk(ast: &Ast, env: &Env, name: &str) -> bool {
+        if let AstKind::Call { func, .. } = &ast.value {
+            if func.value == name || calls(env, &func.value, name) {
+                return true;
+            }
+        }
+        ast.children().into_iter().any(|e| walk(e, env, name))
+    }
+    match env.get_func(f) {
+        Some(f) => walk(f.body(), env, name),
+        None => false,
+    }
+}
+
+fn check_with(ast: &Ast, env: &Env, locals: &Locals) -> Result<Type, TypeError> {
+    match &ast.value {
+        AstKind::Int(_) => Ok(Type::Int),
+        AstKind::Float(_) => Ok(Type::Float),
+        AstKind::Imag(_) => Ok(Type::Complex),
+        AstKind::Var(name) => variable_type(name, env, locals)
+            .ok_or_else(|| TypeError::unknown_variable(name, ast.loc.clone())),
+        AstKind::PlusMinus { .. } => Err(TypeError::interval_literal(ast.loc.clone())),
+        AstKind::List(elems) => {
+            let mut t = Type::Any;
+            for e in elems {
+                let et = check_with(e, env, locals)?;
+                t = t
+                    .promote(&et)
+                    .ok_or_else(|| TypeError::mismatch(t.clone(), et, e.loc.clone()))?;
+            }
+            Ok(Type::List(Box::new(t)))
+        }
+        AstKind::Index { e, index } => {
+            let it = check_with(index, env, locals)?;
+            expect(Type::Int, it, &index.loc)?;
+            match check_with(e, env, locals)? {
+                Type::List(t) => Ok(*t),
+                Type::Any => Ok(Type::Any),
+                t => Err(TypeError::not_list(t, e.loc.clone())),
+            }
+        }
+        AstKind::Call { func, args } if forms::lookup(&func.value, args.len()).is_some() => {
+            let form = forms::lookup(&func.value, args.len()).unwrap();
+            if form.params.len() + 2 != args.len() {
+                return Err(TypeError::arg_count(
+                    form.params.len() + 2,
+                    args.len(),
+                    ast.loc.clone(),
+                ));
+            }
+            let x = forms::bound_var(args)
+                .ok_or_else(|| TypeError::expected_variable(args[1].loc.clone()))?;
+            let mut inner = locals.clone();
+            inner.insert(x.to_string(), form.var.clone());
+            let t = check_with(&args[0], env, &inner)?;
+            check_param(form.body, &t, &args[0].loc)?;
+            for (&param, e) in form.params.iter().zip(&args[2..]) {
+                check_param(param, &check_with(e, env, locals)?, &e.loc)?;
+            }
+            Ok((form.ret)(&t))
+        }
+        AstKind::Call { func, args } if lambda::lookup(&func.value).is_some() => {
+            let hof = lambda::lookup(&func.value).unwrap();
+            if hof.arity != args.len() {
+                return Err(TypeError::arg_count(hof.arity, args.len(), ast.loc.clone()));
+            }
+            let types = args
+                .iter()
+                .map(|e| check_with(e, env, locals).map(|t| Annot::new(t, e.loc.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut apply = |f: &FuncType, types| apply_type(f, types, env, &func.value, &ast.loc);
+            (hof.ret)(&types, &mut apply)
+        }
+        AstKind::Call { func, args }
+            if builtins::lookup(&func.value).is_none()
+                && func_type(&func.value, env, locals).is_some() =>
+        {
+            let f = func_type(&func.value, env, locals).unwrap();
+            let types = args
+                .iter()
+                .map(|e| check_with(e, env, locals))
+                .collect::<Result<Vec<_>, _>>()?;
+            check_called_params(&f, args, &types, env)?;
+            apply_type(&f, types, env, &func.value, &ast.loc)
+        }
+        // `x(1)` where `x` holds a number
+        AstKind::Call { func, .. }
+            if builtins::lookup(&func.value).is_none()
+                && env.get_func(&func.value).is_none()
+                && variable_type(&func.value, env, locals).is_some() =>
+        {
+            let t = variable_type(&func.value, env, locals).unwrap();
+            Err(TypeError::not_function(t, func.loc.clone()))
+        }
+        AstKind::Call { func, args } if builtins::lookup(&func.value).is_none() => {
+            let f = env
+                .get_func(&func.value)
+                .ok_or_else(|| TypeError::unknown_function(&func.value, func.loc.clone()))?;
+            if f.params().len() != args.len() {
+                return Err(TypeError::arg_count(
+                    f.params().len(),
+                    args.len(),
+                    ast.loc.clone(),
+                ));
+            }
+            let mut types = Locals::new();
+            for (param, arg) in f.params().iter().zip(args) {
+                types.insert(param.value.clone(), check_with(arg, env, locals)?);
+            }
+            check_with(f.body(), env, &types).map_err(|e| TypeError::new(e.value, ast.loc.clone()))
+        }
+        AstKind::Call { func, args } => {
+            let builtin = builtins::lookup(&func.value).unwrap();
+            if builtin.params.len() != args.len() {
+                return Err(TypeError::arg_count(
+                    builtin.params.len(),
+                    args.len(),
+                    ast.loc.clone(),
+                ));
+            }
+            let mut types = Vec::new();
+            for (param, arg) in builtin.params.iter().zip(args) {
+                let t = check_with(arg, env, locals)?;
+                check_param(*param, &t, &arg.loc)?;
+                types.push(t);
+            }
+            Ok((builtin.ret)(&types))
+        }
+        AstKind::Lambda { params, body } => {
+            check_params(params)?;
+            let mut captured: Vec<(String, Type)> = Vec::new();
+            for free in lambda::free_names(params, body) {
+                let t = match variable_type(free.name, env, locals) {
+                    Some(t) if !free.call || matches!(t, Type::Func(_)) => t,
+                    None if !free.call => {
+                        return Err(TypeError::unknown_variable(free.name, free.loc.clone()))
+                    }
+                    // a user-defined function is called by its name
+                    _ if env.get_func(free.name).is_some() => continue,
+                    _ => return Err(TypeError::unknown_function(free.name, free.loc.clone())),
+                };
+                if !captured.iter().any(|(name, _)| name == free.name) {
+                    captured.push((free.name.to_string(), t));
+                }
+            }
+            Ok(Type::Func(Rc::new(FuncType {
+                params: params.iter().map(|p| p.value.clone()).collect(),
+                body: Rc::new((**body).clone()),
+                captured,
+            })))
+        }
+        AstKind::UniOp { op, e } => {
+            let t = check_with(e, env, locals)?;
+            if op.value == super::UniOpKind::BitNot {
+                expect(Type::Int, expect_number(t.clone(), &e.loc)?, &e.loc)?;
+                return Ok(t);
+            }
+            elementwise(&t, &Type::Int, &|t, _| Some(t.clone()), &e.loc, &e.loc)
+        }
+        AstKind::BinOp { op, l, r } => {
+            use super::BinOpKind::*;
+            let lt = check_with(l, env, locals)?;
+            let rt = check_with(r, env, locals)?;
+            // `compose(f, g)(1)` applies the lambda, see `implicit_mult`
+            if let (Type::Func(f), true) = (&lt, implicit_mult(op)) {
+                return apply_type(f, vec![rt], env, &l.to_string(), &ast.loc);
+            }
+            match op.value {
+                Add | Sub | Mult => elementwise(&lt, &rt, &|l, r| l.promote(r), &l.loc, &r.loc),
+                Div => {
+                    let div = |l: &Type, r: &Type| Type::Float.promote(&l.promote(r)?);
+                    elementwise(&lt, &rt, &div, &l.loc, &r.loc)
+                }
+                MatMul => {
+                    let (lrank, le) = rank(&lt, &l.loc)?;
+                    let (rrank, re) = rank(&rt, &r.loc)?;
+                    let t = le.promote(&re).unwrap();
+                    Ok(match (lrank, rrank) {
+                        (1, 1) => t,
+                        (2, 2) => Type::List(Box::new(Type::List(Box::new(t)))),
+                        _ => Type::List(Box::new(t)),
+                    })
+                }
+                IntDiv | BitAnd | BitOr | BitXor | Shl | Shr => {
+                    let lt = expect_number(lt, &l.loc)?;
+                    let rt = expect_number(rt, &r.loc)?;
+                    expect(Type::Int, lt, &l.loc)?;
+                    expect(Type::Int, rt, &r.loc)?;
+                    Ok(Type::Int)
+                }
+            }
+        }
+    }
+}
+
+fn variable_type(name: &str, env: &Env, locals: &Locals) -> Option<Type> {
+    match locals.get(name) {
+        Some(t) => Some(t.clone()),
+        None => env.get(name).map(Value::type_of),
+    }
+}
+
+// The type of the lambda held by the variable, if it holds one.
+fn func_type(name: &str, env: &Env, locals: &Locals) -> Option<Rc<FuncType>> {
+    match variable_type(name, env, locals) {
+        Some(Type::Func(f)) => Some(f),
+        _ => None,
+    }
+}
+
+// The arguments of the parameters which the body calls must be lambdas,
+// e.g. not `2` in `g(2)` with `g = x -> x(1)`.
+fn check_called_params(
+    f: &FuncType,
+    args: &[Ast],
+    types: &[Type],
+    env: &Env,
+) -> Result<(), TypeError> {
+    if f.params.len() != args.len() {
+        return Ok(());
+    }
+    let free = lambda::free_names(&[], &f.body);
+    let called = |name: &str| free.iter().any(|u| u.call && u.name == name);
+    for ((param, arg), t) in f.params.iter().zip(args).zip(types) {
+        // a user-defined function is called, unless the argument is a lambda
+        let by_name = env.get_func(param).is_some();
+        if called(param) && !by_name && !matches!(t, Type::Func(_) | Type::Any) {
+            return Err(TypeError::not_function(t.clone(), arg.loc.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Applies the type of a lambda to the types of the arguments.
+pub(super) type ApplyType<'a> = dyn FnMut(&FuncType, Vec<Type>) -> Result<Type, TypeError> + 'a;
+
+// Lambdas may be applied to themselves, as in `w = f -> f(f); w(w)`, which
+// would be checked forever, so the lambdas being applied are counted.
+const MAX_APPLIED: usize = 32;
+
+thread_local! {
+    static APPLIED: Cell<usize> = const { Cell::new(0) };
+}
+
+// Check the body of the lambda `name` with the types of the arguments. As
+// for a user-defined function, an error is reported at the call.
+fn apply_type(
+    f: &FuncType,
+    args: Vec<Type>,
+    env: &Env,
+    name: &str,
+    loc: &Loc,
+) -> Result<Type, TypeError> {
+    if f.params.len() != args.len() {
+        return Err(TypeError::arg_count(
+            f.params.len(),
+            args.len(),
+            loc.clone(),
+        ));
+    }
+    let mut locals: Locals = f.captured.iter().cloned().collect();
+    locals.extend(f.params.iter().cloned().zip(args));
+    let depth = APPLIED.with(|n| n.replace(n.get() + 1));
+    let t = if depth >= MAX_APPLIED {
+        Err(TypeError::recursive_function(name, loc.clone()))
+    } else {
+        check_with(&f.body, env, &locals)
+    };
+    APPLIED.with(|n| n.set(depth));
+    t.map_err(|e| TypeError::new(e.value, loc.clone()))
+}
+
+// The type of an arithmetic operation on numbers, which is applied to the
+// elements of lists, e.g. `[1, 2] * 2.5` is a `list[float]`.
+fn elementwise(
+    lt: &Type,
+    rt: &Type,
+    op: &dyn Fn(&Type, &Type) -> Option<Type>,
+    lloc: &Loc,
+    rloc: &Loc,
+) -> Result<Type, TypeError> {
+    let t = match (lt, rt) {
+        (Type::List(lt), Type::List(rt)) => elementwise(lt, rt, op, lloc, rloc)?,
+        (Type::List(lt), rt) => elementwise(lt, rt, op, lloc, rloc)?,
+        (lt, Type::List(rt)) => elementwise(lt, rt, op, lloc, rloc)?,
+        (lt, rt) => {
+            let lt = expect_number(lt.clone(), lloc)?;
+            let rt = expect_number(rt.clone(), rloc)?;
+            return Ok(op(&lt, &rt).unwrap());
+        }
+    };
+    Ok(Type::List(Box::new(t)))
+}
+
+// Whether the operand of `@` is a vector or a matrix, and its element type.
+fn rank(t: &Type, loc: &Loc) -> Result<(usize, Type), TypeError> {
+    match t {
+        Type::List(t) => match &**t {
+            Type::List(t) => Ok((2, expect_number((**t).clone(), loc)?)),
+            t => Ok((1, expect_number(t.clone(), loc)?)),
+        },
+        t => Err(TypeError::not_list(t.clone(), loc.clone())),
+    }
+}
+
+fn check_param(param: Param, found: &Type, loc: &Loc) -> Result<(), TypeError> {
+    match (param, found) {
+        (Param::Int, Type::Int) | (Param::Int, Type::Any) => Ok(()),
+        (Param::Int, t) => Err(TypeError::mismatch(Type::Int, t.clone(), loc.clone())),
+        (Param::Real, t) => expect_real(t.clone(), loc),
+        (Param::Number, t) => expect_number(t.clone(), loc).map(|_| ()),
+        (Param::RealList, Type::Any) => Ok(()),
+        (Param::RealList, Type::List(t)) if t.is_real() => Ok(()),
+        (Param::RealList, Type::List(t)) if t.is_number() => {
+            Err(TypeError::mismatch(Type::Float, (**t).clone(), loc.clone()))
+        }
+        (Param::RealList, Type::List(t)) => Err(TypeError::not_number((**t).clone(), loc.clone())),
+        (Param::RealList, t) => Err(TypeError::not_list(t.clone(), loc.clone())),
+        (Param::RealMatrix, Type::List(t)) if **t != Type::Any => {
+            check_param(Param::RealList, t, loc)
+        }
+        (Param::RealMatrix, Type::List(_)) | (Param::RealMatrix, Type::Any) => Ok(()),
+        (Param::RealMatrix, t) => Err(TypeError::not_list(t.clone(), loc.clone())),
+    }
+}
+
+fn expect_number(found: Type, loc: &Loc) -> Result<Type, TypeError> {
+    if found.is_number() {
+        Ok(found)
+    } else {
+        Err(TypeError::not_number(found, loc.clone()))
+    }
+}
+
+pub(super) fn expect_real(found: Type, loc: &Loc) -> Result<(), TypeError> {
+    if found.is_real() {
+        Ok(())
+    } else {
+        Err(TypeError::mismatch(Type::Float, found, loc.clone()))
+    }
+}
+
+fn expect(expected: Type, found: Type, loc: &Loc) -> Result<(), TypeError> {
+    if expected == found || found == Type::Any {
+        Ok(())
+    } else {
+        Err(TypeError::mismatch(expected, found, loc.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, check_in, Type, TypeError};
+    use crate::calc::{lex, parse, Env, Loc, Value};
+
+    fn check_str(input: &str) -> Result<Type, TypeError> {
+        check(&parse(lex(input).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check_str("1 + 2 * 3"), Ok(Type::Int));
+        assert_eq!(check_str("1 + 2.5"), Ok(Type::Float));
+        assert_eq!(check_str("-(1 * 2.0)"), Ok(Type::Float));
+        assert_eq!(check_str("4 / 2"), Ok(Type::Float));
+        assert_eq!(check_str("7 // (1 + 2)"), Ok(Type::Int));
+    }
+
+    #[test]
+    fn test_check_mismatch() {
+        assert_eq!(
+            check_str("1 + 7 // (2 * 1.5)"),
+            Err(TypeError::mismatch(Type::Int, Type::Float, Loc(9, 18)))
+        );
+        assert_eq!(
+            check_str("1.0 // 2"),
+            Err(TypeError::mismatch(Type::Int, Type::Float, Loc(0, 3)))
+        );
+        assert_eq!(
+            check_str("1 + 2±1"),
+            Err(TypeError::interval_literal(Loc(4, 8)))
+        );
+    }
+
+    #[test]
+    fn test_check_list() {
+        let list = |t| Type::List(Box::new(t));
+        assert_eq!(check_str("[1, 2.5]"), Ok(list(Type::Float)));
+        assert_eq!(check_str("[]"), Ok(list(Type::Any)));
+        assert_eq!(check_str("[[1], []]"), Ok(list(list(Type::Int))));
+        assert_eq!(check_str("[1, 2][0]"), Ok(Type::Int));
+        assert_eq!(check_str("sum([1, 2])"), Ok(Type::Int));
+        assert_eq!(check_str("mean([1, 2])"), Ok(Type::Float));
+        assert_eq!(check_str("sort([2, 1.5])"), Ok(list(Type::Float)));
+    }
+
+    #[test]
+    fn test_check_complex() {
+        assert_eq!(check_str("1 + 2i"), Ok(Type::Complex));
+        assert_eq!(check_str("2.5 * 2i / 2"), Ok(Type::Complex));
+        assert_eq!(
+            check_str("[1, 2i]"),
+            Ok(Type::List(Box::new(Type::Complex)))
+        );
+        assert_eq!(check_str("abs(3 + 4i)"), Ok(Type::Float));
+        assert_eq!(check_str("abs(-3)"), Ok(Type::Int));
+        assert_eq!(check_str("conj(1i)"), Ok(Type::Complex));
+        assert_eq!(check_str("re(1i) + im(2)"), Ok(Type::Float));
+        assert_eq!(
+            check_str("1i // 2"),
+            Err(TypeError::mismatch(Type::Int, Type::Complex, Loc(0, 2)))
+        );
+        assert_eq!(
+            check_str("sum([1i])"),
+            Err(TypeError::mismatch(Type::Float, Type::Complex, Loc(4, 8)))
+        );
+    }
+
+    #[test]
+    fn test_check_list_error() {
+        let list = |t| Type::List(Box::new(t));
+        assert_eq!(
+            check_str("[1, [2]]"),
+            Err(TypeError::mismatch(Type::Int, list(Type::Int), Loc(4, 7)))
+        );
+        assert_eq!(
+            check_str("[1] // 2"),
+            Err(TypeError::not_number(list(Type::Int), Loc(0, 3)))
+        );
+        assert_eq!(
+            check_str("[[1]] @ 2"),
+            Err(TypeError::not_list(Type::Int, Loc(8, 9)))
+        );
+        assert_eq!(
+            check_str("[1][1.0]"),
+            Err(TypeError::mismatch(Type::Int, Type::Float, Loc(4, 7)))
+        );
+        assert_eq!(
+            check_str("sum(1)"),
+            Err(TypeError::not_list(Type::Int, Loc(4, 5)))
+        );
+        assert_eq!(
+            check_str("foo([1])"),
+            Err(TypeError::unknown_function("foo", Loc(0, 3)))
+        );
+        assert_eq!(
+            check_str("max([1], [2])"),
+            Err(TypeError::arg_count(1, 2, Loc(0, 13)))
+        );
+    }
+
+    #[test]
+    fn test_check_var() {
+        let mut env = Env::new();
+        env.set("x", Value::Int(1));
+        env.set("xs", Value::List(vec![Value::Float(1.5)]));
+        let check_env = |input| check_in(&parse(lex(input).unwrap()).unwrap(), &env);
+        assert_eq!(check_env("x * 2"), Ok(Type::Int));
+        assert_eq!(check_env("xs[x] + x"), Ok(Type::Float));
+        assert_eq!(
+            check_env("xs // 2"),
+            Err(TypeError::not_number(
+                Type::List(Box::new(Type::Float)),
+                Loc(0, 2)
+            ))
+        );
+        assert_eq!(
+            check_str("x + 1"),
+            Err(TypeError::unknown_variable("x", Loc(0, 1)))
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// A fixed integer width for programmer mode, e.g. `u8` or `i32`.
+//
+// Values are kept in `Value::Int(i64)` as the bit pattern of the word, so
+// a `u64` above `i64::MAX` is stored as a negative number. Use `value` to
+// get the number the pattern represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WordWidth {
+    bits: u32,
+    signed: bool,
+}
+
+impl WordWidth {
+    pub fn new(bits: u32, signed: bool) -> Option<Self> {
+        match bits {
+            8 | 16 | 32 | 64 => Some(WordWidth { bits, signed }),
+            _ => None,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        self.bits
+    }
+
+    pub fn is_signed(self) -> bool {
+        self.signed
+    }
+
+    // Truncate `n` to the width (two's complement wrap-around) and return
+    // the stored bit pattern.
+    pub(super) fn wrap(self, n: i128) -> i64 {
+        let mask = (1u128 << self.bits) - 1;
+        let low = (n as u128) & mask;
+        if self.signed && low >> (self.bits - 1) == 1 {
+            (low as i128 - (1i128 << self.bits)) as i64
+        } else {
+            low as u64 as i64
+        }
+    }
+
+    // The number represented by a stored bit pattern.
+    pub fn value(self, n: i64) -> i128 {
+        if self.signed {
+            n as i128
+        } else {
+            n as u64 as i128
+        }
+    }
+
+    fn pattern(self, n: i64) -> u64 {
+        let mask = if self.bits == 64 {
+            u64::MAX
+        } else {
+            (1u64 << self.bits) - 1
+        };
+        n as u64 & mask
+    }
+
+    // Render the bit pattern in hex, octal and binary, e.g.
+    // `0xff 0o377 0b11111111`.
+    pub fn radix_string(self, n: i64) -> String {
+        let p = self.pattern(n);
+        format!("{:#x} {:#o} {:#b}", p, p, p)
+    }
+}
+
+impl std::str::FromStr for WordWidth {
+    type Err = String;
+
+    // `u8`, `i16`, `u32`, `i64`, ...
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid word width: {}", s);
+        let signed = match s.chars().next() {
+            Some('i') => true,
+            Some('u') => false,
+            _ => return Err(err()),
+        };
+        let bits = s[1..].parse().map_err(|_| err())?;
+        WordWidth::new(bits, signed).ok_or_else(err)
+    }
+}
+
+impl std::fmt::Display for WordWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sign = if self.signed { 'i' } else { 'u' };
+        write!(f, "{}{}", sign, self.bits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WordWidth;
+
+    #[test]
+    fn test_wrap() {
+        let u8 = WordWidth::new(8, false).unwrap();
+        let i8 = WordWidth::new(8, true).unwrap();
+        let u64 = WordWidth::new(64, false).unwrap();
+        assert_eq!(u8.wrap(256), 0);
+        assert_eq!(u8.wrap(-1), 255);
+        assert_eq!(i8.wrap(128), -128);
+        assert_eq!(i8.wrap(-129), 127);
+        assert_eq!(u64.wrap(-1), -1);
+        assert_eq!(u64.value(-1), u64::MAX as i128);
+    }
+
+    #[test]
+    fn test_radix_string() {
+        let i8: WordWidth = "i8".parse().unwrap();
+        assert_eq!(i8.radix_string(-1), "0xff 0o377 0b11111111");
+        assert_eq!(i8.radix_string(5), "0x5 0o5 0b101");
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("u16".parse(), Ok(WordWidth::new(16, false).unwrap()));
+        assert_eq!("i64".parse(), Ok(WordWidth::new(64, true).unwrap()));
+        assert!("u12".parse::<WordWidth>().is_err());
+        assert!("x8".parse::<WordWidth>().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// How numbers are written in results, shared by calc and rpn.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notation {
+    // the shortest form which reads back as the same number, e.g. `0.1`
+    Auto,
+    // a number of digits after the decimal point, e.g. `3.1416`
+    Fixed(usize),
+    // a number of significant digits, e.g. `3.142` or `0.001235`
+    Significant(usize),
+    // a number of digits after the point of the mantissa, e.g. `1.235e3`
+    Scientific(usize),
+    // like scientific with an exponent which is a multiple of 3, e.g. `12.35e3`
+    Engineering(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Formatter {
+    pub notation: Notation,
+    // groups the digits before the decimal mark, e.g. `,` for `1,234,567`
+    pub thousands: Option<char>,
+    // e.g. `,` for `3,14`
+    pub decimal: char,
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Formatter {
+            notation: Notation::Auto,
+            thousands: None,
+            decimal: '.',
+        }
+    }
+
+    pub fn fixed(precision: usize) -> Self {
+        Formatter {
+            notation: Notation::Fixed(precision),
+            ..Self::new()
+        }
+    }
+
+    // Change one setting, as in the REPL command `:format sci 3` or the
+    // flag `--sci 3`. The notations are `auto`, `fixed N`, `sig N`, `sci N`
+    // and `eng N`, the marks are set with `thousands C|off` and `decimal C`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let digits = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("expected a number of digits for {}: {}", key, value))
+        };
+        let mark = || {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_ascii_digit() && c != '-' => Ok(c),
+                _ => Err(format!(
+                    "expected a single character for {}: {}",
+                    key, value
+                )),
+            }
+        };
+        let mut next = *self;
+        match key {
+            "auto" => next.notation = Notation::Auto,
+            "fixed" => next.notation = Notation::Fixed(digits()?),
+            "sig" => match digits()? {
+                0 => return Err("sig needs at least 1 digit".to_string()),
+                n => next.notation = Notation::Significant(n),
+            },
+            "sci" => next.notation = Notation::Scientific(digits()?),
+            "eng" => next.notation = Notation::Engineering(digits()?),
+            "thousands" if value == "off" => next.thousands = None,
+            "thousands" => next.thousands = Some(mark()?),
+            "decimal" => next.decimal = mark()?,
+            _ => return Err(format!("unknown format setting: {}", key)),
+        }
+        if next.thousands == Some(next.decimal) {
+            return Err("the thousands separator and the decimal mark must differ".to_string());
+        }
+        *self = next;
+        Ok(())
+    }
+
+    // Scientific and engineering notation also apply to integers, the other
+    // notations keep them exact. Words of 64 bits may be unsigned, hence
+    // the `i128`.
+    pub fn int(&self, n: impl Into<i128>) -> String {
+        let n = n.into();
+        match self.notation {
+            Notation::Scientific(_) | Notation::Engineering(_) => self.float(n as f64),
+            _ => self.marks(&n.to_string()),
+        }
+    }
+
+    // In `Auto` notation a whole number keeps its decimal point, so that
+    // `3.0` is not confused with `3`.
+    pub fn float(&self, x: f64) -> String {
+        match self.notation {
+            Notation::Auto => self.marks(&format!("{:?}", x)),
+            _ => self.real(x),
+        }
+    }
+
+    // Like `float`, but without the decimal point of a whole number in
+    // `Auto` notation, as in the parts of `2+3i`.
+    pub fn real(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+        let s = match self.notation {
+            Notation::Auto => x.to_string(),
+            Notation::Fixed(p) => format!("{:.*}", p, x),
+            Notation::Significant(d) => {
+                let sci = format!("{:.*e}", d - 1, x);
+                let exp = exponent(&sci);
+                if (-5..15).contains(&exp) {
+                    // round to the digits first, then print them in full
+                    let rounded: f64 = sci.parse().unwrap();
+                    format!("{:.*}", (d as i32 - 1 - exp).max(0) as usize, rounded)
+                } else {
+                    sci
+                }
+            }
+            Notation::Scientific(p) => format!("{:.*e}", p, x),
+            Notation::Engineering(p) => {
+                let mut e3 = exponent(&format!("{:.*e}", p, x)).div_euclid(3) * 3;
+                let mut m = format!("{:.*}", p, x / 10f64.powi(e3));
+                // rounding may carry into the next group, as in `999.96`
+                if m.trim_start_matches('-').split('.').next().unwrap().len() > 3 {
+                    e3 += 3;
+                    m = format!("{:.*}", p, x / 10f64.powi(e3));
+                }
+                format!("{}e{}", m, e3)
+            }
+        };
+        self.marks(&s)
+    }
+
+    // Group the digits of the integer part and replace the decimal point.
+    fn marks(&self, s: &str) -> String {
+        let (sign, rest) = match s.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", s),
+        };
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (int, tail) = rest.split_at(end);
+        let mut out = sign.to_string();
+        for (i, c) in int.chars().enumerate() {
+            if i > 0 && (int.len() - i).is_multiple_of(3) {
+                if let Some(sep) = self.thousands {
+                    out.push(sep);
+                }
+            }
+            out.push(c);
+        }
+        out.extend(
+            tail.chars()
+                .map(|c| if c == '.' { self.decimal } else { c }),
+        );
+        out
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The exponent of `1.5e-3`.
+fn exponent(s: &str) -> i32 {
+    s[s.find('e').unwrap() + 1..].parse().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Formatter, Notation};
+
+    fn with(notation: Notation) -> Formatter {
+        Formatter {
+            notation,
+            ..Formatter::new()
+        }
+    }
+
+    #[test]
+    fn test_notations() {
+        let auto = Formatter::new();
+        assert_eq!(auto.float(3.0), "3.0");
+        assert_eq!(auto.real(3.0), "3");
+        assert_eq!(auto.float(0.1), "0.1");
+        assert_eq!(auto.int(-42), "-42");
+
+        let fixed = Formatter::fixed(4);
+        assert_eq!(fixed.float(26.284), "26.2840");
+        assert_eq!(fixed.int(3), "3");
+
+        let sig = with(Notation::Significant(4));
+        assert_eq!(sig.float(1.23456), "1.235");
+        assert_eq!(sig.float(0.00123456), "0.001235");
+        assert_eq!(sig.float(123456.0), "123500");
+        assert_eq!(sig.float(9.9996), "10.00");
+        assert_eq!(sig.float(1.5e20), "1.500e20");
+
+        let sci = with(Notation::Scientific(3));
+        assert_eq!(sci.float(1234.5), "1.234e3");
+        assert_eq!(sci.float(-0.00015), "-1.500e-4");
+        assert_eq!(sci.int(1000), "1.000e3");
+
+        let eng = with(Notation::Engineering(2));
+        assert_eq!(eng.float(12345.0), "12.35e3");
+        assert_eq!(eng.float(0.00015), "150.00e-6");
+        assert_eq!(eng.float(999.999), "1.00e3");
+        assert_eq!(eng.float(1.0), "1.00e0");
+
+        assert_eq!(fixed.float(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut f = Formatter::fixed(2);
+        f.set("thousands", ",").unwrap();
+        assert_eq!(f.float(1234567.891), "1,234,567.89");
+        assert_eq!(f.float(-123.0), "-123.00");
+        assert_eq!(f.int(-1234), "-1,234");
+
+        // the German style
+        f.set("thousands", "off").unwrap();
+        f.set("decimal", ",").unwrap();
+        f.set("thousands", ".").unwrap();
+        assert_eq!(f.float(1234.5), "1.234,50");
+
+        assert!(f.set("decimal", ".").is_err());
+        assert!(f.set("thousands", "ab").is_err());
+        assert!(f.set("sig", "0").is_err());
+        assert!(f.set("fixed", "x").is_err());
+        assert!(f.set("color", "red").is_err());
+    }
+}
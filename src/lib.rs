@@ -1,5 +1,16 @@
+// written before the lints of today's clippy, and kept as they were
+#[allow(clippy::needless_arbitrary_self_type)]
 pub mod bitonic;
+#[allow(
+    clippy::needless_arbitrary_self_type,
+    clippy::new_without_default,
+    clippy::len_without_is_empty,
+    clippy::mem_replace_with_default,
+    clippy::borrowed_box,
+    mismatched_lifetime_syntaxes
+)]
 pub mod collection;
+pub mod format;
 pub mod rpn;
 pub mod util;
 
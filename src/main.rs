@@ -1,6 +1,8 @@
 use rust_in_action::bitonic;
 use rust_in_action::bitonic::SortOrder::{Ascending, Descending};
+use rust_in_action::format::Formatter;
 use rust_in_action::util;
+use rustyline::history::{History, SearchDirection};
 use std::time::Instant;
 
 fn main() {
@@ -10,16 +12,44 @@ fn main() {
             "bitonic" => bitonic(),
             "bitonic_benchmark" => bitonic_benchmark(23),
             "lex" => lex_loop(),
+            "calc" => calc_loop(),
+            "calc_benchmark" => calc_benchmark(100_000),
+            "calc_lsp" => calc_lsp(),
+            "calc_batch" => calc_batch(),
+            "calc_fuzz" => calc_fuzz(),
+            "calc_sheet" => calc_sheet(),
             e => println!("Unknown arg: {}", e),
         },
         None => eprintln!("arg is required"),
     }
 }
 
+// `rpn [--fixed 4] [--thousands ,] ...`, see `Formatter::set`.
 fn rpn() {
     use rust_in_action::rpn;
 
-    rpn::rpn();
+    match format_flags(Formatter::fixed(4)) {
+        Ok(fmt) => rpn::rpn(&fmt),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// Apply the flags after the subcommand, e.g. `--sci 3` or `--auto`.
+fn format_flags(mut fmt: Formatter) -> Result<Formatter, String> {
+    let mut args = std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        let key = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument: {}", arg))?;
+        let value = match key {
+            "auto" => String::new(),
+            _ => args
+                .next()
+                .ok_or_else(|| format!("--{} needs a value", key))?,
+        };
+        fmt.set(key, &value)?;
+    }
+    Ok(fmt)
 }
 
 fn bitonic() {
@@ -62,6 +92,7 @@ fn bitonic_benchmark(bits: u32) {
 
     println!("speed up: {:.2}x", seq_ns / parallel_ns);
 }
+#[allow(clippy::unnecessary_mut_passed)]
 fn timed_sort(len: usize, sorter: bitonic::Sorter) -> f64 {
     let mut xs = util::new_u32_vec(len);
 
@@ -74,27 +105,353 @@ fn timed_sort(len: usize, sorter: bitonic::Sorter) -> f64 {
     dur.subsec_nanos() as f64 + dur.as_secs() as f64 * 1e9_f64
 }
 
-fn lex_loop() {
+fn calc_lsp() {
     use rust_in_action::calc;
-    use std::io::BufRead;
+
     let stdin = std::io::stdin();
-    let stdin = stdin.lock();
-    let stdin = std::io::BufReader::new(stdin);
-    let mut lines = stdin.lines();
+    let stdout = std::io::stdout();
+    calc::serve_lsp(stdin.lock(), stdout.lock()).expect("stdio");
+}
+
+// `calc_batch [csv|json] [FILE]`, reading stdin without a file.
+fn calc_batch() {
+    use rust_in_action::calc::{self, BatchFormat};
+
+    let format = match std::env::args().nth(2) {
+        Some(s) => match s.parse() {
+            Ok(format) => format,
+            Err(e) => return eprintln!("{}", e),
+        },
+        None => BatchFormat::Csv,
+    };
+    let stdout = std::io::stdout();
+    let summary = match std::env::args().nth(3) {
+        Some(path) => match std::fs::File::open(&path) {
+            Ok(file) => calc::eval_batch(std::io::BufReader::new(file), stdout.lock(), format),
+            Err(e) => return eprintln!("{}: {}", path, e),
+        },
+        None => {
+            let stdin = std::io::stdin();
+            calc::eval_batch(stdin.lock(), stdout.lock(), format)
+        }
+    };
+    match summary {
+        Ok(s) => eprintln!("{} expressions, {} failed", s.total, s.failed),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// `calc_sheet [FILE]`: read a sheet of expressions as CSV, reading stdin
+// without a file, and print its values as CSV.
+fn calc_sheet() {
+    use rust_in_action::calc::Sheet;
+    use std::io::Read;
+
+    let mut input = String::new();
+    let read = match std::env::args().nth(2) {
+        Some(path) => std::fs::File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut input))
+            .map_err(|e| format!("{}: {}", path, e)),
+        None => std::io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|e| e.to_string()),
+    };
+    if let Err(e) = read {
+        return eprintln!("{}", e);
+    }
+    match Sheet::from_csv(&input) {
+        Ok(sheet) => print!("{}", sheet.values_to_csv(&Formatter::new())),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// `calc_fuzz [SEED] [N]`: compare calc with rpn on generated expressions.
+fn calc_fuzz() {
+    use rust_in_action::calc;
+
+    let arg = |i, default| {
+        std::env::args()
+            .nth(i)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(default)
+    };
+    let (seed, n) = (arg(2, 0), arg(3, 10_000));
+    match calc::fuzz(seed, n as usize) {
+        Ok(()) => println!("{} expressions agree (seed {})", n, seed),
+        Err(d) => println!("divergence (seed {}):\n{}", seed, d),
+    }
+}
+
+fn calc_benchmark(n: i64) {
+    use rust_in_action::calc::{self, Env, Interpreter, Value};
+
+    let input = "x * x + 3 * x - y / 2 + max([x, y, 1])";
+    println!("evaluating `{}` with {} bindings", input, n);
+    let bind = |env: &mut Env, i: i64| {
+        env.set("x", Value::Int(i));
+        env.set("y", Value::Float(i as f64 * 0.5));
+    };
+
+    let mut interp = Interpreter::new();
+    bind(interp.env_mut(), 0);
+    let reparse_ns = timed(|| {
+        for i in 0..n {
+            bind(interp.env_mut(), i);
+            calc::eval_with(&mut interp, input).expect("evaluated");
+        }
+    });
+    println!("re-parse: {} seconds", reparse_ns / 1e9);
+
+    let ast = calc::parse(calc::lex(input).unwrap()).unwrap();
+    calc::check_in(&ast, interp.env()).expect("checked");
+    let walk_ns = timed(|| {
+        for i in 0..n {
+            bind(interp.env_mut(), i);
+            interp.eval(&ast).expect("evaluated");
+        }
+    });
+    println!("tree-walk: {} seconds", walk_ns / 1e9);
+
+    let f = calc::compile(&interp, &ast).expect("compiled");
+    let mut env = Env::new();
+    let compiled_ns = timed(|| {
+        for i in 0..n {
+            bind(&mut env, i);
+            f(&env).expect("evaluated");
+        }
+    });
+    println!("compiled: {} seconds", compiled_ns / 1e9);
+
+    println!(
+        "speed up: {:.2}x over re-parse, {:.2}x over tree-walk",
+        reparse_ns / compiled_ns,
+        walk_ns / compiled_ns
+    );
+}
+fn timed(mut f: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    f();
+    let dur = start.elapsed();
+    dur.subsec_nanos() as f64 + dur.as_secs() as f64 * 1e9_f64
+}
+
+fn lex_loop() {
+    use rust_in_action::calc;
+    let mut editor = Editor::new();
+    while let Some(line) = editor.readline("> ") {
+        let token = calc::lex(&line);
+        println!("{:?}", token);
+    }
+}
+fn calc_loop() {
+    use rust_in_action::calc;
+    use std::io::IsTerminal;
+    let mut editor = Editor::new();
+    // colours are only used on a terminal, so that piped output stays plain
+    let color_err = std::io::stderr().is_terminal();
+    let mut fmt = match format_flags(Formatter::new()) {
+        Ok(fmt) => fmt,
+        Err(e) => return eprintln!("{}", e),
+    };
+    let mut interp = calc::Interpreter::new();
+    let mut interval_mode = false;
+    let mut polar = false;
+    let mut radix = false;
+    // `None`, or `Some(true)` for JSON
+    let mut trace: Option<bool> = None;
     loop {
-        prompt("> ").unwrap();
-        if let Some(Ok(line)) = lines.next() {
-            let token = calc::lex(&line);
-            println!("{:?}", token);
+        editor.set_env(interp.env());
+        let p = if interval_mode { "interval> " } else { "> " };
+        if let Some(line) = editor.readline(p) {
+            match line.trim() {
+                ":mode interval" => interval_mode = true,
+                ":mode normal" => interval_mode = false,
+                ":display polar" => polar = true,
+                ":display rect" => {
+                    polar = false;
+                    radix = false;
+                }
+                ":display radix" => radix = true,
+                cmd if cmd.starts_with(":format ") => {
+                    let mut words = cmd[8..].split_whitespace();
+                    let key = words.next().unwrap_or("");
+                    if let Err(e) = fmt.set(key, words.next().unwrap_or("")) {
+                        eprintln!("{}", e);
+                    }
+                }
+                ":trace text" => trace = Some(false),
+                ":trace json" => trace = Some(true),
+                ":trace off" => trace = None,
+                ":limits sandbox" => interp.set_limits(calc::Limits::sandbox()),
+                ":limits off" => interp.set_limits(calc::Limits::default()),
+                ":width off" => interp.set_word_width(None),
+                cmd if cmd.starts_with(":width ") => match cmd[7..].trim().parse() {
+                    Ok(w) => interp.set_word_width(Some(w)),
+                    Err(e) => eprintln!("{}", e),
+                },
+                cmd if cmd.starts_with(":save ") => {
+                    let source = calc::save_session(&interp, &editor.history());
+                    if let Err(e) = std::fs::write(cmd[6..].trim(), source) {
+                        eprintln!("{}", e);
+                    }
+                }
+                cmd if cmd.starts_with(":load ") => {
+                    let path = cmd[6..].trim();
+                    match std::fs::read_to_string(path) {
+                        Ok(source) => match calc::load_session(&mut interp, &source) {
+                            Ok(history) => editor.add_history(history),
+                            Err((n, e)) => eprintln!("{}:{}: {}", path, n, e),
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                cmd if cmd.starts_with(":plot ") => {
+                    let input = &cmd[6..];
+                    let chart = calc::Chart {
+                        braille: std::io::stdout().is_terminal(),
+                        ..calc::Chart::new()
+                    };
+                    match calc::plot(&mut interp, input, &chart, &fmt) {
+                        Ok(chart) => println!("{}", chart),
+                        Err(e) => report(input, &e, color_err),
+                    }
+                }
+                cmd if cmd.starts_with(':') => eprintln!("Unknown command: {}", cmd),
+                _ => {
+                    for w in calc::lint(&interp, &line) {
+                        eprintln!("warning: {}", w);
+                        eprintln!("{}", calc::annotate(&line, w.loc()));
+                    }
+                    let result = if let (Some(json), false) = (trace, interval_mode) {
+                        calc::trace(&mut interp, &line).map(|t| {
+                            if json {
+                                Some(t.to_json())
+                            } else {
+                                Some(t.to_string())
+                            }
+                        })
+                    } else if interval_mode {
+                        calc::eval_interval_with(&interp, &line).map(|v| Some(v.to_string()))
+                    } else {
+                        let word = interp.word_width();
+                        calc::exec(&mut interp, &line).map(|v| {
+                            v.map(|v| match v {
+                                calc::Value::Int(n) if radix => {
+                                    // show the pattern in i64 when no width is selected
+                                    let w = word
+                                        .unwrap_or_else(|| calc::WordWidth::new(64, true).unwrap());
+                                    format!("{} {}", w.value(n), w.radix_string(n))
+                                }
+                                calc::Value::Int(n) => match word {
+                                    Some(w) => fmt.int(w.value(n)),
+                                    None => fmt.int(n),
+                                },
+                                v if polar => v.to_polar_string(),
+                                v => v.format(&fmt),
+                            })
+                        })
+                    };
+                    match result {
+                        Ok(Some(v)) => println!("{}", v),
+                        Ok(None) => {}
+                        Err(e) => report(&line, &e, color_err),
+                    }
+                }
+            }
         } else {
             break;
         }
     }
 }
-fn prompt(s: &str) -> std::io::Result<()> {
-    use std::io::Write;
-    let stdout = std::io::stdout();
-    let mut stdout = stdout.lock();
-    stdout.write(s.as_bytes())?;
-    stdout.flush()
+
+// Print an error with the part of the input it is located at.
+fn report(input: &str, e: &rust_in_action::calc::CalcError, color: bool) {
+    use rust_in_action::calc;
+    eprintln!("{}", e);
+    match e.loc() {
+        Some(loc) if color => eprintln!("{}", calc::annotate_highlighted(input, loc)),
+        Some(loc) => eprintln!("{}", calc::annotate(input, loc)),
+        None => {}
+    }
+}
+
+// A line editor with the history kept in `~/.calc_history`, reverse search
+// (Ctrl-R) and completion of the names in the calc environment.
+struct Editor {
+    editor: rustyline::Editor<rust_in_action::calc::CalcHelper, rustyline::history::DefaultHistory>,
+    history: Option<std::path::PathBuf>,
+    // the number of entries read from the history file
+    loaded: usize,
+}
+
+impl Editor {
+    fn new() -> Self {
+        let mut editor = rustyline::Editor::new().expect("terminal");
+        editor.set_helper(Some(Default::default()));
+        let history =
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".calc_history"));
+        if let Some(path) = &history {
+            // there is no history yet on the first run
+            let _ = editor.load_history(path);
+        }
+        let loaded = editor.history().len();
+        Editor {
+            editor,
+            history,
+            loaded,
+        }
+    }
+
+    fn set_env(&mut self, env: &rust_in_action::calc::Env) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_env(env);
+        }
+    }
+
+    // The entries of this session, without those loaded from the file.
+    fn history(&self) -> Vec<String> {
+        let history = self.editor.history();
+        (self.loaded..history.len())
+            .filter_map(|i| history.get(i, SearchDirection::Forward).ok()?)
+            .map(|entry| entry.entry.into_owned())
+            .filter(|line| !line.trim_start().starts_with(":save "))
+            .collect()
+    }
+
+    fn add_history(&mut self, lines: Vec<String>) {
+        for line in lines {
+            let _ = self.editor.add_history_entry(line);
+        }
+    }
+
+    // The next line, or `None` at the end of input. Ctrl-C discards the line.
+    fn readline(&mut self, prompt: &str) -> Option<String> {
+        use rustyline::error::ReadlineError;
+        loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = self.editor.add_history_entry(line.as_str());
+                    }
+                    return Some(line);
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return None,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        if let Some(path) = &self.history {
+            if let Err(e) = self.editor.save_history(path) {
+                eprintln!("failed to save history: {}", e);
+            }
+        }
+    }
 }
@@ -1,10 +1,12 @@
-pub fn rpn() {
+use crate::format::Formatter;
+
+pub fn rpn(fmt: &Formatter) {
     let expr = "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -";
     let ans = solve(expr);
 
-    debug_assert_eq!("26.2840", format!("{:.4}", ans));
+    debug_assert_eq!("26.2840", Formatter::fixed(4).float(ans));
 
-    println!("ans = {:.4}", ans);
+    println!("ans = {}", fmt.float(ans));
 }
 
 pub fn solve(expr: &str) -> f64 {
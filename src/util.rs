@@ -4,10 +4,17 @@ use rand::{Rng, SeedableRng};
 use rand_pcg::Pcg64Mcg;
 
 pub fn new_u32_vec(n: usize) -> Vec<u32> {
-    let mut rng = Pcg64Mcg::from_seed([0; 16]);
+    let mut rng = new_rng(0);
     rng.sample_iter(&Standard).take(n).collect()
 }
 
+// A reproducible generator, the same sequence for the same seed.
+pub fn new_rng(seed: u64) -> Pcg64Mcg {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    Pcg64Mcg::from_seed(bytes)
+}
+
 pub fn is_sorted<T: Ord>(xs: &[T], ord: SortOrder) -> bool {
     xs.windows(2).all(|pair| {
         if ord == SortOrder::Ascending {